@@ -1,16 +1,237 @@
+pub mod buffer;
 pub mod command_buffer;
 pub mod command_pool;
+pub mod descriptor;
 pub mod device;
 pub mod error;
 mod extensions;
 pub mod fence;
+pub mod frame_renderer;
 mod framebuffer;
+pub mod image;
 pub mod instance;
+pub mod memory;
 mod physical_device;
 pub mod pipeline;
+pub mod post_process;
+mod sampled_pass;
+pub mod sampler;
 pub mod selectors;
 pub mod semaphore;
 pub mod shader;
 pub mod surface;
 pub mod swapchain;
+pub mod textured_quad;
 mod validation;
+
+use std::{error::Error, sync::Arc};
+
+use ash::{Entry, vk::make_api_version};
+use device::{Device, DeviceBuilder};
+use extensions::ExtensionManager;
+use instance::{Instance, InstanceBuilder};
+use sdl3::video::Window;
+use selectors::{DrawQueueFamilySelector, DrawQueues};
+use surface::Surface;
+use swapchain::{PresentMode, PresentNotSupportedError, SwapchainManager};
+use validation::ValidationLayerManager;
+
+use crate::window::WindowManager;
+
+///
+/// Names of the instance extensions that can be passed to InstanceBuilder::extensions
+///
+pub fn available_instance_extensions(entry: &Entry) -> Vec<String> {
+    ExtensionManager::init(entry).available_names()
+}
+
+///
+/// Names of the installed validation layers, always empty without debug_assertions
+///
+pub fn available_validation_layers(entry: &Entry) -> Vec<String> {
+    ValidationLayerManager::init(entry).available_names()
+}
+
+pub struct VulkanBuilder<'a> {
+    window: &'a WindowManager,
+    present_mode: PresentMode,
+    entry: Option<Arc<Entry>>,
+}
+
+impl<'a> VulkanBuilder<'a> {
+    pub fn new(window: &'a WindowManager) -> Self {
+        Self {
+            window,
+            present_mode: PresentMode::default(),
+            entry: None,
+        }
+    }
+
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+
+    ///
+    /// Uses entry instead of Entry::linked, e.g. Entry::load to load the Vulkan loader
+    /// at runtime so the binary starts on systems without it
+    ///
+    pub fn with_entry(mut self, entry: Arc<Entry>) -> Self {
+        self.entry = Some(entry);
+        self
+    }
+
+    fn init_entry(&self) -> Arc<Entry> {
+        match &self.entry {
+            Some(entry) => Arc::clone(entry),
+            None => Arc::new(Entry::linked()),
+        }
+    }
+
+    fn init_instance(&self, entry: Arc<Entry>) -> Result<Arc<Instance>, Box<dyn Error>> {
+        let instance = InstanceBuilder::new(entry)
+            .extensions(self.window.get_vk_extensions()?)
+            .validation_layers(vec![String::from("VK_LAYER_KHRONOS_validation")])
+            .api_version(make_api_version(0, 1, 3, 0))
+            .build()?;
+        Ok(Arc::new(instance))
+    }
+
+    fn init_surface(&self, instance: Arc<Instance>) -> Result<Arc<Surface>, Box<dyn Error>> {
+        Ok(Arc::new(Surface::init(instance, self.window)?))
+    }
+
+    fn init_device(
+        &self,
+        instance: Arc<Instance>,
+        surface: Arc<Surface>,
+    ) -> Result<(Arc<Device>, DrawQueueFamilySelector), Box<dyn Error>> {
+        let selector = DrawQueueFamilySelector::new(Arc::clone(&instance), Arc::clone(&surface));
+        let (device, selector) = DeviceBuilder::new(instance, surface, selector).build()?;
+        Ok((Arc::new(device), selector))
+    }
+
+    pub fn build(self) -> Result<Vulkan, Box<dyn Error>> {
+        let entry = self.init_entry();
+        let instance = self.init_instance(Arc::clone(&entry))?;
+        let surface = self.init_surface(Arc::clone(&instance))?;
+        let (device, queue_family_selector) =
+            self.init_device(Arc::clone(&instance), Arc::clone(&surface))?;
+        let queues = device::fill_selector(Arc::clone(&device), queue_family_selector.clone());
+        let swapchain_manager = Arc::new(
+            SwapchainManager::new(Arc::clone(&device), Arc::clone(&surface))
+                .present_mode(self.present_mode),
+        );
+        let (width, height) = self.window.get_size_in_pixels();
+        swapchain_manager.set_framebuffer_size(width, height);
+
+        Ok(Vulkan {
+            entry,
+            instance,
+            surface,
+            device,
+            queue_family_selector,
+            queues,
+            swapchain_manager,
+        })
+    }
+}
+
+pub struct Vulkan {
+    entry: Arc<Entry>,
+    instance: Arc<Instance>,
+    surface: Arc<Surface>,
+    device: Arc<Device>,
+    queue_family_selector: DrawQueueFamilySelector,
+    queues: DrawQueues,
+    swapchain_manager: Arc<SwapchainManager>,
+}
+
+impl Vulkan {
+    pub fn get_instance(&self) -> Arc<Instance> {
+        Arc::clone(&self.instance)
+    }
+
+    pub fn get_device(&self) -> Arc<Device> {
+        Arc::clone(&self.device)
+    }
+
+    pub fn get_queues(&self) -> &DrawQueues {
+        &self.queues
+    }
+
+    pub fn get_queue_family_selector(&self) -> DrawQueueFamilySelector {
+        self.queue_family_selector.clone()
+    }
+
+    pub fn get_swapchain_manager(&self) -> Arc<SwapchainManager> {
+        Arc::clone(&self.swapchain_manager)
+    }
+
+    ///
+    /// Surface of an additional window, see WindowManager::create_window.
+    /// Has to be dropped before shutdown
+    ///
+    pub fn create_surface_for(&self, window: &Window) -> Result<Arc<Surface>, sdl3::Error> {
+        let surface = Surface::from_window(Arc::clone(&self.instance), window)?;
+        Ok(Arc::new(surface))
+    }
+
+    ///
+    /// SwapchainManager of an additional window sharing the device and queues,
+    /// its framebuffer size has to be set before creating a swapchain.
+    /// Returns PresentNotSupportedError if the present queue can't present to surface
+    ///
+    pub fn create_swapchain_for(
+        &self,
+        surface: Arc<Surface>,
+    ) -> Result<SwapchainManager, Box<dyn Error>> {
+        if !self.device.supports_present_to(&surface)? {
+            return Err(PresentNotSupportedError.into());
+        }
+        Ok(SwapchainManager::new(Arc::clone(&self.device), surface))
+    }
+
+    ///
+    /// Waits for the device to become idle and destroys everything in dependency order.
+    /// Warns about every object that is still referenced from outside and therefore
+    /// outlives this call, debug builds panic afterwards
+    ///
+    pub fn shutdown(self) {
+        self.device.wait_idle();
+
+        let Self {
+            entry,
+            instance,
+            surface,
+            device,
+            queue_family_selector,
+            queues,
+            swapchain_manager,
+        } = self;
+
+        let mut unique = check_unique(swapchain_manager, "SwapchainManager");
+        drop(queues);
+        unique &= check_unique(device, "Device");
+        drop(queue_family_selector);
+        unique &= check_unique(surface, "Surface");
+        unique &= check_unique(instance, "Instance");
+        unique &= check_unique(entry, "Entry");
+        debug_assert!(unique, "Vulkan objects outlive Vulkan::shutdown");
+    }
+}
+
+///
+/// Whether object has no other references, warns if it has
+///
+fn check_unique<T>(object: Arc<T>, name: &str) -> bool {
+    let count = Arc::strong_count(&object);
+    if count > 1 {
+        log::warn!(
+            "{} is still referenced {} more time(s) during shutdown and will outlive Vulkan",
+            name,
+            count - 1
+        );
+    }
+    count == 1
+}
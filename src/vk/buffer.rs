@@ -0,0 +1,329 @@
+use std::{
+    ptr,
+    sync::{Arc, Weak},
+};
+
+use ash::vk;
+
+use super::{
+    device::Device,
+    error::fatal_vk_error,
+    memory::{Allocation, AllocationError, Allocator, MappedMemory},
+};
+
+pub struct Buffer {
+    weak_self: Weak<Self>,
+    device: Arc<Device>,
+    buffer: vk::Buffer,
+    allocation: Allocation,
+    size: u64,
+    usage: vk::BufferUsageFlags,
+}
+
+impl Buffer {
+    ///
+    /// SHADER_DEVICE_ADDRESS usage needs DeviceBuilder::buffer_device_address, otherwise
+    /// BufferDeviceAddressNotEnabled is returned. The memory is then allocated with the
+    /// DEVICE_ADDRESS flag
+    ///
+    pub fn new(
+        device: Arc<Device>,
+        allocator: &Allocator,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Arc<Self>, AllocationError> {
+        check_device_address(&device, usage)?;
+        let create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+        let buffer = unsafe { device.raw_handle().create_buffer(&create_info, None) }
+            .unwrap_or_else(|error| fatal_vk_error("failed to create_buffer", error));
+
+        let requirements = unsafe { device.raw_handle().get_buffer_memory_requirements(buffer) };
+        let allocate_flags = if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS) {
+            vk::MemoryAllocateFlags::DEVICE_ADDRESS
+        } else {
+            vk::MemoryAllocateFlags::empty()
+        };
+        let allocation =
+            match allocator.allocate_with_flags(requirements, properties, allocate_flags) {
+                Ok(allocation) => allocation,
+                Err(error) => {
+                    unsafe { device.raw_handle().destroy_buffer(buffer, None) };
+                    return Err(error);
+                }
+            };
+        unsafe {
+            device
+                .raw_handle()
+                .bind_buffer_memory(buffer, allocation.raw_handle(), 0)
+                .unwrap_or_else(|error| fatal_vk_error("failed to bind_buffer_memory", error));
+        }
+
+        Ok(Arc::new_cyclic(|weak_self| Self {
+            weak_self: Weak::clone(weak_self),
+            device,
+            buffer,
+            allocation,
+            size,
+            usage,
+        }))
+    }
+
+    ///
+    /// Creates a buffer on memory imported with e.g. Allocator::import_fd
+    ///
+    pub fn import(
+        device: Arc<Device>,
+        allocation: Allocation,
+        size: u64,
+        usage: vk::BufferUsageFlags,
+    ) -> Result<Arc<Self>, AllocationError> {
+        check_device_address(&device, usage)?;
+        let mut external_info = vk::ExternalMemoryBufferCreateInfo::default()
+            .handle_types(allocation.get_external_handle_type());
+        let create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .push_next(&mut external_info);
+        let buffer = unsafe { device.raw_handle().create_buffer(&create_info, None) }
+            .unwrap_or_else(|error| fatal_vk_error("failed to create_buffer", error));
+
+        let requirements = unsafe { device.raw_handle().get_buffer_memory_requirements(buffer) };
+        let error =
+            if requirements.memory_type_bits & (1 << allocation.get_memory_type_index()) == 0 {
+                Some(AllocationError::InvalidMemoryType(
+                    allocation.get_memory_type_index(),
+                ))
+            } else if requirements.size > allocation.get_size() {
+                Some(AllocationError::ImportTooSmall {
+                    required: requirements.size,
+                    size: allocation.get_size(),
+                })
+            } else {
+                None
+            };
+        if let Some(error) = error {
+            unsafe { device.raw_handle().destroy_buffer(buffer, None) };
+            return Err(error);
+        }
+        unsafe {
+            device
+                .raw_handle()
+                .bind_buffer_memory(buffer, allocation.raw_handle(), 0)
+                .unwrap_or_else(|error| fatal_vk_error("failed to bind_buffer_memory", error));
+        }
+
+        Ok(Arc::new_cyclic(|weak_self| Self {
+            weak_self: Weak::clone(weak_self),
+            device,
+            buffer,
+            allocation,
+            size,
+            usage,
+        }))
+    }
+
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    ///
+    /// GPU address of the start of the buffer, for buffer references in shaders.
+    /// Panics if the buffer wasn't created with SHADER_DEVICE_ADDRESS usage
+    ///
+    pub fn device_address(&self) -> vk::DeviceAddress {
+        assert!(
+            self.usage
+                .contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
+            "buffer was created without SHADER_DEVICE_ADDRESS usage"
+        );
+        let info = vk::BufferDeviceAddressInfo::default().buffer(self.buffer);
+        unsafe { self.device.raw_handle().get_buffer_device_address(&info) }
+    }
+
+    ///
+    /// Maps the buffer memory, see Allocation::map. The mapping is unmapped when the
+    /// returned guard is dropped
+    ///
+    pub fn map(&self) -> Result<MappedMemory<'_>, vk::Result> {
+        self.allocation.map_len(self.size)
+    }
+
+    pub(in crate::vk) fn arc_self(&self) -> Arc<Self> {
+        self.weak_self.upgrade().unwrap()
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::Buffer {
+        self.buffer
+    }
+}
+
+fn check_device_address(
+    device: &Device,
+    usage: vk::BufferUsageFlags,
+) -> Result<(), AllocationError> {
+    if usage.contains(vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS)
+        && !device.is_buffer_device_address_enabled()
+    {
+        return Err(AllocationError::BufferDeviceAddressNotEnabled);
+    }
+    Ok(())
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw_handle().destroy_buffer(self.buffer, None);
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{size} bytes do not fit into the {free} bytes left in the frame segment")]
+pub struct DynamicBufferFullError {
+    size: u64,
+    free: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DynamicBufferCreationError {
+    #[error("a dynamic buffer needs at least one frame in flight")]
+    NoFramesInFlight,
+    #[error(transparent)]
+    Allocation(#[from] AllocationError),
+}
+
+///
+/// Persistently mapped uniform buffer split into one segment per frame in flight.
+/// Every alloc returns a dynamic offset for a UNIFORM_BUFFER_DYNAMIC descriptor.
+/// next_frame moves to the next segment, the GPU must be done reading it by then.
+/// The raw pointer into the mapping makes DynamicBuffer !Send and !Sync, it stays on
+/// the thread that created it
+///
+pub struct DynamicBuffer {
+    buffer: Arc<Buffer>,
+    data: *mut u8,
+    alignment: u64,
+    segment_size: u64,
+    segment_count: u64,
+    segment: u64,
+    offset: u64,
+}
+
+impl DynamicBuffer {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: &Allocator,
+        segment_size: u64,
+        frames_in_flight: u32,
+    ) -> Result<Self, DynamicBufferCreationError> {
+        if frames_in_flight == 0 {
+            return Err(DynamicBufferCreationError::NoFramesInFlight);
+        }
+        let alignment = device
+            .get_properties()
+            .limits
+            .min_uniform_buffer_offset_alignment;
+        let segment_size = segment_size.next_multiple_of(alignment);
+        let segment_count = frames_in_flight as u64;
+        let buffer = Buffer::new(
+            device,
+            allocator,
+            segment_size * segment_count,
+            vk::BufferUsageFlags::UNIFORM_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        let data = unsafe { buffer.allocation.map_raw() }.map_err(AllocationError::from)?;
+        Ok(Self {
+            buffer,
+            data,
+            alignment,
+            segment_size,
+            segment_count,
+            segment: 0,
+            offset: 0,
+        })
+    }
+
+    pub fn alloc(&mut self, data: &[u8]) -> Result<u32, DynamicBufferFullError> {
+        let size = data.len() as u64;
+        if self.offset + size > self.segment_size {
+            return Err(DynamicBufferFullError {
+                size,
+                free: self.segment_size - self.offset,
+            });
+        }
+        let offset = buffer_offset(self.segment, self.segment_size, self.offset);
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), self.data.add(offset as usize), data.len());
+        }
+        self.offset = next_alloc_offset(self.offset, size, self.alignment);
+        Ok(offset as u32)
+    }
+
+    pub fn next_frame(&mut self) {
+        self.segment = next_segment(self.segment, self.segment_count);
+        self.offset = 0;
+    }
+
+    pub fn get_buffer(&self) -> Arc<Buffer> {
+        Arc::clone(&self.buffer)
+    }
+}
+
+fn buffer_offset(segment: u64, segment_size: u64, offset: u64) -> u64 {
+    segment * segment_size + offset
+}
+
+///
+/// Where the alloc after size bytes at offset starts, keeping dynamic offsets aligned
+///
+fn next_alloc_offset(offset: u64, size: u64, alignment: u64) -> u64 {
+    (offset + size).next_multiple_of(alignment)
+}
+
+fn next_segment(segment: u64, segment_count: u64) -> u64 {
+    (segment + 1) % segment_count
+}
+
+impl Drop for DynamicBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            self.buffer.allocation.unmap_raw();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dynamic_offsets() {
+        assert_eq!(buffer_offset(0, 512, 0), 0);
+        assert_eq!(buffer_offset(2, 512, 256), 1280);
+    }
+
+    #[test]
+    fn dynamic_alignment() {
+        assert_eq!(next_alloc_offset(0, 1, 256), 256);
+        assert_eq!(next_alloc_offset(0, 256, 256), 256);
+        assert_eq!(next_alloc_offset(256, 257, 256), 768);
+        assert_eq!(next_alloc_offset(0, 0, 64), 0);
+    }
+
+    #[test]
+    fn dynamic_segment_wrap() {
+        assert_eq!(next_segment(0, 3), 1);
+        assert_eq!(next_segment(2, 3), 0);
+        assert_eq!(next_segment(0, 1), 0);
+    }
+}
@@ -1,16 +1,28 @@
-use std::{any::Any, sync::Arc};
+use std::{
+    any::Any,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
 
 use ash::vk;
 
 use super::{
+    buffer::Buffer,
     command_pool::CommandPool,
+    descriptor::DescriptorSet,
     device::Device,
     error::fatal_vk_error,
     framebuffer::Framebuffer,
-    pipeline::{GraphicsPipeline, render_pass::RenderPass},
+    image::Image,
+    pipeline::{
+        GraphicsPipeline,
+        layout::PipelineLayout,
+        render_pass::{DEFAULT_CLEAR_COLOR, RenderPass},
+    },
+    swapchain::InvalidSwapchainError,
 };
 
-#[derive(Default)]
+#[derive(Default, Debug, Clone, Copy)]
 pub struct DrawInfo {
     pub vertex_count: u32,
     pub instance_count: u32,
@@ -18,6 +30,16 @@ pub struct DrawInfo {
     pub first_instance: u32,
 }
 
+///
+/// Commands counted while recording, reset by begin
+///
+#[cfg(feature = "stats")]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct RecordingStats {
+    pub draw_calls: u32,
+    pub pipeline_binds: u32,
+}
+
 #[derive(Debug, strum::Display, Clone, Copy, PartialEq, Eq)]
 pub enum CommandBufferState {
     Initial,
@@ -31,12 +53,55 @@ pub enum CommandBufferState {
 #[error("Invalid command buffer state: {0}")]
 pub struct CommandBufferStateError(pub CommandBufferState);
 
+#[derive(Debug, thiserror::Error)]
+pub enum CommandRecordingError {
+    #[error(transparent)]
+    State(#[from] CommandBufferStateError),
+    #[error("{buffers} buffers were given with {offsets} offsets")]
+    OffsetCountMismatch { buffers: usize, offsets: usize },
+    #[error("inline updates must be a non-zero multiple of 4 of at most 65536 bytes, got {0}")]
+    InvalidUpdateSize(usize),
+    #[error("fill size must be vk::WHOLE_SIZE or a non-zero multiple of 4, got {0}")]
+    InvalidFillSize(u64),
+    #[error("buffer offset {0} is not a multiple of 4")]
+    UnalignedOffset(u64),
+    #[error("range of {size} bytes at offset {offset} exceeds the buffer size of {buffer_size}")]
+    OutOfBounds {
+        offset: u64,
+        size: u64,
+        buffer_size: u64,
+    },
+    #[error("aspects {aspects:?} are not a subset of the image aspects {image_aspects:?}")]
+    InvalidAspects {
+        aspects: vk::ImageAspectFlags,
+        image_aspects: vk::ImageAspectFlags,
+    },
+    #[error("transitioning depth and stencil separately needs separate depth stencil layouts")]
+    SeparateDepthStencilLayoutsNotEnabled,
+    #[error(transparent)]
+    InvalidSwapchain(#[from] InvalidSwapchainError),
+}
+
+///
+/// Queue family ownership transfer of an EXCLUSIVE resource.
+/// The release half is recorded for a queue of src_queue_family, the acquire half
+/// for a queue of dst_queue_family, and the acquire submit has to wait on a semaphore
+/// signaled by the release submit
+///
+#[derive(Debug, Clone, Copy)]
+pub struct OwnershipTransfer {
+    pub src_queue_family: u32,
+    pub dst_queue_family: u32,
+}
+
 pub struct CommandBuffer {
-    _command_pool: Arc<CommandPool>,
+    command_pool: Arc<CommandPool>,
     device: Arc<Device>,
     command_buffer: vk::CommandBuffer,
     state: CommandBufferState,
-    markers: Vec<Arc<dyn Any>>,
+    markers: Vec<Arc<dyn Any + Send + Sync>>,
+    #[cfg(feature = "stats")]
+    stats: RecordingStats,
 }
 
 impl CommandBuffer {
@@ -46,25 +111,60 @@ impl CommandBuffer {
         command_buffer: vk::CommandBuffer,
     ) -> Self {
         CommandBuffer {
-            _command_pool: command_pool,
+            command_pool,
             device,
             command_buffer,
             state: CommandBufferState::Initial,
             markers: Vec::new(),
+            #[cfg(feature = "stats")]
+            stats: RecordingStats::default(),
         }
     }
 
+    #[cfg(feature = "stats")]
+    pub fn get_stats(&self) -> RecordingStats {
+        self.stats
+    }
+
+    ///
+    /// Allocated from a CommandPool::new_protected pool
+    ///
+    pub fn is_protected(&self) -> bool {
+        self.command_pool.is_protected()
+    }
+
     pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::CommandBuffer {
         self.command_buffer
     }
 
+    ///
+    /// Begins recording. An executable command buffer is implicitly reset,
+    /// releasing every resource kept alive by previous recording
+    ///
     pub fn begin(&mut self) -> Result<(), CommandBufferStateError> {
+        self.begin_with_usage(vk::CommandBufferUsageFlags::empty())
+    }
+
+    ///
+    /// Same as begin, with usage flags such as ONE_TIME_SUBMIT for command buffers
+    /// that are submitted once and then re-recorded or freed
+    ///
+    pub fn begin_with_usage(
+        &mut self,
+        flags: vk::CommandBufferUsageFlags,
+    ) -> Result<(), CommandBufferStateError> {
         match self.state {
             CommandBufferState::Initial => (),
             CommandBufferState::Executable => (),
             state => return Err(CommandBufferStateError(state)),
         };
-        let begin_info = vk::CommandBufferBeginInfo::default();
+        self.markers.clear();
+        #[cfg(feature = "stats")]
+        {
+            self.stats = RecordingStats::default();
+        }
+        let begin_info = vk::CommandBufferBeginInfo::default().flags(flags);
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device
                 .raw_handle()
@@ -75,25 +175,51 @@ impl CommandBuffer {
         Ok(())
     }
 
+    pub fn reset(&mut self) -> Result<(), CommandBufferStateError> {
+        if self.state == CommandBufferState::Pending {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device
+                .raw_handle()
+                .reset_command_buffer(self.command_buffer, vk::CommandBufferResetFlags::empty())
+                .unwrap_or_else(|error| fatal_vk_error("failed to reset_command_buffer", error));
+        }
+        self.markers.clear();
+        self.state = CommandBufferState::Initial;
+        Ok(())
+    }
+
+    ///
+    /// Clears color attachments to DEFAULT_CLEAR_COLOR
+    ///
     pub fn cmd_begin_render_pass(
         &mut self,
         render_pass: Arc<RenderPass>,
         framebuffer: Arc<Framebuffer>,
+    ) -> Result<(), CommandBufferStateError> {
+        self.cmd_begin_render_pass_with_clear_color(render_pass, framebuffer, DEFAULT_CLEAR_COLOR)
+    }
+
+    pub fn cmd_begin_render_pass_with_clear_color(
+        &mut self,
+        render_pass: Arc<RenderPass>,
+        framebuffer: Arc<Framebuffer>,
+        clear_color: [f32; 4],
     ) -> Result<(), CommandBufferStateError> {
         if self.state != CommandBufferState::Recording {
             return Err(CommandBufferStateError(self.state));
         }
 
+        let clear_values = render_pass.get_clear_values(clear_color);
         let render_pass_begin = vk::RenderPassBeginInfo::default()
             .render_pass(unsafe { render_pass.raw_handle() })
             .framebuffer(unsafe { framebuffer.raw_handle() })
             .render_area(vk::Rect2D::default().extent(framebuffer.get_extent()))
-            .clear_values(&[vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: [0.0f32, 0.0f32, 0.0f32, 1.0f32],
-                },
-            }]);
+            .clear_values(&clear_values);
 
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device.raw_handle().cmd_begin_render_pass(
                 self.command_buffer,
@@ -116,6 +242,7 @@ impl CommandBuffer {
             return Err(CommandBufferStateError(self.state));
         }
 
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device.raw_handle().cmd_bind_pipeline(
                 self.command_buffer,
@@ -124,6 +251,475 @@ impl CommandBuffer {
             );
         }
 
+        self.markers.push(pipeline.handle_marker());
+        #[cfg(feature = "stats")]
+        {
+            self.stats.pipeline_binds += 1;
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Binds descriptor_set as set number set of the graphics bind point
+    ///
+    pub fn cmd_bind_graphics_descriptor_set(
+        &mut self,
+        layout: Arc<PipelineLayout>,
+        set: u32,
+        descriptor_set: Arc<DescriptorSet>,
+    ) -> Result<(), CommandBufferStateError> {
+        self.cmd_bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            layout,
+            set,
+            &[descriptor_set],
+            &[],
+        )
+    }
+
+    ///
+    /// Binds sets as set numbers first_set onwards. dynamic_offsets has one offset per
+    /// dynamic binding of the sets, in set and binding order, e.g. the offsets returned
+    /// by DynamicBuffer::alloc for UNIFORM_BUFFER_DYNAMIC bindings
+    ///
+    pub fn cmd_bind_descriptor_sets(
+        &mut self,
+        bind_point: vk::PipelineBindPoint,
+        layout: Arc<PipelineLayout>,
+        first_set: u32,
+        sets: &[Arc<DescriptorSet>],
+        dynamic_offsets: &[u32],
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+
+        let raw_sets: Vec<_> = sets.iter().map(|set| unsafe { set.raw_handle() }).collect();
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_bind_descriptor_sets(
+                self.command_buffer,
+                bind_point,
+                layout.raw_handle(),
+                first_set,
+                &raw_sets,
+                dynamic_offsets,
+            );
+        }
+
+        self.markers.push(layout);
+        for set in sets {
+            self.markers.push(set.clone());
+        }
+
+        Ok(())
+    }
+
+    pub fn cmd_bind_vertex_buffers(
+        &mut self,
+        first_binding: u32,
+        buffers: &[&Buffer],
+        offsets: &[u64],
+    ) -> Result<(), CommandRecordingError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state).into());
+        }
+        if buffers.len() != offsets.len() {
+            return Err(CommandRecordingError::OffsetCountMismatch {
+                buffers: buffers.len(),
+                offsets: offsets.len(),
+            });
+        }
+
+        let raw_buffers: Vec<_> = buffers
+            .iter()
+            .map(|buffer| unsafe { buffer.raw_handle() })
+            .collect();
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_bind_vertex_buffers(
+                self.command_buffer,
+                first_binding,
+                &raw_buffers,
+                offsets,
+            );
+        }
+
+        self.markers.extend(
+            buffers
+                .iter()
+                .map(|buffer| buffer.arc_self() as Arc<dyn Any + Send + Sync>),
+        );
+
+        Ok(())
+    }
+
+    fn cmd_pipeline_barrier(
+        &mut self,
+        src_stage: vk::PipelineStageFlags,
+        dst_stage: vk::PipelineStageFlags,
+        buffer_barriers: &[vk::BufferMemoryBarrier],
+        image_barriers: &[vk::ImageMemoryBarrier],
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_pipeline_barrier(
+                self.command_buffer,
+                src_stage,
+                dst_stage,
+                vk::DependencyFlags::empty(),
+                &[],
+                buffer_barriers,
+                image_barriers,
+            );
+        }
+        Ok(())
+    }
+
+    fn buffer_ownership_barrier(
+        buffer: &Buffer,
+        transfer: OwnershipTransfer,
+    ) -> vk::BufferMemoryBarrier<'static> {
+        vk::BufferMemoryBarrier::default()
+            .src_queue_family_index(transfer.src_queue_family)
+            .dst_queue_family_index(transfer.dst_queue_family)
+            .buffer(unsafe { buffer.raw_handle() })
+            .size(vk::WHOLE_SIZE)
+    }
+
+    fn image_ownership_barrier(
+        image: &Image,
+        transfer: OwnershipTransfer,
+        old_layout: vk::ImageLayout,
+        new_layout: vk::ImageLayout,
+    ) -> vk::ImageMemoryBarrier<'static> {
+        vk::ImageMemoryBarrier::default()
+            .src_queue_family_index(transfer.src_queue_family)
+            .dst_queue_family_index(transfer.dst_queue_family)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .image(unsafe { image.raw_handle() })
+            .subresource_range(image.get_subresource_range())
+    }
+
+    ///
+    /// Release half of a buffer ownership transfer, makes writes done at src_stage
+    /// with src_access available to the acquiring queue family
+    ///
+    pub fn cmd_release_buffer_ownership(
+        &mut self,
+        buffer: &Buffer,
+        transfer: OwnershipTransfer,
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+    ) -> Result<(), CommandBufferStateError> {
+        let barrier = Self::buffer_ownership_barrier(buffer, transfer).src_access_mask(src_access);
+        self.cmd_pipeline_barrier(
+            src_stage,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[barrier],
+            &[],
+        )?;
+        self.markers.push(buffer.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Acquire half of a buffer ownership transfer, transfer must match the release
+    ///
+    pub fn cmd_acquire_buffer_ownership(
+        &mut self,
+        buffer: &Buffer,
+        transfer: OwnershipTransfer,
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> Result<(), CommandBufferStateError> {
+        let barrier = Self::buffer_ownership_barrier(buffer, transfer).dst_access_mask(dst_access);
+        self.cmd_pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            dst_stage,
+            &[barrier],
+            &[],
+        )?;
+        self.markers.push(buffer.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Release half of an image ownership transfer. A layout transition, if any,
+    /// has to be given identically to both halves
+    ///
+    pub fn cmd_release_image_ownership(
+        &mut self,
+        image: &Image,
+        transfer: OwnershipTransfer,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        src_stage: vk::PipelineStageFlags,
+        src_access: vk::AccessFlags,
+    ) -> Result<(), CommandBufferStateError> {
+        let barrier = Self::image_ownership_barrier(image, transfer, old_layout, new_layout)
+            .src_access_mask(src_access);
+        self.cmd_pipeline_barrier(
+            src_stage,
+            vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+            &[],
+            &[barrier],
+        )?;
+        self.markers.push(image.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Acquire half of an image ownership transfer, transfer and layouts must match
+    /// the release
+    ///
+    pub fn cmd_acquire_image_ownership(
+        &mut self,
+        image: &Image,
+        transfer: OwnershipTransfer,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        dst_stage: vk::PipelineStageFlags,
+        dst_access: vk::AccessFlags,
+    ) -> Result<(), CommandBufferStateError> {
+        let barrier = Self::image_ownership_barrier(image, transfer, old_layout, new_layout)
+            .dst_access_mask(dst_access);
+        self.cmd_pipeline_barrier(
+            vk::PipelineStageFlags::TOP_OF_PIPE,
+            dst_stage,
+            &[],
+            &[barrier],
+        )?;
+        self.markers.push(image.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Transitions image from old_layout to new_layout, making accesses in src
+    /// available to accesses in dst
+    ///
+    pub fn cmd_image_barrier(
+        &mut self,
+        image: &Image,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+        (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    ) -> Result<(), CommandBufferStateError> {
+        let barrier = vk::ImageMemoryBarrier::default()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .image(unsafe { image.raw_handle() })
+            .subresource_range(image.get_subresource_range());
+        self.cmd_pipeline_barrier(src_stage, dst_stage, &[], &[barrier])?;
+        self.markers.push(image.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Like cmd_image_barrier, but only transitions the aspects in aspect_mask, e.g. the
+    /// depth aspect of a combined depth/stencil image to DEPTH_ATTACHMENT_OPTIMAL.
+    /// Transitioning only one aspect of such an image needs
+    /// DeviceBuilder::separate_depth_stencil_layouts
+    ///
+    pub fn cmd_image_aspect_barrier(
+        &mut self,
+        image: &Image,
+        aspect_mask: vk::ImageAspectFlags,
+        (old_layout, new_layout): (vk::ImageLayout, vk::ImageLayout),
+        (src_stage, src_access): (vk::PipelineStageFlags, vk::AccessFlags),
+        (dst_stage, dst_access): (vk::PipelineStageFlags, vk::AccessFlags),
+    ) -> Result<(), CommandRecordingError> {
+        let range = image.get_subresource_range();
+        if aspect_mask.is_empty() || !range.aspect_mask.contains(aspect_mask) {
+            return Err(CommandRecordingError::InvalidAspects {
+                aspects: aspect_mask,
+                image_aspects: range.aspect_mask,
+            });
+        }
+        if aspect_mask != range.aspect_mask
+            && !self.device.is_separate_depth_stencil_layouts_enabled()
+        {
+            return Err(CommandRecordingError::SeparateDepthStencilLayoutsNotEnabled);
+        }
+        let barrier = vk::ImageMemoryBarrier::default()
+            .src_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .dst_queue_family_index(vk::QUEUE_FAMILY_IGNORED)
+            .old_layout(old_layout)
+            .new_layout(new_layout)
+            .src_access_mask(src_access)
+            .dst_access_mask(dst_access)
+            .image(unsafe { image.raw_handle() })
+            .subresource_range(range.aspect_mask(aspect_mask));
+        self.cmd_pipeline_barrier(src_stage, dst_stage, &[], &[barrier])?;
+        self.markers.push(image.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Copies the whole image, which has to be in layout, tightly packed into
+    /// the start of buffer
+    ///
+    pub fn cmd_copy_image_to_buffer(
+        &mut self,
+        image: &Image,
+        layout: vk::ImageLayout,
+        buffer: &Buffer,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let range = image.get_subresource_range();
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(range.aspect_mask)
+                    .layer_count(1),
+            )
+            .image_extent(image.get_extent().into());
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_copy_image_to_buffer(
+                self.command_buffer,
+                image.raw_handle(),
+                layout,
+                buffer.raw_handle(),
+                &[region],
+            );
+        }
+        self.markers.push(image.arc_self());
+        self.markers.push(buffer.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Copies tightly packed texels from the start of buffer into the whole image,
+    /// which has to be in layout
+    ///
+    pub fn cmd_copy_buffer_to_image(
+        &mut self,
+        buffer: &Buffer,
+        image: &Image,
+        layout: vk::ImageLayout,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let range = image.get_subresource_range();
+        let region = vk::BufferImageCopy::default()
+            .image_subresource(
+                vk::ImageSubresourceLayers::default()
+                    .aspect_mask(range.aspect_mask)
+                    .layer_count(1),
+            )
+            .image_extent(image.get_extent().into());
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_copy_buffer_to_image(
+                self.command_buffer,
+                buffer.raw_handle(),
+                image.raw_handle(),
+                layout,
+                &[region],
+            );
+        }
+        self.markers.push(buffer.arc_self());
+        self.markers.push(image.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Writes data into buffer without a staging buffer, for small per-frame updates.
+    /// Has to be recorded outside of a render pass
+    ///
+    pub fn cmd_update_buffer(
+        &mut self,
+        buffer: &Buffer,
+        offset: u64,
+        data: &[u8],
+    ) -> Result<(), CommandRecordingError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state).into());
+        }
+        if data.is_empty() || data.len() > 65536 || !data.len().is_multiple_of(4) {
+            return Err(CommandRecordingError::InvalidUpdateSize(data.len()));
+        }
+        if !offset.is_multiple_of(4) {
+            return Err(CommandRecordingError::UnalignedOffset(offset));
+        }
+        if offset + data.len() as u64 > buffer.get_size() {
+            return Err(CommandRecordingError::OutOfBounds {
+                offset,
+                size: data.len() as u64,
+                buffer_size: buffer.get_size(),
+            });
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_update_buffer(
+                self.command_buffer,
+                buffer.raw_handle(),
+                offset,
+                data,
+            );
+        }
+        self.markers.push(buffer.arc_self());
+        Ok(())
+    }
+
+    ///
+    /// Fills size bytes at offset with repetitions of data, e.g. to reset indirect draw
+    /// counters. size can be vk::WHOLE_SIZE to fill up to the end of the buffer.
+    /// Has to be recorded outside of a render pass
+    ///
+    pub fn cmd_fill_buffer(
+        &mut self,
+        buffer: &Buffer,
+        offset: u64,
+        size: u64,
+        data: u32,
+    ) -> Result<(), CommandRecordingError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state).into());
+        }
+        if !offset.is_multiple_of(4) {
+            return Err(CommandRecordingError::UnalignedOffset(offset));
+        }
+        if size != vk::WHOLE_SIZE {
+            if size == 0 || !size.is_multiple_of(4) {
+                return Err(CommandRecordingError::InvalidFillSize(size));
+            }
+            if offset + size > buffer.get_size() {
+                return Err(CommandRecordingError::OutOfBounds {
+                    offset,
+                    size,
+                    buffer_size: buffer.get_size(),
+                });
+            }
+        } else if offset >= buffer.get_size() {
+            return Err(CommandRecordingError::OutOfBounds {
+                offset,
+                size,
+                buffer_size: buffer.get_size(),
+            });
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_fill_buffer(
+                self.command_buffer,
+                buffer.raw_handle(),
+                offset,
+                size,
+                data,
+            );
+        }
+        self.markers.push(buffer.arc_self());
         Ok(())
     }
 
@@ -134,6 +730,7 @@ impl CommandBuffer {
         if self.state != CommandBufferState::Recording {
             return Err(CommandBufferStateError(self.state));
         }
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device
                 .raw_handle()
@@ -145,6 +742,7 @@ impl CommandBuffer {
         if self.state != CommandBufferState::Recording {
             return Err(CommandBufferStateError(self.state));
         }
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device
                 .raw_handle()
@@ -153,6 +751,83 @@ impl CommandBuffer {
         Ok(())
     }
 
+    ///
+    /// Only for pipelines built with GraphicsPipelineBuilder::dynamic_line_width,
+    /// widths other than 1 need the wide_lines feature
+    ///
+    pub fn cmd_set_line_width(&mut self, line_width: f32) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device
+                .raw_handle()
+                .cmd_set_line_width(self.command_buffer, line_width);
+        }
+        Ok(())
+    }
+
+    ///
+    /// Requires a pipeline built with dynamic stencil state
+    ///
+    pub fn cmd_set_stencil_reference(
+        &mut self,
+        face: vk::StencilFaceFlags,
+        reference: u32,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_set_stencil_reference(
+                self.command_buffer,
+                face,
+                reference,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn cmd_set_stencil_compare_mask(
+        &mut self,
+        face: vk::StencilFaceFlags,
+        compare_mask: u32,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_set_stencil_compare_mask(
+                self.command_buffer,
+                face,
+                compare_mask,
+            );
+        }
+        Ok(())
+    }
+
+    pub fn cmd_set_stencil_write_mask(
+        &mut self,
+        face: vk::StencilFaceFlags,
+        write_mask: u32,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device.raw_handle().cmd_set_stencil_write_mask(
+                self.command_buffer,
+                face,
+                write_mask,
+            );
+        }
+        Ok(())
+    }
+
     pub fn cmd_draw(&mut self, draw_info: DrawInfo) -> Result<(), CommandBufferStateError> {
         if self.state != CommandBufferState::Recording {
             return Err(CommandBufferStateError(self.state));
@@ -164,6 +839,7 @@ impl CommandBuffer {
             first_instance,
         } = draw_info;
 
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device.raw_handle().cmd_draw(
                 self.command_buffer,
@@ -173,6 +849,31 @@ impl CommandBuffer {
                 first_instance,
             );
         }
+        #[cfg(feature = "stats")]
+        {
+            self.stats.draw_calls += 1;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Advances to the next subpass of the current render pass
+    ///
+    pub fn cmd_next_subpass(
+        &mut self,
+        contents: vk::SubpassContents,
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device
+                .raw_handle()
+                .cmd_next_subpass(self.command_buffer, contents);
+        }
+
         Ok(())
     }
 
@@ -181,6 +882,7 @@ impl CommandBuffer {
             return Err(CommandBufferStateError(self.state));
         }
 
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device
                 .raw_handle()
@@ -195,6 +897,7 @@ impl CommandBuffer {
             return Err(CommandBufferStateError(self.state));
         }
 
+        let _pool = self.command_pool.lock();
         unsafe {
             self.device
                 .raw_handle()
@@ -205,4 +908,97 @@ impl CommandBuffer {
 
         Ok(())
     }
+
+    ///
+    /// Opens a labeled region shown in RenderDoc/Nsight captures.
+    /// Compiles to a no-op without debug_assertions
+    ///
+    #[cfg(debug_assertions)]
+    pub fn cmd_begin_debug_label(
+        &mut self,
+        name: &str,
+        color: [f32; 4],
+    ) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+
+        let name = std::ffi::CString::new(name).unwrap_or_default();
+        let label = vk::DebugUtilsLabelEXT::default()
+            .label_name(&name)
+            .color(color);
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device
+                .debug_utils_handle()
+                .cmd_begin_debug_utils_label(self.command_buffer, &label);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(debug_assertions)]
+    pub fn cmd_end_debug_label(&mut self) -> Result<(), CommandBufferStateError> {
+        if self.state != CommandBufferState::Recording {
+            return Err(CommandBufferStateError(self.state));
+        }
+
+        let _pool = self.command_pool.lock();
+        unsafe {
+            self.device
+                .debug_utils_handle()
+                .cmd_end_debug_utils_label(self.command_buffer);
+        }
+
+        Ok(())
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn cmd_begin_debug_label(
+        &mut self,
+        _: &str,
+        _: [f32; 4],
+    ) -> Result<(), CommandBufferStateError> {
+        Ok(())
+    }
+
+    #[cfg(not(debug_assertions))]
+    pub fn cmd_end_debug_label(&mut self) -> Result<(), CommandBufferStateError> {
+        Ok(())
+    }
+
+    ///
+    /// Opens a labeled region that is closed when the returned guard is dropped.
+    /// Commands are recorded through the guard
+    ///
+    pub fn debug_label(&mut self, name: &str) -> Result<DebugLabel<'_>, CommandBufferStateError> {
+        self.cmd_begin_debug_label(name, [0.0; 4])?;
+        Ok(DebugLabel {
+            command_buffer: self,
+        })
+    }
+}
+
+pub struct DebugLabel<'a> {
+    command_buffer: &'a mut CommandBuffer,
+}
+
+impl Deref for DebugLabel<'_> {
+    type Target = CommandBuffer;
+    fn deref(&self) -> &Self::Target {
+        self.command_buffer
+    }
+}
+
+impl DerefMut for DebugLabel<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.command_buffer
+    }
+}
+
+impl Drop for DebugLabel<'_> {
+    fn drop(&mut self) {
+        // The command buffer may have been ended inside the scope, the label is lost then
+        let _ = self.command_buffer.cmd_end_debug_label();
+    }
 }
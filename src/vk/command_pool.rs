@@ -1,4 +1,4 @@
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, MutexGuard, Weak};
 
 use ash::vk;
 
@@ -8,18 +8,47 @@ use super::{command_buffer::CommandBuffer, device::Device, error::fatal_vk_error
 pub enum CommandPoolCreationError {
     #[error("queue faily index {0} is out of range 0..{1}")]
     InvalidQueueFamily(usize, usize),
+    #[error("queue family {0} has no protected queues")]
+    NotProtected(u32),
 }
 
+///
+/// Vulkan command pools are externally synchronized. Allocating, freeing, resetting and
+/// every call recording into one of its command buffers lock the pool, so they are safe
+/// from any thread. Recording on several threads into buffers of one pool is serialized
+/// by that lock, multithreaded recording should use one pool per thread
+///
 pub struct CommandPool {
     weak_self: Weak<Self>,
     device: Arc<Device>,
-    command_pool: vk::CommandPool,
+    command_pool: Mutex<vk::CommandPool>,
+    protected: bool,
 }
 
 impl CommandPool {
     pub fn new(
         device: Arc<Device>,
         queue_family_index: u32,
+    ) -> Result<Arc<Self>, CommandPoolCreationError> {
+        Self::create(device, queue_family_index, false)
+    }
+
+    ///
+    /// Pool of protected command buffers, which can access protected images and are
+    /// submitted with Queue::submit_batch. The family's queues have to be protected,
+    /// see QueueFamilySelector::protected_families
+    ///
+    pub fn new_protected(
+        device: Arc<Device>,
+        queue_family_index: u32,
+    ) -> Result<Arc<Self>, CommandPoolCreationError> {
+        Self::create(device, queue_family_index, true)
+    }
+
+    fn create(
+        device: Arc<Device>,
+        queue_family_index: u32,
+        protected: bool,
     ) -> Result<Arc<Self>, CommandPoolCreationError> {
         if queue_family_index as usize >= device.get_queue_family_count() {
             return Err(CommandPoolCreationError::InvalidQueueFamily(
@@ -27,36 +56,116 @@ impl CommandPool {
                 device.get_queue_family_count(),
             ));
         }
-        let create_info =
-            vk::CommandPoolCreateInfo::default().queue_family_index(queue_family_index);
+        if protected && !device.is_queue_family_protected(queue_family_index) {
+            return Err(CommandPoolCreationError::NotProtected(queue_family_index));
+        }
+        let mut flags = vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER;
+        if protected {
+            flags |= vk::CommandPoolCreateFlags::PROTECTED;
+        }
+        let create_info = vk::CommandPoolCreateInfo::default()
+            .queue_family_index(queue_family_index)
+            .flags(flags);
         let command_pool = unsafe { device.raw_handle().create_command_pool(&create_info, None) }
             .unwrap_or_else(|error| fatal_vk_error("failed to create_command_pool", error));
 
         Ok(Arc::new_cyclic(|weak_self| Self {
             weak_self: Weak::clone(weak_self),
             device,
-            command_pool,
+            command_pool: Mutex::new(command_pool),
+            protected,
         }))
     }
 
+    ///
+    /// Held by every call recording into a command buffer of the pool
+    ///
+    pub(in crate::vk) fn lock(&self) -> MutexGuard<'_, vk::CommandPool> {
+        self.command_pool.lock().unwrap()
+    }
+
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+
     pub fn allocate_command_buffer(&self) -> CommandBuffer {
+        self.allocate_command_buffers(1).pop().unwrap()
+    }
+
+    ///
+    /// Allocates count primary command buffers with a single vkAllocateCommandBuffers call
+    ///
+    pub fn allocate_command_buffers(&self, count: u32) -> Vec<CommandBuffer> {
+        if count == 0 {
+            return Vec::new();
+        }
+        let command_pool = self.lock();
         let allocate_info = vk::CommandBufferAllocateInfo::default()
-            .command_pool(self.command_pool)
-            .command_buffer_count(1)
+            .command_pool(*command_pool)
+            .command_buffer_count(count)
             .level(vk::CommandBufferLevel::PRIMARY);
 
-        let command_buffer = unsafe {
+        let command_buffers = unsafe {
             self.device
                 .raw_handle()
                 .allocate_command_buffers(&allocate_info)
-                .unwrap_or_else(|error| fatal_vk_error("failed to allocate_command_buffer", error))
-                [0]
+                .unwrap_or_else(|error| fatal_vk_error("failed to allocate_command_buffers", error))
         };
-        CommandBuffer::new(
-            self.weak_self.upgrade().unwrap(),
-            self.device.clone(),
-            command_buffer,
-        )
+        drop(command_pool);
+        let command_pool = self.weak_self.upgrade().unwrap();
+        command_buffers
+            .into_iter()
+            .map(|command_buffer| {
+                CommandBuffer::new(
+                    Arc::clone(&command_pool),
+                    self.device.clone(),
+                    command_buffer,
+                )
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns command_buffer to the pool, it must not be pending execution
+    ///
+    pub(in crate::vk) fn free_command_buffer(&self, command_buffer: CommandBuffer) {
+        let command_pool = self.lock();
+        unsafe {
+            self.device
+                .raw_handle()
+                .free_command_buffers(*command_pool, &[command_buffer.raw_handle()]);
+        }
+    }
+
+    ///
+    /// Frees a command buffer whose submissions have completed, unless it is still
+    /// referenced elsewhere
+    ///
+    pub(in crate::vk) fn free_submitted_command_buffer(&self, command_buffer: Arc<CommandBuffer>) {
+        if let Ok(command_buffer) = Arc::try_unwrap(command_buffer) {
+            self.free_command_buffer(command_buffer);
+        }
+    }
+
+    ///
+    /// Resets every command buffer allocated from this pool at once.
+    /// None of them may be pending execution. Afterwards they are all in the initial
+    /// state on the GPU side, while their CommandBufferState is left as is:
+    /// re-record them with CommandBuffer::begin, which accepts both states and
+    /// releases the resources kept alive by the previous recording
+    ///
+    pub fn reset(&self, release_resources: bool) -> Result<(), vk::Result> {
+        let flags = if release_resources {
+            vk::CommandPoolResetFlags::RELEASE_RESOURCES
+        } else {
+            vk::CommandPoolResetFlags::empty()
+        };
+        let command_pool = self.lock();
+        unsafe {
+            self.device
+                .raw_handle()
+                .reset_command_pool(*command_pool, flags)
+        }
     }
 }
 
@@ -65,7 +174,7 @@ impl Drop for CommandPool {
         unsafe {
             self.device
                 .raw_handle()
-                .destroy_command_pool(self.command_pool, None);
+                .destroy_command_pool(*self.command_pool.get_mut().unwrap(), None);
         }
     }
 }
@@ -0,0 +1,356 @@
+use std::{
+    any::Any,
+    ptr,
+    sync::{Arc, Mutex, Weak},
+};
+
+use ash::vk;
+
+use super::{
+    buffer::Buffer, device::Device, error::fatal_vk_error, image::Image, sampler::Sampler,
+    shader::ShaderStage,
+};
+
+pub struct DescriptorSetLayoutBuilder {
+    device: Arc<Device>,
+    bindings: Vec<vk::DescriptorSetLayoutBinding<'static>>,
+}
+
+impl DescriptorSetLayoutBuilder {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            bindings: Vec::new(),
+        }
+    }
+
+    pub fn binding(
+        self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stage: ShaderStage,
+    ) -> Self {
+        self.binding_for_stages(binding, descriptor_type, count, stage.into())
+    }
+
+    ///
+    /// Binding used by several stages, e.g. VERTEX | FRAGMENT
+    ///
+    pub fn binding_for_stages(
+        mut self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        count: u32,
+        stages: vk::ShaderStageFlags,
+    ) -> Self {
+        self.bindings.push(
+            vk::DescriptorSetLayoutBinding::default()
+                .binding(binding)
+                .descriptor_type(descriptor_type)
+                .descriptor_count(count)
+                .stage_flags(stages),
+        );
+        self
+    }
+
+    ///
+    /// Input attachments can only be read from fragment shaders
+    ///
+    pub fn input_attachment(self, binding: u32) -> Self {
+        self.binding(
+            binding,
+            vk::DescriptorType::INPUT_ATTACHMENT,
+            1,
+            ShaderStage::Fragment,
+        )
+    }
+
+    pub fn combined_image_sampler(self, binding: u32, stage: ShaderStage) -> Self {
+        self.binding(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            1,
+            stage,
+        )
+    }
+
+    ///
+    /// Uniform buffer bound at an offset given to cmd_bind_descriptor_sets,
+    /// e.g. a slice of a DynamicBuffer
+    ///
+    pub fn uniform_buffer_dynamic(self, binding: u32, stage: ShaderStage) -> Self {
+        self.binding(
+            binding,
+            vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+            1,
+            stage,
+        )
+    }
+
+    pub fn build(self) -> DescriptorSetLayout {
+        let create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&self.bindings);
+        let layout = unsafe { self.device.create_descriptor_set_layout(&create_info) };
+        DescriptorSetLayout {
+            device: self.device,
+            layout,
+        }
+    }
+}
+
+pub struct DescriptorSetLayout {
+    device: Arc<Device>,
+    layout: vk::DescriptorSetLayout,
+}
+
+impl DescriptorSetLayout {
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::DescriptorSetLayout {
+        self.layout
+    }
+}
+
+impl Drop for DescriptorSetLayout {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_descriptor_set_layout(self.layout);
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum DescriptorSetFreeError {
+    #[error("the pool was built without DescriptorPoolBuilder::free_descriptor_set")]
+    NotFreeable,
+    #[error("the descriptor set was allocated from a different pool")]
+    ForeignSet,
+}
+
+pub struct DescriptorPoolBuilder {
+    device: Arc<Device>,
+    max_sets: u32,
+    pool_sizes: Vec<vk::DescriptorPoolSize>,
+    free_descriptor_set: bool,
+}
+
+impl DescriptorPoolBuilder {
+    pub fn new(device: Arc<Device>, max_sets: u32) -> Self {
+        Self {
+            device,
+            max_sets,
+            pool_sizes: Vec::new(),
+            free_descriptor_set: false,
+        }
+    }
+
+    ///
+    /// Total number of descriptors of descriptor_type over all sets allocated from the pool,
+    /// repeated types are summed up
+    ///
+    pub fn pool_size(mut self, descriptor_type: vk::DescriptorType, count: u32) -> Self {
+        match self
+            .pool_sizes
+            .iter_mut()
+            .find(|size| size.ty == descriptor_type)
+        {
+            Some(size) => size.descriptor_count += count,
+            None => self.pool_sizes.push(
+                vk::DescriptorPoolSize::default()
+                    .ty(descriptor_type)
+                    .descriptor_count(count),
+            ),
+        }
+        self
+    }
+
+    ///
+    /// Lets sets be returned to the pool individually with DescriptorPool::free
+    ///
+    pub fn free_descriptor_set(mut self, free_descriptor_set: bool) -> Self {
+        self.free_descriptor_set = free_descriptor_set;
+        self
+    }
+
+    pub fn build(self) -> Arc<DescriptorPool> {
+        let flags = if self.free_descriptor_set {
+            vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET
+        } else {
+            vk::DescriptorPoolCreateFlags::empty()
+        };
+        let create_info = vk::DescriptorPoolCreateInfo::default()
+            .flags(flags)
+            .max_sets(self.max_sets)
+            .pool_sizes(&self.pool_sizes);
+        let pool = unsafe {
+            self.device
+                .raw_handle()
+                .create_descriptor_pool(&create_info, None)
+        }
+        .unwrap_or_else(|error| fatal_vk_error("failed to create_descriptor_pool", error));
+
+        Arc::new_cyclic(|weak_self| DescriptorPool {
+            weak_self: Weak::clone(weak_self),
+            device: self.device,
+            pool,
+            free_descriptor_set: self.free_descriptor_set,
+        })
+    }
+}
+
+pub struct DescriptorPool {
+    weak_self: Weak<Self>,
+    device: Arc<Device>,
+    pool: vk::DescriptorPool,
+    free_descriptor_set: bool,
+}
+
+impl DescriptorPool {
+    ///
+    /// Fails with ERROR_OUT_OF_POOL_MEMORY or ERROR_FRAGMENTED_POOL when the pool is exhausted
+    ///
+    pub fn allocate(&self, layout: Arc<DescriptorSetLayout>) -> Result<DescriptorSet, vk::Result> {
+        let set_layouts = [unsafe { layout.raw_handle() }];
+        let allocate_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.pool)
+            .set_layouts(&set_layouts);
+        let set = unsafe {
+            self.device
+                .raw_handle()
+                .allocate_descriptor_sets(&allocate_info)
+        }?[0];
+
+        Ok(DescriptorSet {
+            pool: self.weak_self.upgrade().unwrap(),
+            device: Arc::clone(&self.device),
+            _layout: layout,
+            set,
+            resources: Mutex::new(Vec::new()),
+        })
+    }
+
+    ///
+    /// Returns set to the pool. Taking it by value guarantees that no command buffer
+    /// still references it
+    ///
+    pub fn free(&self, set: DescriptorSet) -> Result<(), DescriptorSetFreeError> {
+        if !self.free_descriptor_set {
+            return Err(DescriptorSetFreeError::NotFreeable);
+        }
+        if !ptr::eq(Arc::as_ptr(&set.pool), self) {
+            return Err(DescriptorSetFreeError::ForeignSet);
+        }
+        unsafe {
+            self.device
+                .raw_handle()
+                .free_descriptor_sets(self.pool, &[set.set])
+                .unwrap_or_else(|error| fatal_vk_error("failed to free_descriptor_sets", error));
+        }
+        Ok(())
+    }
+}
+
+impl Drop for DescriptorPool {
+    fn drop(&mut self) {
+        unsafe {
+            self.device
+                .raw_handle()
+                .destroy_descriptor_pool(self.pool, None);
+        }
+    }
+}
+
+///
+/// Freed together with its pool or with DescriptorPool::free. Keeps the resources written into it alive,
+/// so that command buffers binding it only have to reference the set
+///
+pub struct DescriptorSet {
+    pool: Arc<DescriptorPool>,
+    device: Arc<Device>,
+    _layout: Arc<DescriptorSetLayout>,
+    set: vk::DescriptorSet,
+    resources: Mutex<Vec<Arc<dyn Any + Send + Sync>>>,
+}
+
+impl DescriptorSet {
+    ///
+    /// The set must not be in use by a pending command buffer
+    ///
+    pub(in crate::vk) fn write_image(
+        &self,
+        binding: u32,
+        descriptor_type: vk::DescriptorType,
+        image: &Image,
+        sampler: Option<Arc<Sampler>>,
+        layout: vk::ImageLayout,
+    ) {
+        let mut image_info = vk::DescriptorImageInfo::default()
+            .image_view(unsafe { image.get_view().raw_handle() })
+            .image_layout(layout);
+        if let Some(sampler) = &sampler {
+            image_info = image_info.sampler(unsafe { sampler.raw_handle() });
+        }
+        let image_infos = [image_info];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .descriptor_type(descriptor_type)
+            .image_info(&image_infos);
+        unsafe {
+            self.device
+                .raw_handle()
+                .update_descriptor_sets(&[write], &[]);
+        }
+
+        let mut resources = self.resources.lock().unwrap();
+        resources.push(image.arc_self());
+        if let Some(sampler) = sampler {
+            resources.push(sampler);
+        }
+    }
+
+    ///
+    /// Writes a texture and the sampler it is read with into a COMBINED_IMAGE_SAMPLER binding.
+    /// layout is the layout image will be in when the set is used, usually
+    /// SHADER_READ_ONLY_OPTIMAL. The set must not be in use by a pending command buffer
+    ///
+    pub fn write_combined_image_sampler(
+        &self,
+        binding: u32,
+        image: &Image,
+        sampler: Arc<Sampler>,
+        layout: vk::ImageLayout,
+    ) {
+        self.write_image(
+            binding,
+            vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            image,
+            Some(sampler),
+            layout,
+        );
+    }
+
+    ///
+    /// Writes range bytes of buffer into a UNIFORM_BUFFER_DYNAMIC binding, the offset
+    /// into buffer is given when binding the set.
+    /// The set must not be in use by a pending command buffer
+    ///
+    pub fn write_uniform_buffer_dynamic(&self, binding: u32, buffer: &Buffer, range: u64) {
+        let buffer_infos = [vk::DescriptorBufferInfo::default()
+            .buffer(unsafe { buffer.raw_handle() })
+            .range(range)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.set)
+            .dst_binding(binding)
+            .descriptor_type(vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC)
+            .buffer_info(&buffer_infos);
+        unsafe {
+            self.device
+                .raw_handle()
+                .update_descriptor_sets(&[write], &[]);
+        }
+        self.resources.lock().unwrap().push(buffer.arc_self());
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::DescriptorSet {
+        self.set
+    }
+}
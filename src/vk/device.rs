@@ -1,32 +1,78 @@
 pub mod device_extensions;
 pub mod queues;
 
-use std::{error::Error, ffi::CStr, sync::Arc};
+use std::{
+    error::Error,
+    ffi::{CStr, CString},
+    fmt,
+    sync::Arc,
+};
 
 use ash::{
-    khr,
+    ext, khr,
     vk::{
         self, DeviceCreateInfo, DeviceQueueCreateInfo, ImageView, PhysicalDeviceProperties,
         PipelineCache, ShaderModule, SwapchainCreateInfoKHR, SwapchainKHR,
     },
 };
 use device_extensions::DeviceExtensionManager;
-use queues::{Queue, QueueFamilySelector};
+use queues::{OneTimeSubmitError, Queue, QueueFamilySelector};
 
 use super::{
+    command_buffer::CommandBuffer,
+    command_pool::CommandPool,
     error::fatal_vk_error,
     instance::Instance,
     physical_device::{
         self,
         features::{FeaturesInfo, PhysicalDeviceFeatures2},
+        properties::{DriverInfo, SubgroupInfo},
     },
     surface::{PhysicalDeviceSurfaceInfo, Surface},
+    swapchain::{PRESENT_ID_EXTENSION, PRESENT_WAIT_EXTENSION},
 };
 
+#[derive(Debug, thiserror::Error)]
+#[error("protected queues and swapchains require DeviceBuilder::protected_memory")]
+pub struct ProtectedMemoryNotEnabledError;
+
+#[derive(Debug, thiserror::Error)]
+#[error("queue family {0} doesn't support protected queues")]
+pub struct ProtectedQueueNotSupportedError(pub u32);
+
+#[derive(Debug, thiserror::Error)]
+#[error("no depth format{} can be used as an attachment on this device", if *.need_stencil { " with stencil" } else { "" })]
+pub struct NoDepthFormatError {
+    pub need_stencil: bool,
+}
+
+///
+/// Depth formats in order of preference. D16_UNORM is always supported as a last resort
+///
+const DEPTH_FORMATS: [vk::Format; 4] = [
+    vk::Format::D32_SFLOAT,
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM,
+];
+const DEPTH_STENCIL_FORMATS: [vk::Format; 3] = [
+    vk::Format::D32_SFLOAT_S8_UINT,
+    vk::Format::D24_UNORM_S8_UINT,
+    vk::Format::D16_UNORM_S8_UINT,
+];
+
+///
+/// Every feature and extension requested with the builder is required,
+/// physical devices that don't support all of them are discarded
+///
 pub struct DeviceBuilder<S: QueueFamilySelector> {
     queue_family_selector: S,
     instance: Arc<Instance>,
     surface: Arc<Surface>,
+    features: FeaturesInfo,
+    extensions: Vec<CString>,
+    preferred_device_type: Option<vk::PhysicalDeviceType>,
+    rater: Option<Box<dyn DeviceRater>>,
 }
 
 impl<S: QueueFamilySelector> DeviceBuilder<S> {
@@ -35,13 +81,89 @@ impl<S: QueueFamilySelector> DeviceBuilder<S> {
             queue_family_selector,
             instance,
             surface,
+            features: FeaturesInfo::required(vk::PhysicalDeviceFeatures::default()),
+            extensions: Vec::new(),
+            preferred_device_type: None,
+            rater: None,
         }
     }
 
+    ///
+    /// Features to enable on top of the ones the crate always requires
+    ///
+    pub fn features(mut self, features: vk::PhysicalDeviceFeatures) -> Self {
+        self.features.features = FeaturesInfo::required(features).features;
+        self
+    }
+
+    ///
+    /// Enables multiview rendering (core since Vulkan 1.1)
+    ///
+    pub fn multiview(mut self, multiview: bool) -> Self {
+        self.features.multiview = multiview;
+        self
+    }
+
+    ///
+    /// Enables buffer device addresses (core since Vulkan 1.2)
+    ///
+    pub fn buffer_device_address(mut self, buffer_device_address: bool) -> Self {
+        self.features.buffer_device_address = buffer_device_address;
+        self
+    }
+
+    ///
+    /// Enables protected memory (core since Vulkan 1.1), needed for protected queues
+    /// and swapchains
+    ///
+    pub fn protected_memory(mut self, protected_memory: bool) -> Self {
+        self.features.protected_memory = protected_memory;
+        self
+    }
+
+    ///
+    /// Enables separate depth and stencil layouts (core since Vulkan 1.2), needed to
+    /// transition one aspect of a combined depth/stencil image at a time
+    ///
+    pub fn separate_depth_stencil_layouts(mut self, separate_depth_stencil_layouts: bool) -> Self {
+        self.features.separate_depth_stencil_layouts = separate_depth_stencil_layouts;
+        self
+    }
+
+    ///
+    /// Extensions to enable on top of REQUIRED_DEVICE_EXTENSIONS
+    ///
+    pub fn extension(mut self, name: &CStr) -> Self {
+        self.extensions.push(name.to_owned());
+        self
+    }
+
+    ///
+    /// Rates suitable devices of device_type higher, e.g. INTEGRATED_GPU for a low-power mode.
+    /// Unlike the requirements above, devices of other types are still used if none match
+    ///
+    pub fn prefer_device_type(mut self, device_type: vk::PhysicalDeviceType) -> Self {
+        self.preferred_device_type = Some(device_type);
+        self
+    }
+
+    ///
+    /// Replaces DefaultDeviceRater, e.g. to require a minimum amount of VRAM or prefer
+    /// a vendor. prefer_device_type is ignored then
+    ///
+    pub fn rater(mut self, rater: impl DeviceRater + 'static) -> Self {
+        self.rater = Some(Box::new(rater));
+        self
+    }
+
     pub fn build(self) -> Result<(Device, S), Box<dyn Error>> {
+        let default_rater = DefaultDeviceRater::new(self.preferred_device_type);
         let physical_device_choice = physical_device::select_physical_device(
-            &self.instance,
+            self.instance.as_ref(),
             self.queue_family_selector.clone(),
+            &self.features,
+            &self.extensions,
+            self.rater.as_deref().unwrap_or(&default_rater),
         )?;
 
         let physical_device = physical_device_choice.device;
@@ -62,51 +184,158 @@ impl<S: QueueFamilySelector> DeviceBuilder<S> {
             queue_counts[*id as usize] = priorities.len();
         }
 
+        let protected_families = queue_family_selector.protected_families();
+        if !protected_families.is_empty() && !self.features.protected_memory {
+            return Err(ProtectedMemoryNotEnabledError.into());
+        }
+        if let Some(&family) = protected_families.iter().find(|&&family| {
+            !physical_device_choice
+                .queue_flags
+                .get(family as usize)
+                .is_some_and(|flags| flags.contains(vk::QueueFlags::PROTECTED))
+        }) {
+            return Err(ProtectedQueueNotSupportedError(family).into());
+        }
+
         let queue_infos: Vec<_> = requirements
             .iter()
             .map(|(id, priorities)| {
+                let flags = if protected_families.contains(id) {
+                    vk::DeviceQueueCreateFlags::PROTECTED
+                } else {
+                    vk::DeviceQueueCreateFlags::empty()
+                };
                 DeviceQueueCreateInfo::default()
+                    .flags(flags)
                     .queue_family_index(*id)
                     .queue_priorities(priorities)
             })
             .collect();
 
-        let features2 = PhysicalDeviceFeatures2::new_required();
+        let features2 = PhysicalDeviceFeatures2::from_info(&self.features);
 
         let device_features = features2.features();
         let mut next = features2.next();
 
         let mut device_extension_manager =
-            DeviceExtensionManager::init(&self.instance, physical_device)?;
+            DeviceExtensionManager::init(self.instance.as_ref(), physical_device)?;
         device_extension_manager.add_extensions(&REQUIRED_DEVICE_EXTENSIONS)?;
+        device_extension_manager.add_extensions(&self.extensions)?;
         let ext_names = device_extension_manager.list_names();
 
-        let device_info = DeviceCreateInfo::default()
+        let mut device_info = DeviceCreateInfo::default()
             .queue_create_infos(&queue_infos)
             .enabled_features(&device_features)
             .enabled_extension_names(&ext_names)
             .push_next(&mut next);
 
+        // Only chained when the extensions are enabled
+        let mut present_id_features =
+            vk::PhysicalDevicePresentIdFeaturesKHR::default().present_id(true);
+        let mut present_wait_features =
+            vk::PhysicalDevicePresentWaitFeaturesKHR::default().present_wait(true);
+        let enabled_extensions = device_extension_manager.enabled_names();
+        let is_enabled = |name: &CStr| {
+            enabled_extensions
+                .iter()
+                .any(|enabled| *enabled == name.to_string_lossy())
+        };
+        if is_enabled(PRESENT_ID_EXTENSION) {
+            device_info = device_info.push_next(&mut present_id_features);
+        }
+        if is_enabled(PRESENT_WAIT_EXTENSION) {
+            device_info = device_info.push_next(&mut present_wait_features);
+        }
+
         let device = unsafe { self.instance.create_device(physical_device, &device_info) }?;
 
-        Ok((
-            Device {
-                instance: self.instance,
-                surface: self.surface,
-                physical_device: physical_device_choice.device,
-                device,
-                queue_counts,
-            },
-            physical_device_choice.queue_family_selector,
-        ))
+        #[cfg(debug_assertions)]
+        let debug_utils =
+            ash::ext::debug_utils::Device::new(&unsafe { self.instance.raw_handle() }, &device);
+
+        let device = Device {
+            instance: self.instance,
+            surface: self.surface,
+            physical_device: physical_device_choice.device,
+            device,
+            queue_counts,
+            graphics_family: queue_family_selector.graphics_family(),
+            present_family: queue_family_selector.present_family(),
+            protected_families,
+            enabled_features: self.features,
+            enabled_extensions,
+            #[cfg(debug_assertions)]
+            debug_utils,
+        };
+
+        log::info!("Created {:?}", device);
+        log::debug!(
+            device:?;
+            "
+{:?} Info:
+physical device: {:?};
+queue counts: {:?};
+graphics family: {:?};
+present family: {:?};
+extensions: {:?};
+features: {:?};",
+            device,
+            device.physical_device,
+            device.queue_counts,
+            device.graphics_family,
+            device.present_family,
+            device.enabled_extensions,
+            device.enabled_features.enabled_names(),
+        );
+
+        Ok((device, physical_device_choice.queue_family_selector))
     }
 }
 
 pub const REQUIRED_DEVICE_EXTENSIONS: [&CStr; 2] =
     [c"VK_KHR_swapchain", c"VK_KHR_vulkan_memory_model"];
 
+///
+/// Rates physical devices that have the required extensions, features and queue families,
+/// the highest rated one is used. Devices rated 0 or less are discarded
+///
+pub trait DeviceRater {
+    fn rate(&self, info: &PhysicalDeviceInfo, queue_props: &[vk::QueueFamilyProperties]) -> i32;
+}
+
+///
+/// Discards devices that are neither discrete nor integrated GPUs,
+/// rates the preferred type 2 and the other 1
+///
+pub struct DefaultDeviceRater {
+    preferred_type: Option<vk::PhysicalDeviceType>,
+}
+
+impl DefaultDeviceRater {
+    pub fn new(preferred_type: Option<vk::PhysicalDeviceType>) -> Self {
+        Self { preferred_type }
+    }
+}
+
+impl DeviceRater for DefaultDeviceRater {
+    fn rate(&self, info: &PhysicalDeviceInfo, _queue_props: &[vk::QueueFamilyProperties]) -> i32 {
+        let device_type = info.properties.device_type;
+        if device_type != vk::PhysicalDeviceType::DISCRETE_GPU
+            && device_type != vk::PhysicalDeviceType::INTEGRATED_GPU
+        {
+            0
+        } else if self.preferred_type == Some(device_type) {
+            2
+        } else {
+            1
+        }
+    }
+}
+
 pub struct PhysicalDeviceInfo {
     pub properties: PhysicalDeviceProperties,
+    pub driver: DriverInfo,
+    pub subgroup: SubgroupInfo,
     pub features: FeaturesInfo,
 }
 
@@ -116,18 +345,177 @@ pub struct Device {
     physical_device: vk::PhysicalDevice,
     device: ash::Device,
     queue_counts: Vec<usize>,
+    graphics_family: Option<u32>,
+    present_family: Option<u32>,
+    protected_families: Vec<u32>,
+    enabled_features: FeaturesInfo,
+    enabled_extensions: Vec<String>,
+    #[cfg(debug_assertions)]
+    debug_utils: ash::ext::debug_utils::Device,
 }
 impl Device {
     pub fn create_swapchain(
         &self,
         create_info: &SwapchainCreateInfoKHR,
-    ) -> Result<SwapchainKHR, Box<dyn Error>> {
+    ) -> Result<SwapchainKHR, vk::Result> {
         unsafe { self.instance.create_swapchain(&self.device, create_info) }
     }
 
     pub fn get_surface_info(&self) -> Result<PhysicalDeviceSurfaceInfo, vk::Result> {
-        self.surface
-            .get_physical_device_surface_info(self.physical_device)
+        self.get_surface_info_for(&self.surface)
+    }
+
+    ///
+    /// Same as get_surface_info for any surface of the instance, e.g. of another window
+    ///
+    pub fn get_surface_info_for(
+        &self,
+        surface: &Surface,
+    ) -> Result<PhysicalDeviceSurfaceInfo, vk::Result> {
+        surface.get_physical_device_surface_info(self.physical_device)
+    }
+
+    ///
+    /// See Surface::get_physical_device_surface_protected
+    ///
+    pub fn get_surface_protected_for(&self, surface: &Surface) -> Result<Option<bool>, vk::Result> {
+        surface.get_physical_device_surface_protected(self.physical_device)
+    }
+
+    ///
+    /// Whether the present queue family can present to surface
+    ///
+    pub fn supports_present_to(&self, surface: &Surface) -> Result<bool, vk::Result> {
+        match self.present_family {
+            Some(family) => {
+                surface.get_physical_device_surface_support(self.physical_device, family)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn get_properties(&self) -> PhysicalDeviceProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_properties(self.physical_device)
+        }
+    }
+
+    pub fn get_enabled_features(&self) -> vk::PhysicalDeviceFeatures {
+        self.enabled_features.features
+    }
+
+    pub fn is_multiview_enabled(&self) -> bool {
+        self.enabled_features.multiview
+    }
+
+    pub fn is_protected_memory_enabled(&self) -> bool {
+        self.enabled_features.protected_memory
+    }
+
+    pub fn is_buffer_device_address_enabled(&self) -> bool {
+        self.enabled_features.buffer_device_address
+    }
+
+    pub fn is_separate_depth_stencil_layouts_enabled(&self) -> bool {
+        self.enabled_features.separate_depth_stencil_layouts
+    }
+
+    ///
+    /// True if PRESENT_ID_EXTENSION and PRESENT_WAIT_EXTENSION are enabled
+    ///
+    pub fn is_present_wait_enabled(&self) -> bool {
+        self.is_extension_enabled(PRESENT_ID_EXTENSION)
+            && self.is_extension_enabled(PRESENT_WAIT_EXTENSION)
+    }
+
+    ///
+    /// True if queues of the family were created with DeviceQueueCreateFlags::PROTECTED
+    ///
+    pub fn is_queue_family_protected(&self, queue_family_index: u32) -> bool {
+        self.protected_families.contains(&queue_family_index)
+    }
+
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        let name = name.to_string_lossy();
+        self.enabled_extensions
+            .iter()
+            .any(|enabled| *enabled == name)
+    }
+
+    pub fn get_memory_properties(&self) -> vk::PhysicalDeviceMemoryProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_memory_properties(self.physical_device)
+        }
+    }
+
+    ///
+    /// Checks that format can be a depth/stencil attachment with optimal tiling
+    ///
+    pub fn is_depth_format_supported(&self, format: vk::Format) -> bool {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+        properties
+            .optimal_tiling_features
+            .contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    }
+
+    ///
+    /// Best depth attachment format of the device, D32_SFLOAT without stencil,
+    /// D32_SFLOAT_S8_UINT or D24_UNORM_S8_UINT with it
+    ///
+    pub fn default_depth_format(
+        &self,
+        need_stencil: bool,
+    ) -> Result<vk::Format, NoDepthFormatError> {
+        choose_depth_format(need_stencil, |format| {
+            self.is_depth_format_supported(format)
+        })
+        .ok_or(NoDepthFormatError { need_stencil })
+    }
+
+    ///
+    /// Sample counts supported by both color and depth framebuffer attachments,
+    /// the highest bit is the best usable MSAA level
+    ///
+    pub fn get_max_usable_sample_count(&self) -> vk::SampleCountFlags {
+        let limits = self.get_properties().limits;
+        highest_sample_count(
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts,
+        )
+    }
+
+    ///
+    /// Highest supported sample count that is not above requested,
+    /// e.g. TYPE_4 becomes TYPE_2 on a device without 4x MSAA
+    ///
+    pub fn clamp_sample_count(&self, requested: vk::SampleCountFlags) -> vk::SampleCountFlags {
+        let limits = self.get_properties().limits;
+        clamp_sample_count(
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts,
+            requested,
+        )
+    }
+
+    ///
+    /// Empty if format can't be used for sparse 2D images with usage
+    ///
+    pub fn get_sparse_image_format_properties(
+        &self,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Vec<vk::SparseImageFormatProperties> {
+        unsafe {
+            self.instance
+                .get_physical_device_sparse_image_format_properties(
+                    self.physical_device,
+                    format,
+                    usage,
+                )
+        }
     }
 
     pub fn get_queue_family_count(&self) -> usize {
@@ -138,6 +526,41 @@ impl Device {
         self.queue_counts.clone()
     }
 
+    ///
+    /// Queue family index chosen for graphics, if the queue family selector has one
+    ///
+    pub fn get_graphics_family(&self) -> Option<u32> {
+        self.graphics_family
+    }
+
+    ///
+    /// Queue family index chosen for presentation, if the queue family selector has one
+    ///
+    pub fn get_present_family(&self) -> Option<u32> {
+        self.present_family
+    }
+
+    ///
+    /// Records a ONE_TIME_SUBMIT command buffer from command_pool with f and waits for it
+    /// to complete on queue, see Queue::one_time_submit
+    ///
+    pub async fn one_time_submit(
+        &self,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        f: impl FnOnce(&mut CommandBuffer),
+    ) -> Result<(), OneTimeSubmitError> {
+        queue.one_time_submit(command_pool, f).await
+    }
+
+    pub fn wait_idle(&self) {
+        unsafe {
+            self.device
+                .device_wait_idle()
+                .unwrap_or_else(|e| fatal_vk_error("failed to device_wait_idle", e))
+        }
+    }
+
     pub unsafe fn destroy_swapchain(&self, swapchain: SwapchainKHR) -> Result<(), Box<dyn Error>> {
         unsafe { self.instance.destroy_swapchain(&self.device, swapchain) }
     }
@@ -196,12 +619,33 @@ impl Device {
     pub unsafe fn destroy_pipeline_layout(&self, layout: vk::PipelineLayout) {
         unsafe { self.device.destroy_pipeline_layout(layout, None) };
     }
+
+    pub unsafe fn create_descriptor_set_layout(
+        &self,
+        create_info: &vk::DescriptorSetLayoutCreateInfo,
+    ) -> vk::DescriptorSetLayout {
+        unsafe {
+            self.device
+                .create_descriptor_set_layout(create_info, None)
+                .unwrap_or_else(|e| fatal_vk_error("failed to create descriptor set layout", e))
+        }
+    }
+
+    pub unsafe fn destroy_descriptor_set_layout(&self, layout: vk::DescriptorSetLayout) {
+        unsafe { self.device.destroy_descriptor_set_layout(layout, None) };
+    }
     pub unsafe fn create_render_pass(
         &self,
         create_info: &vk::RenderPassCreateInfo,
     ) -> Result<vk::RenderPass, vk::Result> {
         unsafe { self.device.create_render_pass(create_info, None) }
     }
+    pub unsafe fn create_render_pass2(
+        &self,
+        create_info: &vk::RenderPassCreateInfo2,
+    ) -> Result<vk::RenderPass, vk::Result> {
+        unsafe { self.device.create_render_pass2(create_info, None) }
+    }
     pub unsafe fn destroy_render_pass(&self, render_pass: vk::RenderPass) {
         unsafe {
             self.device.destroy_render_pass(render_pass, None);
@@ -245,19 +689,95 @@ impl Device {
         }
     }
 
+    ///
+    /// Escape hatch for interop with libraries that work on raw ash
+    ///
+    /// # Safety
+    /// The device must not be destroyed through it, and objects created through it
+    /// have to be destroyed before this Device is dropped
+    ///
+    pub unsafe fn raw_ash_device(&self) -> &ash::Device {
+        &self.device
+    }
+
+    ///
+    /// # Safety
+    /// See raw_ash_device
+    ///
+    pub unsafe fn raw_physical_device(&self) -> vk::PhysicalDevice {
+        self.physical_device
+    }
+
     pub(in crate::vk) unsafe fn raw_handle(&self) -> ash::Device {
         self.device.clone()
     }
 
+    #[cfg(debug_assertions)]
+    pub(in crate::vk) unsafe fn debug_utils_handle(&self) -> &ash::ext::debug_utils::Device {
+        &self.debug_utils
+    }
+
     pub(in crate::vk) unsafe fn make_swapchain_device(&self) -> khr::swapchain::Device {
         unsafe { khr::swapchain::Device::new(&self.instance.raw_handle(), &self.device) }
     }
+
+    pub(in crate::vk) unsafe fn make_hdr_metadata_device(&self) -> ext::hdr_metadata::Device {
+        unsafe { ext::hdr_metadata::Device::new(&self.instance.raw_handle(), &self.device) }
+    }
+
+    pub(in crate::vk) unsafe fn make_present_wait_device(&self) -> khr::present_wait::Device {
+        unsafe { khr::present_wait::Device::new(&self.instance.raw_handle(), &self.device) }
+    }
+
+    pub(in crate::vk) unsafe fn make_full_screen_exclusive_device(
+        &self,
+    ) -> ext::full_screen_exclusive::Device {
+        unsafe {
+            ext::full_screen_exclusive::Device::new(&self.instance.raw_handle(), &self.device)
+        }
+    }
+}
+impl fmt::Debug for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device {:?}", self.device.handle())
+    }
 }
+
 impl Drop for Device {
     fn drop(&mut self) {
         self.destroy_device();
     }
 }
+fn highest_sample_count(counts: vk::SampleCountFlags) -> vk::SampleCountFlags {
+    match 31u32.checked_sub(counts.as_raw().leading_zeros()) {
+        Some(bit) => vk::SampleCountFlags::from_raw(1 << bit),
+        None => vk::SampleCountFlags::TYPE_1,
+    }
+}
+
+fn clamp_sample_count(
+    supported: vk::SampleCountFlags,
+    requested: vk::SampleCountFlags,
+) -> vk::SampleCountFlags {
+    let allowed = requested.as_raw() | (requested.as_raw().saturating_sub(1));
+    highest_sample_count(supported & vk::SampleCountFlags::from_raw(allowed))
+}
+
+fn choose_depth_format(
+    need_stencil: bool,
+    is_supported: impl Fn(vk::Format) -> bool,
+) -> Option<vk::Format> {
+    let candidates: &[vk::Format] = if need_stencil {
+        &DEPTH_STENCIL_FORMATS
+    } else {
+        &DEPTH_FORMATS
+    };
+    candidates
+        .iter()
+        .copied()
+        .find(|&format| is_supported(format))
+}
+
 pub fn fill_selector<S: QueueFamilySelector>(device: Arc<Device>, selector: S) -> S::Q {
     let requirements = selector.requirements();
 
@@ -277,13 +797,21 @@ pub fn fill_selector<S: QueueFamilySelector>(device: Arc<Device>, selector: S) -
                     .iter()
                     .enumerate()
                     .map(|(queue_index, _)| unsafe {
+                        // Protected queues can only be retrieved with vkGetDeviceQueue2
+                        let flags = if device.is_queue_family_protected(*queue_family_index) {
+                            vk::DeviceQueueCreateFlags::PROTECTED
+                        } else {
+                            vk::DeviceQueueCreateFlags::empty()
+                        };
+                        let queue_info = vk::DeviceQueueInfo2::default()
+                            .flags(flags)
+                            .queue_family_index(*queue_family_index)
+                            .queue_index(queue_index.try_into().unwrap());
                         Queue::new(
                             Arc::clone(&device),
-                            device.raw_handle().get_device_queue(
-                                *queue_family_index,
-                                queue_index.try_into().unwrap(),
-                            ),
+                            device.raw_handle().get_device_queue2(&queue_info),
                         )
+                        .with_protected(!flags.is_empty())
                     })
                     .collect::<Vec<Queue>>(),
             )
@@ -294,3 +822,59 @@ pub fn fill_selector<S: QueueFamilySelector>(device: Arc<Device>, selector: S) -
 
     queues
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn depth_format_preference() {
+        assert_eq!(
+            choose_depth_format(false, |_| true),
+            Some(vk::Format::D32_SFLOAT)
+        );
+        assert_eq!(
+            choose_depth_format(true, |_| true),
+            Some(vk::Format::D32_SFLOAT_S8_UINT)
+        );
+        assert_eq!(
+            choose_depth_format(true, |format| format != vk::Format::D32_SFLOAT_S8_UINT),
+            Some(vk::Format::D24_UNORM_S8_UINT)
+        );
+        assert_eq!(
+            choose_depth_format(false, |format| format == vk::Format::D16_UNORM),
+            Some(vk::Format::D16_UNORM)
+        );
+        assert_eq!(
+            choose_depth_format(true, |format| format == vk::Format::D16_UNORM),
+            None
+        );
+    }
+
+    #[test]
+    fn sample_count_clamping() {
+        let supported = vk::SampleCountFlags::TYPE_1
+            | vk::SampleCountFlags::TYPE_2
+            | vk::SampleCountFlags::TYPE_8;
+        assert_eq!(
+            highest_sample_count(supported),
+            vk::SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            highest_sample_count(vk::SampleCountFlags::empty()),
+            vk::SampleCountFlags::TYPE_1
+        );
+        assert_eq!(
+            clamp_sample_count(supported, vk::SampleCountFlags::TYPE_4),
+            vk::SampleCountFlags::TYPE_2
+        );
+        assert_eq!(
+            clamp_sample_count(supported, vk::SampleCountFlags::TYPE_64),
+            vk::SampleCountFlags::TYPE_8
+        );
+        assert_eq!(
+            clamp_sample_count(supported, vk::SampleCountFlags::TYPE_1),
+            vk::SampleCountFlags::TYPE_1
+        );
+    }
+}
@@ -4,12 +4,11 @@ use std::{
     ffi::{CStr, CString},
     fmt,
     os::raw::c_char,
-    sync::Arc,
 };
 
 use ash::vk::{self, PhysicalDevice};
 
-use crate::vk::instance::Instance;
+use crate::vk::physical_device::PhysicalDeviceQueries;
 
 #[derive(Debug)]
 pub struct DeviceExtensionUnavailableError {
@@ -42,7 +41,10 @@ pub struct DeviceExtensionManager {
 }
 
 impl DeviceExtensionManager {
-    pub fn init(instance: &Arc<Instance>, device: PhysicalDevice) -> Result<Self, vk::Result> {
+    pub fn init(
+        instance: &impl PhysicalDeviceQueries,
+        device: PhysicalDevice,
+    ) -> Result<Self, vk::Result> {
         let available = unsafe { instance.enumerate_device_extension_properties(device) }?
             .into_iter()
             .map(|ext| ext.extension_name_as_c_str().unwrap().to_owned())
@@ -77,10 +79,22 @@ impl DeviceExtensionManager {
     pub fn list_names(&self) -> Vec<*const c_char> {
         self.enabled.iter().map(|ext| ext.as_ptr()).collect()
     }
+    pub fn available_names(&self) -> Vec<String> {
+        self.available
+            .iter()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .collect()
+    }
+    pub fn enabled_names(&self) -> Vec<String> {
+        self.enabled
+            .iter()
+            .map(|ext| ext.to_string_lossy().into_owned())
+            .collect()
+    }
 }
 
 pub fn check_extensions<T: AsRef<CStr>>(
-    instance: &Arc<Instance>,
+    instance: &impl PhysicalDeviceQueries,
     device: PhysicalDevice,
     extensions: &[T],
 ) -> Result<(), Box<dyn Error>> {
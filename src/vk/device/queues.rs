@@ -1,14 +1,79 @@
-use std::sync::Arc;
+#[cfg(feature = "metrics")]
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
 
 use ash::vk;
 
 use super::Device;
 
 use crate::vk::{
-    command_buffer::CommandBuffer, error::fatal_vk_error, fence::Fence, semaphore::Semaphore,
-    swapchain::Swapchain,
+    command_buffer::{CommandBuffer, CommandBufferStateError},
+    command_pool::CommandPool,
+    error::{DeviceLostError, check_device_lost},
+    fence::Fence,
+    image::sparse::{SparseBindError, SparseImage, SparseImageBind},
+    semaphore::{Semaphore, WaitSemaphore},
+    swapchain::{PresentStatus, PresentWaitError, Swapchain},
 };
 
+#[derive(Debug, thiserror::Error)]
+pub enum OneTimeSubmitError {
+    #[error(transparent)]
+    State(#[from] CommandBufferStateError),
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SubmitError {
+    #[error("a submit mixes protected and unprotected command buffers")]
+    MixedProtection,
+    #[error("protected command buffers have to be submitted to a protected queue")]
+    QueueNotProtected,
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+}
+
+///
+/// Whether a submit of command buffers with the given protection is a protected submit,
+/// None if they are mixed
+///
+fn submit_protection(protected: impl IntoIterator<Item = bool>) -> Option<bool> {
+    let mut protected = protected.into_iter();
+    let Some(first) = protected.next() else {
+        return Some(false);
+    };
+    protected.all(|other| other == first).then_some(first)
+}
+
+///
+/// Protection of every submit of a batch, see submit_protection. Protected submits
+/// need a protected queue
+///
+fn batch_protection(
+    queue_protected: bool,
+    submits: impl IntoIterator<Item = impl IntoIterator<Item = bool>>,
+) -> Result<Vec<bool>, SubmitError> {
+    let protected = submits
+        .into_iter()
+        .map(|submit| submit_protection(submit).ok_or(SubmitError::MixedProtection))
+        .collect::<Result<Vec<_>, _>>()?;
+    if protected.contains(&true) && !queue_protected {
+        return Err(SubmitError::QueueNotProtected);
+    }
+    Ok(protected)
+}
+
 pub trait QueueFamilySelector: Clone {
     type Q: Queues;
     fn inspect_queue_family(
@@ -23,14 +88,72 @@ pub trait QueueFamilySelector: Clone {
     fn requirements(&self) -> Vec<(u32, Vec<f32>)>;
 
     fn fill_queues(&self, queues_raw: Vec<(u32, Vec<Queue>)>) -> Self::Q;
+
+    fn graphics_family(&self) -> Option<u32> {
+        None
+    }
+
+    fn present_family(&self) -> Option<u32> {
+        None
+    }
+
+    ///
+    /// Families from requirements whose queues are created protected, requires
+    /// DeviceBuilder::protected_memory. DeviceBuilder::build fails with
+    /// ProtectedQueueNotSupportedError for families without QueueFlags::PROTECTED
+    ///
+    fn protected_families(&self) -> Vec<u32> {
+        Vec::new()
+    }
 }
 
 pub trait Queues {}
 
+#[cfg(feature = "metrics")]
+const METRICS_WINDOW: usize = 64;
+
+///
+/// Average of the last METRICS_WINDOW samples
+///
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct RollingAverage {
+    samples: VecDeque<Duration>,
+    sum: Duration,
+}
+
+#[cfg(feature = "metrics")]
+impl RollingAverage {
+    fn push(&mut self, sample: Duration) {
+        if self.samples.len() == METRICS_WINDOW {
+            self.sum -= self.samples.pop_front().unwrap();
+        }
+        self.samples.push_back(sample);
+        self.sum += sample;
+    }
+
+    fn average(&self) -> Duration {
+        if self.samples.is_empty() {
+            return Duration::ZERO;
+        }
+        self.sum / self.samples.len() as u32
+    }
+}
+
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+struct QueueMetrics {
+    submit: Mutex<RollingAverage>,
+    present: Mutex<RollingAverage>,
+}
+
 #[derive(Clone)]
 pub struct Queue {
     device: Arc<Device>,
     queue: Arc<vk::Queue>,
+    protected: bool,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<QueueMetrics>,
 }
 
 impl Queue {
@@ -38,32 +161,181 @@ impl Queue {
         Self {
             device,
             queue: Arc::new(queue),
+            protected: false,
+            #[cfg(feature = "metrics")]
+            metrics: Arc::default(),
         }
     }
 
+    pub(in crate::vk) fn with_protected(mut self, protected: bool) -> Self {
+        self.protected = protected;
+        self
+    }
+
+    ///
+    /// Created with DeviceQueueCreateFlags::PROTECTED, can run protected command buffers
+    ///
+    pub fn is_protected(&self) -> bool {
+        self.protected
+    }
+
+    ///
+    /// Average CPU time spent in vkQueueSubmit over the last submits
+    ///
+    #[cfg(feature = "metrics")]
+    pub fn get_average_submit_time(&self) -> Duration {
+        self.metrics.submit.lock().unwrap().average()
+    }
+
+    ///
+    /// Average CPU time spent in vkQueuePresentKHR over the last presents
+    ///
+    #[cfg(feature = "metrics")]
+    pub fn get_average_present_time(&self) -> Duration {
+        self.metrics.present.lock().unwrap().average()
+    }
+
+    ///
+    /// Submits a single command buffer, a batch with one submit.
+    /// fence is reset and signaled once the command buffer completes.
+    /// A protected command_buffer needs a protected queue
+    ///
     pub fn submit_command_buffer(
         &self,
         command_buffer: Arc<CommandBuffer>,
-        wait: &[&Semaphore],
+        wait: &[WaitSemaphore],
         signal: &[&Semaphore],
-        wait_mask: &[vk::PipelineStageFlags],
         fence: Option<&mut Fence>,
-    ) {
-        let wait: Vec<_> = wait
-            .into_iter()
-            .map(|s| unsafe { s.raw_handle() })
+    ) -> Result<(), SubmitError> {
+        let command_buffers = [command_buffer.as_ref()];
+        let submit = Submit::new(&command_buffers).wait(wait).signal(signal);
+        self.submit_batch(&[submit], fence)
+    }
+
+    ///
+    /// Submits a batch with a single vkQueueSubmit. Vulkan allows one fence per call,
+    /// so fence belongs to the batch as a whole: it is reset and signaled once every
+    /// submit of the batch completes. To know when a single submit completes, let it
+    /// signal a semaphore, or submit it in a batch of its own.
+    /// A submit of command buffers from CommandPool::new_protected is a protected submit,
+    /// it can't mix them with unprotected ones and needs a protected queue
+    ///
+    pub fn submit_batch(
+        &self,
+        submits: &[Submit],
+        fence: Option<&mut Fence>,
+    ) -> Result<(), SubmitError> {
+        let protected = batch_protection(
+            self.protected,
+            submits
+                .iter()
+                .map(|submit| submit.command_buffers.iter().map(|cb| cb.is_protected())),
+        )?;
+        Ok(self.submit_unchecked(submits, &protected, fence)?)
+    }
+
+    fn submit_unchecked(
+        &self,
+        submits: &[Submit],
+        protected: &[bool],
+        fence: Option<&mut Fence>,
+    ) -> Result<(), DeviceLostError> {
+        let raw: Vec<_> = submits
+            .iter()
+            .map(|submit| {
+                let (wait, wait_mask): (Vec<_>, Vec<_>) = submit
+                    .wait
+                    .iter()
+                    .map(|w| (unsafe { w.semaphore.raw_handle() }, w.stage))
+                    .unzip();
+                let signal: Vec<_> = submit
+                    .signal
+                    .iter()
+                    .map(|s| unsafe { s.raw_handle() })
+                    .collect();
+                let command_buffers: Vec<_> = submit
+                    .command_buffers
+                    .iter()
+                    .map(|cb| unsafe { cb.raw_handle() })
+                    .collect();
+                (wait, wait_mask, signal, command_buffers)
+            })
             .collect();
-        let signal: Vec<_> = signal
-            .into_iter()
-            .map(|s| unsafe { s.raw_handle() })
+        let mut protected_infos: Vec<_> = protected
+            .iter()
+            .map(|&protected| vk::ProtectedSubmitInfo::default().protected_submit(protected))
+            .collect();
+        let submit_infos: Vec<_> = raw
+            .iter()
+            .zip(protected_infos.iter_mut())
+            .map(
+                |((wait, wait_mask, signal, command_buffers), protected_info)| {
+                    let submit_info = vk::SubmitInfo::default()
+                        .wait_semaphores(wait)
+                        .signal_semaphores(signal)
+                        .wait_dst_stage_mask(wait_mask)
+                        .command_buffers(command_buffers);
+                    if protected_info.protected_submit == vk::TRUE {
+                        submit_info.push_next(protected_info)
+                    } else {
+                        submit_info
+                    }
+                },
+            )
             .collect();
-        let cbs = [unsafe { command_buffer.raw_handle() }];
 
-        let submit_info = vk::SubmitInfo::default()
+        let fence = if let Some(fence) = fence {
+            unsafe {
+                fence.reset();
+                fence.raw_handle()
+            }
+        } else {
+            vk::Fence::null()
+        };
+
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        let result = unsafe {
+            self.device
+                .raw_handle()
+                .queue_submit(self.queue.as_ref().clone(), &submit_infos, fence)
+        };
+        #[cfg(feature = "metrics")]
+        self.metrics.submit.lock().unwrap().push(start.elapsed());
+
+        check_device_lost("failed to submit queue", result)
+    }
+
+    ///
+    /// Binds or unbinds memory of regions of image. The queue family has to support
+    /// SPARSE_BINDING and image must not be in use by the GPU while memory it used
+    /// is unbound or rebound, as that memory is released right away
+    ///
+    pub fn bind_sparse(
+        &self,
+        image: &SparseImage,
+        binds: &[SparseImageBind],
+        wait: &[&Semaphore],
+        signal: &[&Semaphore],
+        fence: Option<&mut Fence>,
+    ) -> Result<(), SparseBindError> {
+        for bind in binds {
+            image.check_bind(bind)?;
+        }
+        let memory_binds: Vec<_> = binds
+            .iter()
+            .map(|bind| image.make_memory_bind(bind))
+            .collect();
+        let image_binds = [vk::SparseImageMemoryBindInfo::default()
+            .image(unsafe { image.raw_handle() })
+            .binds(&memory_binds)];
+        let wait: Vec<_> = wait.iter().map(|s| unsafe { s.raw_handle() }).collect();
+        let signal: Vec<_> = signal.iter().map(|s| unsafe { s.raw_handle() }).collect();
+
+        let bind_info = vk::BindSparseInfo::default()
             .wait_semaphores(&wait)
-            .signal_semaphores(&signal)
-            .wait_dst_stage_mask(wait_mask)
-            .command_buffers(&cbs);
+            .image_binds(&image_binds)
+            .signal_semaphores(&signal);
 
         let fence = if let Some(fence) = fence {
             unsafe {
@@ -74,15 +346,102 @@ impl Queue {
             vk::Fence::null()
         };
 
-        unsafe {
+        let result = unsafe {
             self.device
                 .raw_handle()
-                .queue_submit(self.queue.as_ref().clone(), &[submit_info], fence)
-                .unwrap_or_else(|error| fatal_vk_error("failed t osubmit queue", error));
+                .queue_bind_sparse(*self.queue, &[bind_info], fence)
+        };
+        check_device_lost("failed to queue_bind_sparse", result)?;
+        image.track_binds(binds);
+        Ok(())
+    }
+
+    ///
+    /// Submits command_buffer with a fence of its own and returns a future resolving when
+    /// the work completes. The command buffer is kept alive until then.
+    /// Dropping the Submission before it resolves blocks until the work completes
+    ///
+    pub fn submit_async(
+        &self,
+        command_buffer: Arc<CommandBuffer>,
+        wait: &[WaitSemaphore],
+        signal: &[&Semaphore],
+    ) -> Result<Submission, SubmitError> {
+        let mut fence = Fence::new(Arc::clone(&self.device));
+        fence.set_name("Queue::submit_async");
+        self.submit_command_buffer(Arc::clone(&command_buffer), wait, signal, Some(&mut fence))?;
+        Ok(Submission {
+            fence,
+            command_buffer: Some(command_buffer),
+        })
+    }
+
+    ///
+    /// Records a ONE_TIME_SUBMIT command buffer from command_pool with f, submits it and
+    /// waits for it to complete, then frees it. For setup work like uploads and transitions.
+    /// f must leave the command buffer recording, e.g. not inside a render pass
+    ///
+    pub async fn one_time_submit(
+        &self,
+        command_pool: &CommandPool,
+        f: impl FnOnce(&mut CommandBuffer),
+    ) -> Result<(), OneTimeSubmitError> {
+        let mut command_buffer = command_pool.allocate_command_buffer();
+        let recorded = command_buffer
+            .begin_with_usage(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)
+            .and_then(|()| {
+                f(&mut command_buffer);
+                command_buffer.end()
+            });
+        if let Err(error) = recorded {
+            command_pool.free_command_buffer(command_buffer);
+            return Err(error.into());
         }
+
+        let command_buffer = Arc::new(command_buffer);
+        let result = async {
+            self.submit_async(Arc::clone(&command_buffer), &[], &[])?
+                .await?;
+            Ok(())
+        }
+        .await;
+        command_pool.free_submitted_command_buffer(command_buffer);
+        result
+    }
+
+    pub fn present(
+        &self,
+        swapchain: &Swapchain,
+        index: u32,
+        wait: &[&Semaphore],
+    ) -> Result<PresentStatus, DeviceLostError> {
+        self.present_impl(swapchain, index, wait, None)
+    }
+
+    ///
+    /// Presents with present_id, which Swapchain::wait_for_present can wait on.
+    /// Ids have to increase with every present to the swapchain
+    ///
+    pub fn present_with_id(
+        &self,
+        swapchain: &Swapchain,
+        index: u32,
+        wait: &[&Semaphore],
+        present_id: u64,
+    ) -> Result<PresentStatus, PresentWaitError> {
+        if !self.device.is_present_wait_enabled() {
+            return Err(PresentWaitError::NotEnabled);
+        }
+        Ok(self.present_impl(swapchain, index, wait, Some(present_id))?)
     }
 
-    pub fn present(&self, swapchain: &Swapchain, index: u32, wait: &[&Semaphore]) {
+    fn present_impl(
+        &self,
+        swapchain: &Swapchain,
+        index: u32,
+        wait: &[&Semaphore],
+        present_id: Option<u64>,
+    ) -> Result<PresentStatus, DeviceLostError> {
         let wait: Vec<_> = wait
             .into_iter()
             .map(|s| unsafe { s.raw_handle() })
@@ -92,16 +451,136 @@ impl Queue {
 
         let index = [index];
 
-        let present_info = vk::PresentInfoKHR::default()
+        let mut present_info = vk::PresentInfoKHR::default()
             .swapchains(&swapchain_khr)
             .wait_semaphores(&wait)
             .image_indices(&index);
 
-        unsafe {
+        let present_ids = present_id.map(|present_id| [present_id]);
+        let mut present_id_info = vk::PresentIdKHR::default();
+        if let Some(present_ids) = &present_ids {
+            present_id_info = present_id_info.present_ids(present_ids);
+            present_info = present_info.push_next(&mut present_id_info);
+        }
+
+        #[cfg(feature = "metrics")]
+        let start = Instant::now();
+        let result = unsafe {
             swapchain
                 .device_handle()
                 .queue_present(self.queue.as_ref().clone(), &present_info)
-                .unwrap_or_else(|error| fatal_vk_error("failed t osubmit queue", error));
+        };
+        #[cfg(feature = "metrics")]
+        self.metrics.present.lock().unwrap().push(start.elapsed());
+
+        match result {
+            Ok(false) => Ok(PresentStatus::Optimal),
+            Ok(true) => Ok(PresentStatus::Suboptimal),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(PresentStatus::OutOfDate),
+            Err(error) => check_device_lost("failed to present queue", Err(error)),
+        }
+    }
+}
+
+///
+/// Command buffers submitted together with the semaphores they wait on and signal,
+/// one element of a Queue::submit_batch
+///
+pub struct Submit<'a> {
+    command_buffers: &'a [&'a CommandBuffer],
+    wait: &'a [WaitSemaphore<'a>],
+    signal: &'a [&'a Semaphore],
+}
+
+impl<'a> Submit<'a> {
+    pub fn new(command_buffers: &'a [&'a CommandBuffer]) -> Self {
+        Self {
+            command_buffers,
+            wait: &[],
+            signal: &[],
+        }
+    }
+
+    pub fn wait(mut self, wait: &'a [WaitSemaphore<'a>]) -> Self {
+        self.wait = wait;
+        self
+    }
+
+    pub fn signal(mut self, signal: &'a [&'a Semaphore]) -> Self {
+        self.signal = signal;
+        self
+    }
+}
+
+///
+/// Work submitted with Queue::submit_async
+///
+pub struct Submission {
+    fence: Fence,
+    command_buffer: Option<Arc<CommandBuffer>>,
+}
+
+impl Future for Submission {
+    type Output = Result<(), DeviceLostError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let result = Pin::new(&mut self.fence).poll(cx);
+        if result.is_ready() {
+            self.command_buffer = None;
+        }
+        result
+    }
+}
+
+impl Drop for Submission {
+    fn drop(&mut self) {
+        if self.command_buffer.is_some()
+            && let Err(error) = self.fence.wait_blocking()
+        {
+            log::warn!("dropped Submission finished waiting with an error: {error}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn rolling_average_window() {
+        let mut average = RollingAverage::default();
+        assert_eq!(average.average(), Duration::ZERO);
+        for _ in 0..METRICS_WINDOW {
+            average.push(Duration::from_millis(10));
+        }
+        for _ in 0..METRICS_WINDOW {
+            average.push(Duration::from_millis(2));
         }
+        assert_eq!(average.average(), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn protection_of_submits() {
+        assert_eq!(submit_protection([]), Some(false));
+        assert_eq!(submit_protection([false, false]), Some(false));
+        assert_eq!(submit_protection([true, true]), Some(true));
+        assert_eq!(submit_protection([true, false]), None);
+    }
+
+    #[test]
+    fn protection_of_batches() {
+        assert!(matches!(
+            batch_protection(true, [vec![true, false]]),
+            Err(SubmitError::MixedProtection)
+        ));
+        assert!(matches!(
+            batch_protection(false, [vec![false], vec![true]]),
+            Err(SubmitError::QueueNotProtected)
+        ));
+        assert_eq!(batch_protection(false, [vec![false]]).unwrap(), vec![false]);
+        assert_eq!(
+            batch_protection(true, [vec![true], vec![false]]).unwrap(),
+            vec![true, false]
+        );
     }
 }
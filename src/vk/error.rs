@@ -116,10 +116,45 @@ impl From<vk::Result> for VulkanResult {
     }
 }
 
+#[derive(Debug, thiserror::Error)]
+#[error("the logical device has been lost")]
+pub struct DeviceLostError;
+
+///
+/// Turns ERROR_DEVICE_LOST into DeviceLostError, any other error is fatal
+///
+pub fn check_device_lost<T>(
+    msg: &str,
+    result: Result<T, vk::Result>,
+) -> Result<T, DeviceLostError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(vk::Result::ERROR_DEVICE_LOST) => {
+            log::error!("{}: device lost", msg);
+            Err(DeviceLostError)
+        }
+        Err(error) => fatal_vk_error(msg, error),
+    }
+}
+
+///
+/// Panic payload of fatal_vk_error, can be inspected with
+/// catch_unwind and downcast_ref
+///
+#[derive(Debug, thiserror::Error)]
+#[error("fatal: {msg}: {result} ({})", result.doc())]
+pub struct FatalVulkanError {
+    pub msg: String,
+    pub result: VulkanResult,
+}
+
 pub fn fatal_vk_error<T: Into<VulkanResult>>(msg: &str, error: T) -> ! {
-    let e = error.into();
-    log::error!("fatal: {}: {} ({})", msg, e, e.doc());
-    panic!("fatal: {}: {} ({})", msg, e, e.doc());
+    let error = FatalVulkanError {
+        msg: msg.to_owned(),
+        result: error.into(),
+    };
+    log::error!("{}", error);
+    std::panic::panic_any(error);
 }
 
 #[cfg(test)]
@@ -157,6 +192,17 @@ mod test {
         fatal_vk_error("ohno", vulkan_result)
     }
 
+    #[test]
+    fn fatal_payload() {
+        let payload = std::panic::catch_unwind(|| {
+            fatal_vk_error("ohno", vk::Result::ERROR_DEVICE_LOST)
+        })
+        .unwrap_err();
+        let error = payload.downcast_ref::<FatalVulkanError>().unwrap();
+        assert_eq!(error.msg, "ohno");
+        assert_eq!(error.result, VulkanResult::ErrorDeviceLost);
+    }
+
     #[test]
     #[should_panic]
     fn fatal_from_vk_result() {
@@ -46,6 +46,12 @@ impl ExtensionManager {
                 .collect(),
         )
     }
+    pub fn available_names(&self) -> Vec<String> {
+        self.available
+            .iter()
+            .map(|e| e.name.to_string_lossy().into_owned())
+            .collect()
+    }
     pub fn check_extensions(
         &self,
         extensions: &[String],
@@ -1,63 +1,183 @@
 use core::task::Waker;
-use std::mem;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::task::{Context, Poll};
-use std::thread::{self, JoinHandle};
+use std::thread;
 
 use ash::vk;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use super::device::Device;
-use super::error::fatal_vk_error;
+use super::error::{DeviceLostError, check_device_lost, fatal_vk_error};
 
 const FENCE_POLL_PERIOD: Duration = Duration::from_micros(100000);
 
+#[derive(Debug, thiserror::Error)]
+pub enum FenceWaitError {
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+    #[error("the fence was not signaled before the deadline")]
+    Timeout,
+}
+
+///
+/// State shared by a waiting Fence and its poller thread. deadline can be changed while
+/// the thread runs, it is read again on every poll period
+///
+struct Poller {
+    state: Mutex<PollerState>,
+    finished: Condvar,
+}
+
+struct PollerState {
+    deadline: Option<Instant>,
+    waker: Option<Waker>,
+    result: Option<Result<(), FenceWaitError>>,
+}
+
+impl Poller {
+    fn new(deadline: Option<Instant>, waker: Waker) -> Self {
+        Self {
+            state: Mutex::new(PollerState {
+                deadline,
+                waker: Some(waker),
+                result: None,
+            }),
+            finished: Condvar::new(),
+        }
+    }
+
+    fn finish(&self, state: &mut PollerState, result: Result<(), FenceWaitError>) {
+        state.result = Some(result);
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+        self.finished.notify_all();
+    }
+}
+
 enum FenceState {
     Ready(vk::Fence),
-    Waiting(JoinHandle<vk::Fence>),
+    Waiting(vk::Fence, Arc<Poller>),
 }
 
 use FenceState::{Ready, Waiting};
 
 impl FenceState {
-    fn start_wait(&mut self, device: Arc<Device>, waker: Waker) {
+    fn start_wait(
+        &mut self,
+        device: Arc<Device>,
+        waker: Waker,
+        deadline: Option<Instant>,
+        thread_name: String,
+    ) {
         let Ready(fence) = *self else {
             panic!("Tried starting waiting for a fence that is already being waited for!");
         };
-        *self = Waiting(thread::spawn(move || {
-            loop {
-                let code = unsafe {
-                    device.raw_handle().wait_for_fences(
-                        &[fence],
-                        true,
-                        FENCE_POLL_PERIOD.as_nanos().try_into().unwrap(),
-                    )
-                };
-                if check_shutdown() {
-                    break;
-                }
-                let Err(error) = code else {
-                    break;
-                };
-                if error != vk::Result::TIMEOUT {
-                    fatal_vk_error("failed to wait_for_fences", error);
+        let poller = Arc::new(Poller::new(deadline, waker));
+        let thread_poller = Arc::clone(&poller);
+        let builder = thread::Builder::new().name(thread_name);
+        builder
+            .spawn(move || {
+                let poller = thread_poller;
+                loop {
+                    let deadline = poller.state.lock().unwrap().deadline;
+                    let timeout = match deadline {
+                        Some(deadline) => FENCE_POLL_PERIOD
+                            .min(deadline.saturating_duration_since(Instant::now())),
+                        None => FENCE_POLL_PERIOD,
+                    };
+                    let code = unsafe {
+                        device.raw_handle().wait_for_fences(
+                            &[fence],
+                            true,
+                            timeout.as_nanos().try_into().unwrap(),
+                        )
+                    };
+                    let result = match code {
+                        _ if check_shutdown() => Some(Ok(())),
+                        Ok(()) => Some(Ok(())),
+                        Err(vk::Result::ERROR_DEVICE_LOST) => {
+                            log::error!("failed to wait_for_fences: device lost");
+                            Some(Err(DeviceLostError.into()))
+                        }
+                        Err(vk::Result::TIMEOUT) => None,
+                        Err(error) => fatal_vk_error("failed to wait_for_fences", error),
+                    };
+                    // The deadline is checked under the lock, it may have been moved meanwhile
+                    let mut state = poller.state.lock().unwrap();
+                    let result = result.or_else(|| {
+                        state
+                            .deadline
+                            .is_some_and(|deadline| Instant::now() >= deadline)
+                            .then_some(Err(FenceWaitError::Timeout))
+                    });
+                    if let Some(result) = result {
+                        poller.finish(&mut state, result);
+                        break;
+                    }
                 }
+            })
+            .expect("failed to spawn a fence poller thread");
+        *self = Waiting(fence, poller);
+    }
+
+    ///
+    /// Blocks until the poller thread finishes. Its deadline is dropped first, so this
+    /// only times out if the thread already has
+    ///
+    fn wait(&mut self) -> Result<(), FenceWaitError> {
+        let Waiting(fence, poller) = &*self else {
+            return Ok(());
+        };
+        let fence = *fence;
+        let mut state = poller.state.lock().unwrap();
+        state.deadline = None;
+        let result = loop {
+            match state.result.take() {
+                Some(result) => break result,
+                None => state = poller.finished.wait(state).unwrap(),
             }
-            waker.wake();
-            fence
-        }))
+        };
+        drop(state);
+        *self = Ready(fence);
+        result
     }
 
-    fn wait(&mut self) {
-        if let Ready(_) = *self {
-            return;
-        }
-        let s = mem::replace(self, Ready(vk::Fence::null()));
-        let Waiting(handle) = s else {
-            unreachable!();
+    ///
+    /// Result of the poller thread for a wait with deadline without blocking, otherwise
+    /// waker is woken once it is there. A running thread adopts deadline, e.g. when
+    /// a fence awaited without deadline is then awaited with one. A thread that timed out
+    /// for another deadline, e.g. of a dropped FenceDeadline, gives no result for this
+    /// wait: Ready(None) is returned then and the wait has to start over
+    ///
+    fn poll(
+        &mut self,
+        waker: &Waker,
+        deadline: Option<Instant>,
+    ) -> Poll<Option<Result<(), FenceWaitError>>> {
+        let Waiting(fence, poller) = &*self else {
+            return Poll::Ready(Some(Ok(())));
+        };
+        let fence = *fence;
+        let mut state = poller.state.lock().unwrap();
+        let Some(result) = state.result.take() else {
+            state.deadline = deadline;
+            state.waker = Some(waker.clone());
+            return Poll::Pending;
         };
-        *self = Ready(handle.join().unwrap());
+        let thread_deadline = state.deadline;
+        drop(state);
+        *self = Ready(fence);
+        Poll::Ready(match result {
+            Err(FenceWaitError::Timeout)
+                if thread_deadline != deadline
+                    && deadline.is_none_or(|deadline| Instant::now() < deadline) =>
+            {
+                None
+            }
+            result => Some(result),
+        })
     }
 }
 
@@ -99,6 +219,18 @@ impl Fence {
         }
     }
 
+    ///
+    /// Creates an unsignaled fence meant to be reused across submits. Awaiting resolves
+    /// when the fence is signaled or the device is lost, and always leaves it Ready
+    /// (the poller thread is joined and gives the vk::Fence back), so it can be reset
+    /// for the next submit without recreating it
+    ///
+    pub fn oneshot(device: Arc<Device>) -> Self {
+        let mut fence = Self::new(device);
+        fence.set_name("oneshot");
+        fence
+    }
+
     pub fn reset(&mut self) {
         unsafe {
             let Ready(fence) = self.fence else {
@@ -119,6 +251,90 @@ impl Fence {
         }
     }
 
+    ///
+    /// Drops the fence even if it is being waited for, waiting for the poller thread first.
+    /// Blocks until the fence is signaled, the device is lost or fence_shutdown is called,
+    /// so the device should be idle before abandoning fences of submitted work.
+    /// A fence whose wait already timed out may still be signaled by the GPU,
+    /// it is leaked instead of destroyed
+    ///
+    pub fn abandon(mut self) {
+        match self.fence.wait() {
+            Ok(()) => (),
+            Err(FenceWaitError::Timeout) => {
+                log::warn!("abandoned fence timed out and may still be in use, leaking it");
+                self.fence = Ready(vk::Fence::null());
+            }
+            Err(error) => log::warn!("abandoned fence finished waiting with an error: {error}"),
+        }
+    }
+
+    ///
+    /// Blocks the thread until the fence is signaled, waiting for the poller thread first
+    ///
+    pub(in crate::vk) fn wait_blocking(&mut self) -> Result<(), DeviceLostError> {
+        if let Err(FenceWaitError::DeviceLost(error)) = self.fence.wait() {
+            return Err(error);
+        }
+        let fence = unsafe { self.raw_handle() };
+        check_device_lost("failed to wait_for_fences", unsafe {
+            self.device
+                .raw_handle()
+                .wait_for_fences(&[fence], true, u64::MAX)
+        })
+    }
+
+    ///
+    /// Waits like awaiting the fence itself, but resolves to FenceWaitError::Timeout
+    /// if it is not signaled by deadline. The fence is Ready afterwards either way,
+    /// so a timed out fence can be awaited again
+    ///
+    pub fn with_deadline(&mut self, deadline: Instant) -> FenceDeadline<'_> {
+        FenceDeadline {
+            fence: self,
+            deadline,
+        }
+    }
+
+    fn poll_wait(
+        &mut self,
+        cx: &mut Context<'_>,
+        deadline: Option<Instant>,
+    ) -> Poll<Result<(), FenceWaitError>> {
+        if let Waiting(..) = self.fence {
+            let Poll::Ready(result) = self.fence.poll(cx.waker(), deadline) else {
+                return Poll::Pending;
+            };
+            if check_shutdown() {
+                self.polled_after_shutdown();
+            }
+            if let Some(result) = result {
+                return Poll::Ready(result);
+            }
+        }
+        let Ready(fence) = self.fence else {
+            unreachable!("a finished wait leaves the fence Ready");
+        };
+        match unsafe { self.device.raw_handle().get_fence_status(fence) } {
+            Ok(true) => Poll::Ready(Ok(())),
+            Ok(false) if deadline.is_some_and(|deadline| Instant::now() >= deadline) => {
+                Poll::Ready(Err(FenceWaitError::Timeout))
+            }
+            Ok(false) => {
+                let device_clone = Arc::clone(&self.device);
+                let thread_name = self.poller_thread_name();
+                self.fence
+                    .start_wait(device_clone, cx.waker().clone(), deadline, thread_name);
+                Poll::Pending
+            }
+            Err(vk::Result::ERROR_DEVICE_LOST) => {
+                log::error!("failed to get_fence_status: device lost");
+                Poll::Ready(Err(DeviceLostError.into()))
+            }
+            Err(error) => fatal_vk_error("failed to get_fence_status", error),
+        }
+    }
+
     #[cfg(debug_assertions)]
     pub fn set_name(&mut self, name: &str) {
         self.name = name.to_owned();
@@ -129,33 +345,50 @@ impl Fence {
         eprintln!("Fence \"{}\" was polled after shutdown!", self.name);
     }
 
+    ///
+    /// Name of the thread waiting for the fence, includes the fence name if it is set
+    ///
+    #[cfg(debug_assertions)]
+    fn poller_thread_name(&self) -> String {
+        if self.name.is_empty() {
+            "fence poller".to_owned()
+        } else {
+            format!("fence poller \"{}\"", self.name)
+        }
+    }
+
     #[cfg(not(debug_assertions))]
     pub const fn set_name(&mut self, _: &str) {}
 
+    #[cfg(not(debug_assertions))]
+    fn poller_thread_name(&self) -> String {
+        "fence poller".to_owned()
+    }
+
     #[cfg(not(debug_assertions))]
     pub fn polled_after_shutdown(&self) {}
 }
 
 impl Future for Fence {
-    type Output = ();
+    type Output = Result<(), DeviceLostError>;
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let Ready(fence) = self.fence else {
-            self.fence.wait();
-            if check_shutdown() {
-                self.polled_after_shutdown();
-            }
+        self.poll_wait(cx, None).map_err(|error| match error {
+            FenceWaitError::DeviceLost(error) => error,
+            FenceWaitError::Timeout => unreachable!("fence without a deadline timed out"),
+        })
+    }
+}
 
-            return Poll::Ready(());
-        };
-        match unsafe { self.device.raw_handle().get_fence_status(fence) } {
-            Ok(true) => Poll::Ready(()),
-            Ok(false) => {
-                let device_clone = Arc::clone(&self.device);
-                self.fence.start_wait(device_clone, cx.waker().clone());
-                Poll::Pending
-            }
-            Err(error) => fatal_vk_error("failed to get_fence_status", error),
-        }
+pub struct FenceDeadline<'a> {
+    fence: &'a mut Fence,
+    deadline: Instant,
+}
+
+impl Future for FenceDeadline<'_> {
+    type Output = Result<(), FenceWaitError>;
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let deadline = self.deadline;
+        self.fence.poll_wait(cx, Some(deadline))
     }
 }
 
@@ -215,3 +448,55 @@ const fn check_shutdown() -> bool {
 //                     println!("drop");
 //                 });
 //
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn timed_out_wait(deadline: Instant) -> FenceState {
+        let poller = Poller::new(Some(deadline), Waker::noop().clone());
+        poller.state.lock().unwrap().result = Some(Err(FenceWaitError::Timeout));
+        Waiting(vk::Fence::null(), Arc::new(poller))
+    }
+
+    #[test]
+    fn dropped_deadline_wait() {
+        // A FenceDeadline was dropped while Pending, then the fence itself is awaited
+        let waker = Waker::noop();
+        let mut state = timed_out_wait(Instant::now());
+        assert!(matches!(state.poll(waker, None), Poll::Ready(None)));
+        assert!(matches!(state, Ready(_)));
+
+        let deadline = Instant::now();
+        let mut state = timed_out_wait(deadline);
+        assert!(matches!(
+            state.poll(waker, Some(deadline)),
+            Poll::Ready(Some(Err(FenceWaitError::Timeout)))
+        ));
+
+        let mut state = timed_out_wait(deadline);
+        let later = Instant::now() + Duration::from_secs(60);
+        assert!(matches!(state.poll(waker, Some(later)), Poll::Ready(None)));
+    }
+
+    #[test]
+    fn deadline_on_wait_without_deadline() {
+        // The fence is awaited, then awaited again with a deadline while still waiting
+        let waker = Waker::noop();
+        let poller = Arc::new(Poller::new(None, waker.clone()));
+        let mut state = Waiting(vk::Fence::null(), Arc::clone(&poller));
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert!(state.poll(waker, Some(deadline)).is_pending());
+        assert_eq!(poller.state.lock().unwrap().deadline, Some(deadline));
+
+        // The thread times out for the deadline it adopted
+        poller.finish(
+            &mut poller.state.lock().unwrap(),
+            Err(FenceWaitError::Timeout),
+        );
+        assert!(matches!(
+            state.poll(waker, Some(deadline)),
+            Poll::Ready(Some(Err(FenceWaitError::Timeout)))
+        ));
+    }
+}
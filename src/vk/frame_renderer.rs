@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{
+    command_buffer::CommandBuffer,
+    device::{
+        Device,
+        queues::{Queue, SubmitError},
+    },
+    error::DeviceLostError,
+    fence::Fence,
+    selectors::DrawQueues,
+    semaphore::{Semaphore, WaitSemaphore},
+    swapchain::{AcquireStatus, PresentStatus, Swapchain},
+};
+
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+///
+/// What the last frame drawn by FrameRenderer::draw_frame submitted
+///
+#[cfg(feature = "stats")]
+#[derive(Default, Debug, Clone, Copy)]
+pub struct FrameStats {
+    pub command_buffers: u32,
+    pub draw_calls: u32,
+    pub pipeline_binds: u32,
+    pub present_mode: vk::PresentModeKHR,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("frames in flight must be in 1..={image_count}, got {frames_in_flight}")]
+pub struct FramesInFlightError {
+    frames_in_flight: usize,
+    image_count: usize,
+}
+
+///
+/// Sync objects of a single frame in flight.
+/// command_buffer keeps the submitted command buffer alive until in_flight is signaled
+///
+struct Frame {
+    image_available: Semaphore,
+    render_finished: Semaphore,
+    in_flight: Fence,
+    command_buffer: Option<Arc<CommandBuffer>>,
+}
+
+impl Frame {
+    fn new(device: Arc<Device>) -> Self {
+        let mut in_flight = Fence::new(Arc::clone(&device));
+        in_flight.set_name("FrameRenderer in_flight");
+        Self {
+            image_available: Semaphore::new(Arc::clone(&device)),
+            render_finished: Semaphore::new(device),
+            in_flight,
+            command_buffer: None,
+        }
+    }
+
+    async fn wait(&mut self) -> Result<(), DeviceLostError> {
+        if self.command_buffer.is_some() {
+            (&mut self.in_flight).await?;
+            self.command_buffer = None;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Acquire -> render -> present loop over graphics and present queues.
+/// The queues may belong to different families: ordering between them is expressed
+/// only with semaphores (graphics submit signals render_finished, present waits on it).
+/// No ownership transfer is needed for swapchain images, as SwapchainManager creates
+/// them with CONCURRENT sharing when the families differ.
+/// Up to frames_in_flight frames are recorded ahead of the GPU, each with its own sync objects
+///
+pub struct FrameRenderer {
+    device: Arc<Device>,
+    swapchain: Arc<Swapchain>,
+    graphics: Queue,
+    present: Queue,
+    frames: Vec<Frame>,
+    image_frames: Vec<Option<usize>>,
+    frame: usize,
+    #[cfg(feature = "stats")]
+    stats: FrameStats,
+}
+
+impl FrameRenderer {
+    ///
+    /// frames_in_flight must be at least 1 and at most the swapchain image count,
+    /// DEFAULT_FRAMES_IN_FLIGHT is a good default
+    ///
+    pub fn new(
+        device: Arc<Device>,
+        swapchain: Arc<Swapchain>,
+        queues: &DrawQueues,
+        frames_in_flight: usize,
+    ) -> Result<Self, FramesInFlightError> {
+        let image_count = swapchain.get_image_count();
+        if frames_in_flight == 0 || frames_in_flight > image_count {
+            return Err(FramesInFlightError {
+                frames_in_flight,
+                image_count,
+            });
+        }
+        Ok(Self {
+            device: Arc::clone(&device),
+            swapchain,
+            graphics: queues.graphics.clone(),
+            present: queues.present.clone(),
+            frames: (0..frames_in_flight)
+                .map(|_| Frame::new(Arc::clone(&device)))
+                .collect(),
+            image_frames: vec![None; image_count],
+            frame: 0,
+            #[cfg(feature = "stats")]
+            stats: FrameStats::default(),
+        })
+    }
+
+    pub fn get_frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    ///
+    /// Stats of the last frame that was submitted, skipped frames are not counted
+    ///
+    #[cfg(feature = "stats")]
+    pub fn get_frame_stats(&self) -> FrameStats {
+        self.stats
+    }
+
+    ///
+    /// Waits for every frame in flight and continues with swapchain,
+    /// e.g. after draw_frame returned PresentStatus::OutOfDate and the swapchain was recreated
+    /// with SwapchainManager::recreate_swapchain.
+    /// Frames in flight are reduced to the image count of swapchain if it has fewer images
+    ///
+    pub async fn set_swapchain(
+        &mut self,
+        swapchain: Arc<Swapchain>,
+    ) -> Result<(), DeviceLostError> {
+        for frame in self.frames.iter_mut() {
+            frame.wait().await?;
+        }
+        let image_count = swapchain.get_image_count();
+        if self.frames.len() > image_count {
+            log::warn!(
+                "Reducing frames in flight from {} to the swapchain image count {}",
+                self.frames.len(),
+                image_count
+            );
+            for frame in self.frames.drain(image_count..) {
+                frame.in_flight.abandon();
+            }
+        }
+        self.swapchain = swapchain;
+        self.image_frames = vec![None; image_count];
+        self.frame = 0;
+        Ok(())
+    }
+
+    ///
+    /// Waits for the frame submitted frames_in_flight draws ago, acquires an image and
+    /// submits the command buffer returned by command_buffer for that image index,
+    /// then presents it.
+    /// If the swapchain is out of date at acquire, the frame is skipped without calling
+    /// command_buffer. On PresentStatus::OutOfDate the swapchain has to be recreated and
+    /// passed to set_swapchain before drawing again.
+    /// On SubmitError::DeviceLost the whole Vulkan stack has to be recreated
+    ///
+    pub async fn draw_frame(
+        &mut self,
+        command_buffer: impl FnOnce(u32) -> Arc<CommandBuffer>,
+    ) -> Result<PresentStatus, SubmitError> {
+        let frame = self.frame;
+        self.frames[frame].wait().await?;
+
+        let acquire_status = self
+            .swapchain
+            .acquire_next_image(Some(&self.frames[frame].image_available))
+            .await?;
+        let index = match acquire_status {
+            AcquireStatus::Optimal(index) | AcquireStatus::Suboptimal(index) => index,
+            AcquireStatus::OutOfDate => return Ok(PresentStatus::OutOfDate),
+        };
+
+        // The image may still be rendered to by another frame in flight
+        if let Some(previous) = self.image_frames[index as usize] {
+            self.frames[previous].wait().await?;
+        }
+
+        let command_buffer = command_buffer(index);
+        #[cfg(feature = "stats")]
+        {
+            let recording = command_buffer.get_stats();
+            self.stats = FrameStats {
+                command_buffers: 1,
+                draw_calls: recording.draw_calls,
+                pipeline_binds: recording.pipeline_binds,
+                present_mode: self.swapchain.get_present_mode(),
+            };
+        }
+        let current = &mut self.frames[frame];
+        self.graphics.submit_command_buffer(
+            Arc::clone(&command_buffer),
+            &[WaitSemaphore::new(
+                &current.image_available,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            )],
+            &[&current.render_finished],
+            Some(&mut current.in_flight),
+        )?;
+        current.command_buffer = Some(command_buffer);
+        self.image_frames[index as usize] = Some(frame);
+
+        let present_status =
+            self.present
+                .present(&self.swapchain, index, &[&current.render_finished])?;
+
+        self.frame = (frame + 1) % self.frames.len();
+
+        Ok(acquire_status.get_present_status().max(present_status))
+    }
+}
+
+///
+/// Waits for the device to become idle, so that fences of every frame in flight are
+/// signaled, including ones left being waited for by a dropped draw_frame future
+///
+impl Drop for FrameRenderer {
+    fn drop(&mut self) {
+        self.device.wait_idle();
+        for frame in self.frames.drain(..) {
+            frame.in_flight.abandon();
+        }
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::Arc;
 
 use ash::vk;
 
-use super::{device::Device, pipeline::render_pass::RenderPass};
+use super::{device::Device, pipeline::render_pass::RenderPass, swapchain::Swapchain};
 
 pub struct Framebuffer {
     device: Arc<Device>,
@@ -35,6 +35,54 @@ impl Framebuffer {
     }
 }
 
+///
+/// Framebuffers of a swapchain indexed by the image index returned by acquire_next_image
+///
+pub struct SwapchainFramebuffers {
+    swapchain_generation: u64,
+    framebuffers: Vec<Arc<Framebuffer>>,
+}
+
+impl SwapchainFramebuffers {
+    pub(in crate::vk) fn new(
+        swapchain_generation: u64,
+        framebuffers: Vec<Arc<Framebuffer>>,
+    ) -> Self {
+        Self {
+            swapchain_generation,
+            framebuffers,
+        }
+    }
+
+    ///
+    /// Panics if index is not an image index of the swapchain
+    ///
+    pub fn framebuffer_for_image(&self, index: u32) -> &Arc<Framebuffer> {
+        &self.framebuffers[index as usize]
+    }
+
+    pub fn len(&self) -> usize {
+        self.framebuffers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.framebuffers.is_empty()
+    }
+
+    ///
+    /// False once the framebuffers are outlived by a recreation of the swapchain
+    ///
+    pub fn is_for(&self, swapchain: &Swapchain) -> bool {
+        self.swapchain_generation == swapchain.get_generation()
+    }
+}
+
+impl Default for SwapchainFramebuffers {
+    fn default() -> Self {
+        Self::new(0, Vec::new())
+    }
+}
+
 impl Drop for Framebuffer {
     fn drop(&mut self) {
         unsafe {
@@ -0,0 +1,879 @@
+pub mod sparse;
+
+use std::sync::{Arc, Weak};
+
+use ash::vk;
+
+use super::{
+    buffer::Buffer,
+    command_buffer::{CommandBuffer, CommandBufferStateError, OwnershipTransfer},
+    command_pool::CommandPool,
+    device::{
+        Device,
+        queues::{Queue, SubmitError},
+    },
+    error::{DeviceLostError, fatal_vk_error},
+    fence::Fence,
+    memory::{Allocation, AllocationError, Allocator},
+    pipeline::render_pass::aspect_mask,
+    semaphore::{Semaphore, WaitSemaphore},
+};
+
+#[derive(Debug, thiserror::Error)]
+#[error("format {0:?} can not be read back")]
+pub struct UnsupportedReadbackFormatError(pub vk::Format);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadbackError {
+    #[error(transparent)]
+    UnsupportedFormat(#[from] UnsupportedReadbackFormatError),
+    #[error(transparent)]
+    Allocation(#[from] AllocationError),
+    #[error(transparent)]
+    State(#[from] CommandBufferStateError),
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("format {0:?} can not be uploaded")]
+    UnsupportedFormat(vk::Format),
+    #[error("expected {expected} bytes of texels, got {actual}")]
+    SizeMismatch { expected: u64, actual: usize },
+    #[error(transparent)]
+    Allocation(#[from] AllocationError),
+    #[error(transparent)]
+    State(#[from] CommandBufferStateError),
+    #[error(transparent)]
+    Submit(#[from] SubmitError),
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+}
+
+///
+/// Queues of an upload: the copy runs on transfer, then ownership of the image moves
+/// to the graphics queue family that samples it. Both families may be the same,
+/// the ownership transfer is skipped then. The pools have to belong to the respective
+/// families
+///
+pub struct UploadQueues<'a> {
+    pub transfer: &'a Queue,
+    pub transfer_pool: &'a CommandPool,
+    pub graphics: &'a Queue,
+    pub graphics_pool: &'a CommandPool,
+    pub families: OwnershipTransfer,
+}
+
+///
+/// Byte order of 8-bit four channel formats
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgba,
+    Bgra,
+}
+
+impl ChannelOrder {
+    pub fn from_format(format: vk::Format) -> Option<Self> {
+        match format {
+            vk::Format::R8G8B8A8_UNORM
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::R8G8B8A8_SNORM
+            | vk::Format::R8G8B8A8_UINT
+            | vk::Format::R8G8B8A8_SINT => Some(Self::Rgba),
+            vk::Format::B8G8R8A8_UNORM
+            | vk::Format::B8G8R8A8_SRGB
+            | vk::Format::B8G8R8A8_SNORM
+            | vk::Format::B8G8R8A8_UINT
+            | vk::Format::B8G8R8A8_SINT => Some(Self::Bgra),
+            _ => None,
+        }
+    }
+}
+
+///
+/// Tightly packed texels of an image, bytes are laid out as format says
+///
+#[derive(Debug, Clone)]
+pub struct Readback {
+    pub bytes: Vec<u8>,
+    pub format: vk::Format,
+    pub extent: vk::Extent2D,
+}
+
+impl Readback {
+    ///
+    /// Swaps the red and blue channels if needed, e.g. to get RGBA bytes for a PNG
+    /// out of a BGRA image. format is updated accordingly
+    ///
+    pub fn to_channel_order(
+        &mut self,
+        order: ChannelOrder,
+    ) -> Result<(), UnsupportedReadbackFormatError> {
+        let current = ChannelOrder::from_format(self.format)
+            .ok_or(UnsupportedReadbackFormatError(self.format))?;
+        if current == order {
+            return Ok(());
+        }
+        for texel in self.bytes.chunks_exact_mut(4) {
+            texel.swap(0, 2);
+        }
+        self.format = swapped_channel_format(self.format);
+        Ok(())
+    }
+}
+
+fn swapped_channel_format(format: vk::Format) -> vk::Format {
+    match format {
+        vk::Format::R8G8B8A8_UNORM => vk::Format::B8G8R8A8_UNORM,
+        vk::Format::R8G8B8A8_SRGB => vk::Format::B8G8R8A8_SRGB,
+        vk::Format::R8G8B8A8_SNORM => vk::Format::B8G8R8A8_SNORM,
+        vk::Format::R8G8B8A8_UINT => vk::Format::B8G8R8A8_UINT,
+        vk::Format::R8G8B8A8_SINT => vk::Format::B8G8R8A8_SINT,
+        vk::Format::B8G8R8A8_UNORM => vk::Format::R8G8B8A8_UNORM,
+        vk::Format::B8G8R8A8_SRGB => vk::Format::R8G8B8A8_SRGB,
+        vk::Format::B8G8R8A8_SNORM => vk::Format::R8G8B8A8_SNORM,
+        vk::Format::B8G8R8A8_UINT => vk::Format::R8G8B8A8_UINT,
+        vk::Format::B8G8R8A8_SINT => vk::Format::R8G8B8A8_SINT,
+        format => format,
+    }
+}
+
+///
+/// Size of the tightly packed texels of a single-level image
+///
+fn image_size(format: vk::Format, extent: vk::Extent2D) -> Option<u64> {
+    texel_size(format).map(|size| size * extent.width as u64 * extent.height as u64)
+}
+
+///
+/// Size of a texel of the color formats that can be read back
+///
+fn texel_size(format: vk::Format) -> Option<u64> {
+    if ChannelOrder::from_format(format).is_some() {
+        return Some(4);
+    }
+    match format {
+        vk::Format::R8_UNORM => Some(1),
+        vk::Format::R8G8_UNORM => Some(2),
+        vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32
+        | vk::Format::R32_SFLOAT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT => Some(8),
+        vk::Format::R32G32B32A32_SFLOAT => Some(16),
+        _ => None,
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageViewError {
+    #[error("view format {format:?} differs from the image format {image_format:?}")]
+    FormatMismatch {
+        format: vk::Format,
+        image_format: vk::Format,
+    },
+    #[error("mip levels {base}..{base}+{count} exceed the {levels} levels of the image")]
+    MipLevelsOutOfRange { base: u32, count: u32, levels: u32 },
+    #[error("array layers {base}..{base}+{count} exceed the {layers} layers of the image")]
+    ArrayLayersOutOfRange { base: u32, count: u32, layers: u32 },
+    #[error("view type {view_type:?} can't have {count} array layers")]
+    InvalidLayerCount {
+        view_type: vk::ImageViewType,
+        count: u32,
+    },
+    #[error("view type {0:?} can't view a 2D image")]
+    InvalidViewType(vk::ImageViewType),
+    #[error("aspects {aspects:?} are not a subset of the image aspects {image_aspects:?}")]
+    InvalidAspects {
+        aspects: vk::ImageAspectFlags,
+        image_aspects: vk::ImageAspectFlags,
+    },
+}
+
+///
+/// View type, aspects, subresource range and swizzle of an image view.
+/// Defaults to a 2D view of the first mip level and array layer with all aspects
+/// of the format and the identity swizzle
+///
+#[derive(Debug, Clone, Copy)]
+pub struct ImageViewBuilder {
+    format: vk::Format,
+    view_type: vk::ImageViewType,
+    aspect_mask: vk::ImageAspectFlags,
+    base_mip_level: u32,
+    level_count: u32,
+    base_array_layer: u32,
+    layer_count: u32,
+    components: vk::ComponentMapping,
+}
+
+impl ImageViewBuilder {
+    pub fn new(format: vk::Format) -> Self {
+        Self {
+            format,
+            view_type: vk::ImageViewType::TYPE_2D,
+            aspect_mask: aspect_mask(format),
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+            components: vk::ComponentMapping::default(),
+        }
+    }
+
+    ///
+    /// CUBE needs 6 array layers and CUBE_ARRAY a multiple of 6
+    ///
+    pub fn view_type(mut self, view_type: vk::ImageViewType) -> Self {
+        self.view_type = view_type;
+        self
+    }
+
+    ///
+    /// E.g. only DEPTH to sample the depth of a depth/stencil image
+    ///
+    pub fn aspect_mask(mut self, aspect_mask: vk::ImageAspectFlags) -> Self {
+        self.aspect_mask = aspect_mask;
+        self
+    }
+
+    pub fn mip_levels(mut self, base: u32, count: u32) -> Self {
+        self.base_mip_level = base;
+        self.level_count = count;
+        self
+    }
+
+    pub fn array_layers(mut self, base: u32, count: u32) -> Self {
+        self.base_array_layer = base;
+        self.layer_count = count;
+        self
+    }
+
+    pub fn components(mut self, components: vk::ComponentMapping) -> Self {
+        self.components = components;
+        self
+    }
+
+    pub fn get_subresource_range(&self) -> vk::ImageSubresourceRange {
+        vk::ImageSubresourceRange::default()
+            .aspect_mask(self.aspect_mask)
+            .base_mip_level(self.base_mip_level)
+            .level_count(self.level_count)
+            .base_array_layer(self.base_array_layer)
+            .layer_count(self.layer_count)
+    }
+
+    ///
+    /// Checks the view against a 2D image with mip_levels and array_layers
+    ///
+    fn check(&self, mip_levels: u32, array_layers: u32) -> Result<(), ImageViewError> {
+        let image_aspects = aspect_mask(self.format);
+        if self.aspect_mask.is_empty() || !image_aspects.contains(self.aspect_mask) {
+            return Err(ImageViewError::InvalidAspects {
+                aspects: self.aspect_mask,
+                image_aspects,
+            });
+        }
+        if self.level_count == 0
+            || self
+                .base_mip_level
+                .checked_add(self.level_count)
+                .is_none_or(|end| end > mip_levels)
+        {
+            return Err(ImageViewError::MipLevelsOutOfRange {
+                base: self.base_mip_level,
+                count: self.level_count,
+                levels: mip_levels,
+            });
+        }
+        if self.layer_count == 0
+            || self
+                .base_array_layer
+                .checked_add(self.layer_count)
+                .is_none_or(|end| end > array_layers)
+        {
+            return Err(ImageViewError::ArrayLayersOutOfRange {
+                base: self.base_array_layer,
+                count: self.layer_count,
+                layers: array_layers,
+            });
+        }
+        let valid_layer_count = match self.view_type {
+            vk::ImageViewType::TYPE_2D => self.layer_count == 1,
+            vk::ImageViewType::TYPE_2D_ARRAY => true,
+            vk::ImageViewType::CUBE => self.layer_count == 6,
+            vk::ImageViewType::CUBE_ARRAY => self.layer_count.is_multiple_of(6),
+            view_type => return Err(ImageViewError::InvalidViewType(view_type)),
+        };
+        if !valid_layer_count {
+            return Err(ImageViewError::InvalidLayerCount {
+                view_type: self.view_type,
+                count: self.layer_count,
+            });
+        }
+        Ok(())
+    }
+
+    pub(in crate::vk) fn create_info(&self, image: vk::Image) -> vk::ImageViewCreateInfo<'static> {
+        vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(self.view_type)
+            .format(self.format)
+            .components(self.components)
+            .subresource_range(self.get_subresource_range())
+    }
+}
+
+///
+/// Views created with Image::create_view keep their image alive
+///
+pub struct ImageView {
+    device: Arc<Device>,
+    view: vk::ImageView,
+    _image: Option<Arc<Image>>,
+}
+
+impl ImageView {
+    fn new(device: Arc<Device>, create_info: &vk::ImageViewCreateInfo) -> Self {
+        let view = unsafe { device.create_image_view(create_info) };
+        Self {
+            device,
+            view,
+            _image: None,
+        }
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::ImageView {
+        self.view
+    }
+}
+
+impl Drop for ImageView {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_image_view(self.view);
+        }
+    }
+}
+
+///
+/// 2D image with a single mip level and array layer, bound to its own allocation,
+/// together with a view of all of it
+///
+pub struct Image {
+    weak_self: Weak<Self>,
+    device: Arc<Device>,
+    image: vk::Image,
+    allocation: Allocation,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    view: ImageView,
+}
+
+impl Image {
+    pub fn new(
+        device: Arc<Device>,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Arc<Self>, AllocationError> {
+        let create_info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent.into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = unsafe { device.raw_handle().create_image(&create_info, None) }
+            .unwrap_or_else(|error| fatal_vk_error("failed to create_image", error));
+
+        let requirements = unsafe { device.raw_handle().get_image_memory_requirements(image) };
+        let allocation = match allocator.allocate(requirements, properties) {
+            Ok(allocation) => allocation,
+            Err(error) => {
+                unsafe { device.raw_handle().destroy_image(image, None) };
+                return Err(error);
+            }
+        };
+        unsafe {
+            device
+                .raw_handle()
+                .bind_image_memory(image, allocation.raw_handle(), 0)
+                .unwrap_or_else(|error| fatal_vk_error("failed to bind_image_memory", error));
+        }
+
+        let view_info = ImageViewBuilder::new(format).create_info(image);
+        let view = ImageView::new(Arc::clone(&device), &view_info);
+
+        Ok(Arc::new_cyclic(|weak_self| Self {
+            weak_self: Weak::clone(weak_self),
+            device,
+            image,
+            allocation,
+            extent,
+            format,
+            view,
+        }))
+    }
+
+    ///
+    /// Device-local image that can be rendered to and then sampled or read back,
+    /// e.g. the target of an offscreen pass read by post-processing
+    ///
+    pub fn new_color_target(
+        device: Arc<Device>,
+        allocator: &Allocator,
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Arc<Self>, AllocationError> {
+        Self::new(
+            device,
+            allocator,
+            extent,
+            format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )
+    }
+
+    ///
+    /// Creates a sampled image from tightly packed texels without blocking the graphics
+    /// queue: the copy from a staging buffer runs on the transfer queue, the graphics
+    /// queue only acquires ownership of the image. Resolves once the image is in
+    /// SHADER_READ_ONLY_OPTIMAL and can be sampled by the graphics queue family
+    ///
+    pub async fn upload_async(
+        device: Arc<Device>,
+        queues: UploadQueues<'_>,
+        allocator: &Allocator,
+        data: &[u8],
+        extent: vk::Extent2D,
+        format: vk::Format,
+    ) -> Result<Arc<Self>, UploadError> {
+        let size = image_size(format, extent).ok_or(UploadError::UnsupportedFormat(format))?;
+        if size != data.len() as u64 {
+            return Err(UploadError::SizeMismatch {
+                expected: size,
+                actual: data.len(),
+            });
+        }
+        let staging = Buffer::new(
+            Arc::clone(&device),
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_SRC,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        staging
+            .map()
+            .unwrap_or_else(|error| fatal_vk_error("failed to map staging buffer", error))
+            .copy_from_slice(data);
+        let image = Self::new(
+            Arc::clone(&device),
+            allocator,
+            extent,
+            format,
+            vk::ImageUsageFlags::TRANSFER_DST | vk::ImageUsageFlags::SAMPLED,
+            vk::MemoryPropertyFlags::DEVICE_LOCAL,
+        )?;
+
+        let families = queues.families;
+        let transfer_ownership = families.src_queue_family != families.dst_queue_family;
+        let mut copy = queues.transfer_pool.allocate_command_buffer();
+        if let Err(error) =
+            image.record_upload(&mut copy, &staging, transfer_ownership.then_some(families))
+        {
+            queues.transfer_pool.free_command_buffer(copy);
+            return Err(error.into());
+        }
+        let copy = Arc::new(copy);
+
+        let result = if transfer_ownership {
+            image
+                .submit_with_ownership_transfer(&device, &queues, &copy)
+                .await
+        } else {
+            async {
+                queues
+                    .transfer
+                    .submit_async(Arc::clone(&copy), &[], &[])?
+                    .await?;
+                Ok(())
+            }
+            .await
+        };
+        queues.transfer_pool.free_submitted_command_buffer(copy);
+        result.map(|()| image)
+    }
+
+    ///
+    /// Submits the recorded copy, which releases the image, and the acquire half
+    /// on the graphics queue waiting for it
+    ///
+    async fn submit_with_ownership_transfer(
+        &self,
+        device: &Arc<Device>,
+        queues: &UploadQueues<'_>,
+        copy: &Arc<CommandBuffer>,
+    ) -> Result<(), UploadError> {
+        let mut acquire = queues.graphics_pool.allocate_command_buffer();
+        if let Err(error) = self.record_acquire(&mut acquire, queues.families) {
+            queues.graphics_pool.free_command_buffer(acquire);
+            return Err(error.into());
+        }
+        let acquire = Arc::new(acquire);
+
+        let released = Semaphore::new(Arc::clone(device));
+        let result = async {
+            // Dropping a Submission blocks until its work completes, so if the acquire
+            // can't be submitted the copy still finishes before the semaphore and the
+            // command buffers are released
+            let copy_submission =
+                queues
+                    .transfer
+                    .submit_async(Arc::clone(copy), &[], &[&released])?;
+            let wait = [WaitSemaphore::new(
+                &released,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+            )];
+            queues
+                .graphics
+                .submit_async(Arc::clone(&acquire), &wait, &[])?
+                .await?;
+            copy_submission.await?;
+            Ok(())
+        }
+        .await;
+        queues.graphics_pool.free_submitted_command_buffer(acquire);
+        result
+    }
+
+    fn record_acquire(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        families: OwnershipTransfer,
+    ) -> Result<(), CommandBufferStateError> {
+        command_buffer.begin_with_usage(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)?;
+        command_buffer.cmd_acquire_image_ownership(
+            self,
+            families,
+            (
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ),
+            vk::PipelineStageFlags::FRAGMENT_SHADER,
+            vk::AccessFlags::SHADER_READ,
+        )?;
+        command_buffer.end()
+    }
+
+    fn record_upload(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        staging: &Buffer,
+        release: Option<OwnershipTransfer>,
+    ) -> Result<(), CommandBufferStateError> {
+        command_buffer.begin_with_usage(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)?;
+        command_buffer.cmd_image_barrier(
+            self,
+            (
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            ),
+            (
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                vk::AccessFlags::empty(),
+            ),
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            ),
+        )?;
+        command_buffer.cmd_copy_buffer_to_image(
+            staging,
+            self,
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+        )?;
+        let layouts = (
+            vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+        match release {
+            Some(transfer) => command_buffer.cmd_release_image_ownership(
+                self,
+                transfer,
+                layouts,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_WRITE,
+            )?,
+            None => command_buffer.cmd_image_barrier(
+                self,
+                layouts,
+                (
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::AccessFlags::TRANSFER_WRITE,
+                ),
+                (
+                    vk::PipelineStageFlags::FRAGMENT_SHADER,
+                    vk::AccessFlags::SHADER_READ,
+                ),
+            )?,
+        }
+        command_buffer.end()
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn get_allocation(&self) -> &Allocation {
+        &self.allocation
+    }
+
+    pub fn get_view(&self) -> &ImageView {
+        &self.view
+    }
+
+    ///
+    /// Additional view, e.g. with a swizzle or only the depth aspect.
+    /// builder has to be created with the format of the image
+    ///
+    pub fn create_view(&self, builder: &ImageViewBuilder) -> Result<ImageView, ImageViewError> {
+        if builder.format != self.format {
+            return Err(ImageViewError::FormatMismatch {
+                format: builder.format,
+                image_format: self.format,
+            });
+        }
+        builder.check(1, 1)?;
+        let view_info = builder.create_info(self.image);
+        let mut view = ImageView::new(Arc::clone(&self.device), &view_info);
+        view._image = Some(self.arc_self());
+        Ok(view)
+    }
+
+    ///
+    /// Copies the image into host memory and waits for the copy on queue.
+    /// The image needs TRANSFER_SRC usage and has to be in layout when the copy executes,
+    /// it is left in the same layout. The format of the bytes is returned along with them,
+    /// see Readback::to_channel_order
+    ///
+    pub async fn read_to_vec(
+        &self,
+        queue: &Queue,
+        command_pool: &CommandPool,
+        allocator: &Allocator,
+        layout: vk::ImageLayout,
+    ) -> Result<Readback, ReadbackError> {
+        let size = image_size(self.format, self.extent)
+            .ok_or(UnsupportedReadbackFormatError(self.format))?;
+        let buffer = Buffer::new(
+            Arc::clone(&self.device),
+            allocator,
+            size,
+            vk::BufferUsageFlags::TRANSFER_DST,
+            vk::MemoryPropertyFlags::HOST_VISIBLE,
+        )?;
+
+        let mut command_buffer = command_pool.allocate_command_buffer();
+        if let Err(error) = self.record_readback(&mut command_buffer, &buffer, layout) {
+            command_pool.free_command_buffer(command_buffer);
+            return Err(error.into());
+        }
+        let command_buffer = Arc::new(command_buffer);
+
+        let mut fence = Fence::oneshot(Arc::clone(&self.device));
+        let result = async {
+            queue.submit_command_buffer(Arc::clone(&command_buffer), &[], &[], Some(&mut fence))?;
+            fence.await?;
+            Ok::<_, ReadbackError>(())
+        }
+        .await;
+        command_pool.free_submitted_command_buffer(command_buffer);
+        result?;
+
+        let memory = buffer
+            .map()
+            .unwrap_or_else(|error| fatal_vk_error("failed to map readback buffer", error));
+        memory.invalidate(0..size);
+        Ok(Readback {
+            bytes: memory.to_vec(),
+            format: self.format,
+            extent: self.extent,
+        })
+    }
+
+    fn record_readback(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        buffer: &Buffer,
+        layout: vk::ImageLayout,
+    ) -> Result<(), CommandBufferStateError> {
+        command_buffer.begin_with_usage(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT)?;
+        command_buffer.cmd_image_barrier(
+            self,
+            (layout, vk::ImageLayout::TRANSFER_SRC_OPTIMAL),
+            (
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::MEMORY_WRITE,
+            ),
+            (
+                vk::PipelineStageFlags::TRANSFER,
+                vk::AccessFlags::TRANSFER_READ,
+            ),
+        )?;
+        command_buffer.cmd_copy_image_to_buffer(
+            self,
+            vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+            buffer,
+        )?;
+        command_buffer.cmd_image_barrier(
+            self,
+            (vk::ImageLayout::TRANSFER_SRC_OPTIMAL, layout),
+            (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::empty()),
+            (
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::AccessFlags::empty(),
+            ),
+        )?;
+        command_buffer.end()
+    }
+
+    pub(in crate::vk) fn get_subresource_range(&self) -> vk::ImageSubresourceRange {
+        subresource_range(self.format)
+    }
+
+    pub(in crate::vk) fn arc_self(&self) -> Arc<Self> {
+        self.weak_self.upgrade().unwrap()
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::Image {
+        self.image
+    }
+}
+
+///
+/// The whole image, with the depth and stencil aspects for depth formats
+///
+fn subresource_range(format: vk::Format) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange::default()
+        .aspect_mask(aspect_mask(format))
+        .level_count(1)
+        .layer_count(1)
+}
+
+impl Drop for Image {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw_handle().destroy_image(self.image, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn channel_order() {
+        let mut readback = Readback {
+            bytes: vec![1, 2, 3, 4, 5, 6, 7, 8],
+            format: vk::Format::B8G8R8A8_SRGB,
+            extent: vk::Extent2D {
+                width: 2,
+                height: 1,
+            },
+        };
+        readback.to_channel_order(ChannelOrder::Bgra).unwrap();
+        assert_eq!(readback.bytes, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        readback.to_channel_order(ChannelOrder::Rgba).unwrap();
+        assert_eq!(readback.bytes, [3, 2, 1, 4, 7, 6, 5, 8]);
+        assert_eq!(readback.format, vk::Format::R8G8B8A8_SRGB);
+
+        readback.format = vk::Format::R16G16B16A16_SFLOAT;
+        assert!(readback.to_channel_order(ChannelOrder::Rgba).is_err());
+    }
+
+    #[test]
+    fn packed_image_size() {
+        let extent = vk::Extent2D {
+            width: 3,
+            height: 2,
+        };
+        assert_eq!(image_size(vk::Format::R8G8B8A8_SRGB, extent), Some(24));
+        assert_eq!(image_size(vk::Format::R8_UNORM, extent), Some(6));
+        assert_eq!(image_size(vk::Format::BC1_RGB_UNORM_BLOCK, extent), None);
+    }
+
+    #[test]
+    fn image_view_ranges() {
+        let builder = ImageViewBuilder::new(vk::Format::R8G8B8A8_UNORM);
+        assert!(builder.check(1, 1).is_ok());
+        assert!(matches!(
+            builder.mip_levels(1, 1).check(1, 1),
+            Err(ImageViewError::MipLevelsOutOfRange { .. })
+        ));
+        assert!(matches!(
+            builder.array_layers(0, 0).check(1, 1),
+            Err(ImageViewError::ArrayLayersOutOfRange { .. })
+        ));
+        assert!(matches!(
+            builder.aspect_mask(vk::ImageAspectFlags::DEPTH).check(1, 1),
+            Err(ImageViewError::InvalidAspects { .. })
+        ));
+        assert!(
+            ImageViewBuilder::new(vk::Format::D24_UNORM_S8_UINT)
+                .aspect_mask(vk::ImageAspectFlags::DEPTH)
+                .check(1, 1)
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn image_view_types() {
+        let builder = ImageViewBuilder::new(vk::Format::R8G8B8A8_UNORM).array_layers(0, 6);
+        assert!(
+            builder
+                .view_type(vk::ImageViewType::CUBE)
+                .check(1, 6)
+                .is_ok()
+        );
+        assert!(
+            builder
+                .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+                .check(1, 6)
+                .is_ok()
+        );
+        assert!(matches!(
+            builder.check(1, 6),
+            Err(ImageViewError::InvalidLayerCount { .. })
+        ));
+        assert!(matches!(
+            builder
+                .array_layers(0, 4)
+                .view_type(vk::ImageViewType::CUBE_ARRAY)
+                .check(1, 6),
+            Err(ImageViewError::InvalidLayerCount { .. })
+        ));
+        assert!(matches!(
+            builder.view_type(vk::ImageViewType::TYPE_3D).check(1, 6),
+            Err(ImageViewError::InvalidViewType(_))
+        ));
+    }
+}
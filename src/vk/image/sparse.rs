@@ -0,0 +1,320 @@
+use std::sync::{Arc, Mutex};
+
+use ash::vk;
+
+use super::{ImageView, subresource_range};
+use crate::vk::{
+    device::Device,
+    error::{DeviceLostError, fatal_vk_error},
+    memory::Allocation,
+    pipeline::render_pass::aspect_mask,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SparseImageError {
+    #[error("sparse images need the sparse_binding and sparse_residency_image2_d features")]
+    FeaturesNotEnabled,
+    #[error("format {0:?} can't be used for sparse images on this device")]
+    UnsupportedFormat(vk::Format),
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SparseBindError {
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+    #[error("region {0:?} is not aligned to the sparse block granularity")]
+    UnalignedRegion(vk::Rect2D),
+    #[error("region {0:?} exceeds the image extent")]
+    OutOfBounds(vk::Rect2D),
+    #[error("the region needs {required} bytes, but the allocation has {size}")]
+    AllocationTooSmall { required: u64, size: u64 },
+    #[error("memory type {0} can't back the sparse image")]
+    InvalidMemoryType(u32),
+}
+
+///
+/// Binds memory to region of a SparseImage, or unbinds it if memory is None.
+/// The allocation backs the region starting at its offset 0
+///
+#[derive(Clone)]
+pub struct SparseImageBind {
+    pub region: vk::Rect2D,
+    pub memory: Option<Arc<Allocation>>,
+}
+
+fn is_aligned(offset: i32, size: u32, image_size: u32, granularity: u32) -> bool {
+    offset >= 0
+        && (offset as u32).is_multiple_of(granularity)
+        && (size.is_multiple_of(granularity) || offset as u32 + size == image_size)
+}
+
+///
+/// Checks that region is made of whole sparse blocks inside the image,
+/// returns the number of blocks in it
+///
+fn check_region(
+    extent: vk::Extent2D,
+    granularity: vk::Extent3D,
+    region: vk::Rect2D,
+) -> Result<u64, SparseBindError> {
+    let fits = |offset: i32, size: u32, image_size: u32| {
+        offset >= 0
+            && (offset as u32)
+                .checked_add(size)
+                .is_some_and(|end| end <= image_size)
+    };
+    if region.extent.width == 0
+        || region.extent.height == 0
+        || !fits(region.offset.x, region.extent.width, extent.width)
+        || !fits(region.offset.y, region.extent.height, extent.height)
+    {
+        return Err(SparseBindError::OutOfBounds(region));
+    }
+    if !is_aligned(
+        region.offset.x,
+        region.extent.width,
+        extent.width,
+        granularity.width,
+    ) || !is_aligned(
+        region.offset.y,
+        region.extent.height,
+        extent.height,
+        granularity.height,
+    ) {
+        return Err(SparseBindError::UnalignedRegion(region));
+    }
+    Ok(region.extent.width.div_ceil(granularity.width) as u64
+        * region.extent.height.div_ceil(granularity.height) as u64)
+}
+
+fn contains(outer: vk::Rect2D, inner: vk::Rect2D) -> bool {
+    outer.offset.x <= inner.offset.x
+        && outer.offset.y <= inner.offset.y
+        && outer.offset.x as i64 + outer.extent.width as i64
+            >= inner.offset.x as i64 + inner.extent.width as i64
+        && outer.offset.y as i64 + outer.extent.height as i64
+            >= inner.offset.y as i64 + inner.extent.height as i64
+}
+
+///
+/// 2D image with a single mip level and array layer whose memory is bound in blocks
+/// with Queue::bind_sparse, unbound regions are non-resident.
+/// Needs the sparse_binding and sparse_residency_image2_d features, see DeviceBuilder::features
+///
+pub struct SparseImage {
+    device: Arc<Device>,
+    image: vk::Image,
+    extent: vk::Extent2D,
+    format: vk::Format,
+    granularity: vk::Extent3D,
+    block_requirements: vk::MemoryRequirements,
+    bound: Mutex<Vec<(vk::Rect2D, Arc<Allocation>)>>,
+    view: ImageView,
+}
+
+impl SparseImage {
+    pub fn new(
+        device: Arc<Device>,
+        extent: vk::Extent2D,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Result<Self, SparseImageError> {
+        let features = device.get_enabled_features();
+        if features.sparse_binding == vk::FALSE || features.sparse_residency_image2_d == vk::FALSE {
+            return Err(SparseImageError::FeaturesNotEnabled);
+        }
+        let aspect_mask = aspect_mask(format);
+        let Some(format_properties) = device
+            .get_sparse_image_format_properties(format, usage)
+            .into_iter()
+            .find(|properties| properties.aspect_mask.contains(aspect_mask))
+        else {
+            return Err(SparseImageError::UnsupportedFormat(format));
+        };
+
+        let create_info = vk::ImageCreateInfo::default()
+            .flags(vk::ImageCreateFlags::SPARSE_BINDING | vk::ImageCreateFlags::SPARSE_RESIDENCY)
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(extent.into())
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .initial_layout(vk::ImageLayout::UNDEFINED);
+        let image = unsafe { device.raw_handle().create_image(&create_info, None) }
+            .unwrap_or_else(|error| fatal_vk_error("failed to create_image", error));
+
+        // For sparse images the alignment is the size of a single block
+        let requirements = unsafe { device.raw_handle().get_image_memory_requirements(image) };
+        let block_requirements = vk::MemoryRequirements::default()
+            .size(requirements.alignment)
+            .alignment(requirements.alignment)
+            .memory_type_bits(requirements.memory_type_bits);
+
+        let view_info = vk::ImageViewCreateInfo::default()
+            .image(image)
+            .view_type(vk::ImageViewType::TYPE_2D)
+            .format(format)
+            .subresource_range(subresource_range(format));
+        let view = ImageView::new(Arc::clone(&device), &view_info);
+
+        Ok(Self {
+            device,
+            image,
+            extent,
+            format,
+            granularity: format_properties.image_granularity,
+            block_requirements,
+            bound: Mutex::new(Vec::new()),
+            view,
+        })
+    }
+
+    pub fn get_extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
+    pub fn get_format(&self) -> vk::Format {
+        self.format
+    }
+
+    pub fn get_view(&self) -> &ImageView {
+        &self.view
+    }
+
+    ///
+    /// Size in texels of a sparse block, regions bound with Queue::bind_sparse are
+    /// made of whole blocks
+    ///
+    pub fn get_granularity(&self) -> vk::Extent3D {
+        self.granularity
+    }
+
+    ///
+    /// Requirements of the memory of a single block, an allocation backing n blocks
+    /// needs n times the size
+    ///
+    pub fn get_block_requirements(&self) -> vk::MemoryRequirements {
+        self.block_requirements
+    }
+
+    pub(in crate::vk) fn check_bind(&self, bind: &SparseImageBind) -> Result<(), SparseBindError> {
+        let blocks = check_region(self.extent, self.granularity, bind.region)?;
+        if let Some(memory) = &bind.memory {
+            let memory_type_index = memory.get_memory_type_index();
+            if self.block_requirements.memory_type_bits & (1 << memory_type_index) == 0 {
+                return Err(SparseBindError::InvalidMemoryType(memory_type_index));
+            }
+            let required = blocks * self.block_requirements.size;
+            if memory.get_size() < required {
+                return Err(SparseBindError::AllocationTooSmall {
+                    required,
+                    size: memory.get_size(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    pub(in crate::vk) fn make_memory_bind(
+        &self,
+        bind: &SparseImageBind,
+    ) -> vk::SparseImageMemoryBind {
+        let memory = match &bind.memory {
+            Some(memory) => unsafe { memory.raw_handle() },
+            None => vk::DeviceMemory::null(),
+        };
+        vk::SparseImageMemoryBind::default()
+            .subresource(
+                vk::ImageSubresource::default()
+                    .aspect_mask(aspect_mask(self.format))
+                    .mip_level(0)
+                    .array_layer(0),
+            )
+            .offset(vk::Offset3D {
+                x: bind.region.offset.x,
+                y: bind.region.offset.y,
+                z: 0,
+            })
+            .extent(bind.region.extent.into())
+            .memory(memory)
+            .memory_offset(0)
+    }
+
+    ///
+    /// Keeps the bound allocations alive, allocations whose regions are completely
+    /// rebound or unbound are released.
+    /// Only called after the bind is submitted, while the image is not in use by the queue
+    ///
+    pub(in crate::vk) fn track_binds(&self, binds: &[SparseImageBind]) {
+        let mut bound = self.bound.lock().unwrap();
+        for bind in binds {
+            bound.retain(|&(region, _)| !contains(bind.region, region));
+            if let Some(memory) = &bind.memory {
+                bound.push((bind.region, Arc::clone(memory)));
+            }
+        }
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::Image {
+        self.image
+    }
+}
+
+impl Drop for SparseImage {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw_handle().destroy_image(self.image, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const EXTENT: vk::Extent2D = vk::Extent2D {
+        width: 300,
+        height: 256,
+    };
+    const GRANULARITY: vk::Extent3D = vk::Extent3D {
+        width: 128,
+        height: 128,
+        depth: 1,
+    };
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        }
+    }
+
+    #[test]
+    fn whole_blocks() {
+        assert_eq!(
+            check_region(EXTENT, GRANULARITY, rect(0, 0, 256, 256)).unwrap(),
+            4
+        );
+        // The last block of a row is cut by the image edge
+        assert_eq!(
+            check_region(EXTENT, GRANULARITY, rect(256, 0, 44, 128)).unwrap(),
+            1
+        );
+        assert!(matches!(
+            check_region(EXTENT, GRANULARITY, rect(64, 0, 128, 128)),
+            Err(SparseBindError::UnalignedRegion(_))
+        ));
+        assert!(matches!(
+            check_region(EXTENT, GRANULARITY, rect(0, 0, 100, 128)),
+            Err(SparseBindError::UnalignedRegion(_))
+        ));
+        assert!(matches!(
+            check_region(EXTENT, GRANULARITY, rect(256, 128, 128, 128)),
+            Err(SparseBindError::OutOfBounds(_))
+        ));
+    }
+}
@@ -2,7 +2,7 @@ pub mod surface;
 
 use std::{
     error::Error,
-    ffi::{CString, NulError},
+    ffi::{CStr, CString, NulError},
     fmt::{self},
     sync::Arc,
 };
@@ -19,10 +19,34 @@ use sdl3::video::Window;
 use super::{
     error::fatal_vk_error,
     extensions::{ExtensionManager, InstanceExtensionUnavailableError},
-    physical_device::features::{FeaturesInfo, PhysicalDeviceFeatures2},
+    physical_device::{
+        features::{FeaturesInfo, PhysicalDeviceFeatures2, PresentWaitFeatures},
+        properties::{DriverInfo, PhysicalDeviceProperties2, PropertiesInfo, SubgroupInfo},
+    },
     validation::{ValidationLayerManager, ValidationLayerUnavailableError},
 };
-use crate::vk::{device::PhysicalDeviceInfo, validation};
+use crate::vk::{
+    device::{PhysicalDeviceInfo, device_extensions::DeviceExtensionManager},
+    validation,
+};
+
+///
+/// What a physical device offers, to decide which optional features and extensions
+/// to request before building a Device
+///
+pub struct DeviceSupport {
+    pub properties: vk::PhysicalDeviceProperties,
+    pub driver: DriverInfo,
+    pub subgroup: SubgroupInfo,
+    pub features: FeaturesInfo,
+    pub extensions: Vec<String>,
+}
+
+impl DeviceSupport {
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.iter().any(|ext| ext == name)
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 pub enum InstanceInitError {
@@ -34,6 +58,29 @@ pub enum InstanceInitError {
     InvalidName(#[from] NulError),
 }
 
+///
+/// Extra validation layer checks, only used in debug builds
+///
+#[derive(Default, Clone, Copy, Debug)]
+pub struct ValidationFeatures {
+    pub synchronization: bool,
+    pub gpu_assisted: bool,
+}
+
+impl ValidationFeatures {
+    fn enabled(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut enabled = Vec::new();
+        if self.synchronization {
+            enabled.push(vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION);
+        }
+        if self.gpu_assisted {
+            enabled.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+            enabled.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED_RESERVE_BINDING_SLOT);
+        }
+        enabled
+    }
+}
+
 pub struct InstanceBuilder {
     extensions: Vec<String>,
     entry: Arc<Entry>,
@@ -41,6 +88,9 @@ pub struct InstanceBuilder {
     api_version: u32,
     apllication_props: (String, u32),
     engine_props: (String, u32),
+    validation_features: ValidationFeatures,
+    require_validation_layers: bool,
+    debug_messenger: bool,
 }
 
 impl InstanceBuilder {
@@ -52,6 +102,9 @@ impl InstanceBuilder {
             api_version: vk::make_api_version(0, 1, 0, 0),
             apllication_props: (String::new(), 0),
             engine_props: (String::new(), 0),
+            validation_features: ValidationFeatures::default(),
+            require_validation_layers: false,
+            debug_messenger: cfg!(debug_assertions),
         }
     }
     pub fn extensions(mut self, extensions: Vec<String>) -> Self {
@@ -63,6 +116,30 @@ impl InstanceBuilder {
         self
     }
 
+    ///
+    /// By default missing validation layers are skipped with a warning,
+    /// with require set build fails with ValidationLayerUnavailableError instead
+    ///
+    pub fn require_validation_layers(mut self, require: bool) -> Self {
+        self.require_validation_layers = require;
+        self
+    }
+
+    ///
+    /// Logs debug utils messages, e.g. from validation layers, through log.
+    /// Enabled by default only in debug builds, turn it off for profiling a debug build
+    /// or on to see driver messages in a release build
+    ///
+    pub fn debug_messenger(mut self, debug_messenger: bool) -> Self {
+        self.debug_messenger = debug_messenger;
+        self
+    }
+
+    pub fn validation_features(mut self, features: ValidationFeatures) -> Self {
+        self.validation_features = features;
+        self
+    }
+
     pub fn api_version(mut self, version: u32) -> Self {
         self.api_version = version;
         self
@@ -77,7 +154,8 @@ impl InstanceBuilder {
     }
 
     pub fn build(mut self) -> Result<Instance, InstanceInitError> {
-        if cfg!(debug_assertions) {
+        // Debug builds name objects through debug utils even without the messenger
+        if cfg!(debug_assertions) || self.debug_messenger {
             self.extensions.push(String::from("VK_EXT_debug_utils"));
         }
 
@@ -87,7 +165,11 @@ impl InstanceBuilder {
         let extension_names = extension_manager.make_load_extension_list();
 
         let mut validation_manager = ValidationLayerManager::init(&self.entry);
-        validation_manager.add_layers(&self.layers)?;
+        if self.require_validation_layers {
+            validation_manager.add_layers(&self.layers)?;
+        } else {
+            validation_manager.add_available_layers(&self.layers);
+        }
         let layer_names = validation_manager.make_load_layer_list();
 
         let app_name = CString::new(self.apllication_props.0.clone())?;
@@ -98,14 +180,23 @@ impl InstanceBuilder {
             .application_version(self.apllication_props.1)
             .engine_name(&engine_name)
             .engine_version(self.engine_props.1);
-        let create_info = vk::InstanceCreateInfo::default()
+        let enabled_validation_features = self.validation_features.enabled();
+        let mut validation_features = vk::ValidationFeaturesEXT::default()
+            .enabled_validation_features(&enabled_validation_features);
+        let mut create_info = vk::InstanceCreateInfo::default()
             .application_info(&application_info)
             .enabled_extension_names(&extension_names)
             .enabled_layer_names(&layer_names);
+        if cfg!(debug_assertions)
+            && !layer_names.is_empty()
+            && !enabled_validation_features.is_empty()
+        {
+            create_info = create_info.push_next(&mut validation_features);
+        }
         let ash_instance = unsafe { self.entry.create_instance(&create_info, None) }
             .unwrap_or_else(|e| fatal_vk_error("failed to create_instance", e));
 
-        let debug_messenger = if cfg!(debug_assertions) {
+        let debug_messenger = if self.debug_messenger {
             let loader = ash::ext::debug_utils::Instance::new(&self.entry, &ash_instance);
             Some(unsafe { validation::create_debug_messenger(loader) })
         } else {
@@ -115,6 +206,7 @@ impl InstanceBuilder {
         let instance = Instance {
             entry: self.entry,
             instance: ash_instance,
+            extensions: self.extensions.clone(),
             debug_messenger,
         };
 
@@ -127,7 +219,8 @@ api_version: {};
 app: name: {}, version: {};
 engine: name: {}, version: {};
 extension: {:?};
-validation layers: {:?};",
+validation layers: {:?};
+validation features: {:?};",
             instance,
             self.api_version,
             self.apllication_props.0,
@@ -140,6 +233,7 @@ validation layers: {:?};",
             } else {
                 Vec::new()
             },
+            self.validation_features,
         );
 
         Ok(instance)
@@ -149,6 +243,7 @@ validation layers: {:?};",
 pub struct Instance {
     instance: ash::Instance,
     entry: Arc<Entry>,
+    extensions: Vec<String>,
     debug_messenger: Option<vk::DebugUtilsMessengerEXT>,
 }
 
@@ -160,9 +255,26 @@ impl Instance {
     unsafe fn make_surface_instance(&self) -> khr::surface::Instance {
         khr::surface::Instance::new(&self.entry, &self.instance)
     }
+    ///
+    /// # Safety
+    /// khr::get_surface_capabilities2::Instance should not be used after parent instance
+    /// is destroyed, and only if VK_KHR_get_surface_capabilities2 is enabled
+    ///
+    unsafe fn make_surface_capabilities2_instance(
+        &self,
+    ) -> khr::get_surface_capabilities2::Instance {
+        khr::get_surface_capabilities2::Instance::new(&self.entry, &self.instance)
+    }
+    pub fn is_extension_enabled(&self, name: &CStr) -> bool {
+        let name = name.to_string_lossy();
+        self.extensions.iter().any(|enabled| *enabled == name)
+    }
     pub fn create_surface(&self, window: &Window) -> Result<SurfaceKHR, sdl3::Error> {
         window.vulkan_create_surface(self.instance.handle())
     }
+    ///
+    /// Retried by ash until it stops returning INCOMPLETE, never truncated
+    ///
     pub fn enumerate_physical_devices(&self) -> Result<Vec<PhysicalDevice>, vk::Result> {
         unsafe { self.instance.enumerate_physical_devices() }
     }
@@ -174,12 +286,101 @@ impl Instance {
         }
 
         let features = FeaturesInfo::from_features2(features2);
+        let PropertiesInfo {
+            properties,
+            driver,
+            subgroup,
+        } = unsafe { self.get_physical_device_properties2(device) };
 
         PhysicalDeviceInfo {
-            properties: unsafe { self.instance.get_physical_device_properties(device) },
+            properties,
+            driver,
+            subgroup,
             features,
         }
     }
+    pub fn query_device_support(
+        &self,
+        device: PhysicalDevice,
+    ) -> Result<DeviceSupport, vk::Result> {
+        let extensions = DeviceExtensionManager::init(self, device)?.available_names();
+        let PhysicalDeviceInfo {
+            properties,
+            driver,
+            subgroup,
+            features,
+        } = unsafe { self.get_physical_device_info(device) };
+        Ok(DeviceSupport {
+            properties,
+            driver,
+            subgroup,
+            features,
+            extensions,
+        })
+    }
+    pub unsafe fn get_physical_device_properties(
+        &self,
+        physical_device: PhysicalDevice,
+    ) -> vk::PhysicalDeviceProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_properties(physical_device)
+        }
+    }
+    ///
+    /// Properties together with driver and subgroup properties (core since Vulkan 1.2 and 1.1)
+    ///
+    pub unsafe fn get_physical_device_properties2(
+        &self,
+        physical_device: PhysicalDevice,
+    ) -> PropertiesInfo {
+        let mut properties2 = PhysicalDeviceProperties2::new();
+        unsafe {
+            properties2.fill(physical_device, &self.instance);
+        }
+        PropertiesInfo::from_properties2(properties2)
+    }
+    pub unsafe fn get_physical_device_memory_properties(
+        &self,
+        physical_device: PhysicalDevice,
+    ) -> vk::PhysicalDeviceMemoryProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_memory_properties(physical_device)
+        }
+    }
+    pub unsafe fn get_physical_device_format_properties(
+        &self,
+        physical_device: PhysicalDevice,
+        format: vk::Format,
+    ) -> vk::FormatProperties {
+        unsafe {
+            self.instance
+                .get_physical_device_format_properties(physical_device, format)
+        }
+    }
+    ///
+    /// Properties of single-sampled optimal-tiling 2D sparse images of format,
+    /// empty if the format can't be used for sparse residency
+    ///
+    pub unsafe fn get_physical_device_sparse_image_format_properties(
+        &self,
+        physical_device: PhysicalDevice,
+        format: vk::Format,
+        usage: vk::ImageUsageFlags,
+    ) -> Vec<vk::SparseImageFormatProperties> {
+        unsafe {
+            self.instance
+                .get_physical_device_sparse_image_format_properties(
+                    physical_device,
+                    format,
+                    vk::ImageType::TYPE_2D,
+                    vk::SampleCountFlags::TYPE_1,
+                    usage,
+                    vk::ImageTiling::OPTIMAL,
+                )
+        }
+    }
     pub unsafe fn get_physical_device_queue_family_properties(
         &self,
         physical_device: PhysicalDevice,
@@ -190,6 +391,29 @@ impl Instance {
         }
     }
 
+    ///
+    /// # Safety
+    /// physical_device has to support VK_KHR_present_id and VK_KHR_present_wait
+    ///
+    pub unsafe fn get_physical_device_present_wait_features(
+        &self,
+        physical_device: PhysicalDevice,
+    ) -> PresentWaitFeatures {
+        let mut present_id = vk::PhysicalDevicePresentIdFeaturesKHR::default();
+        let mut present_wait = vk::PhysicalDevicePresentWaitFeaturesKHR::default();
+        let mut features2 = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut present_id)
+            .push_next(&mut present_wait);
+        unsafe {
+            self.instance
+                .get_physical_device_features2(physical_device, &mut features2);
+        }
+        PresentWaitFeatures {
+            present_id: present_id.present_id == vk::TRUE,
+            present_wait: present_wait.present_wait == vk::TRUE,
+        }
+    }
+
     pub unsafe fn create_device(
         &self,
         physical_device: PhysicalDevice,
@@ -200,6 +424,9 @@ impl Instance {
                 .create_device(physical_device, device_info, None)
         }
     }
+    ///
+    /// Retried by ash until it stops returning INCOMPLETE, never truncated
+    ///
     pub unsafe fn enumerate_device_extension_properties(
         &self,
         device: PhysicalDevice,
@@ -216,10 +443,9 @@ impl Instance {
         &self,
         device: &Device,
         create_info: &SwapchainCreateInfoKHR,
-    ) -> Result<SwapchainKHR, Box<dyn Error>> {
+    ) -> Result<SwapchainKHR, vk::Result> {
         let loader = khr::swapchain::Device::new(&self.instance, device);
-        let swapchain = unsafe { loader.create_swapchain(create_info, None)? };
-        Ok(swapchain)
+        unsafe { loader.create_swapchain(create_info, None) }
     }
 
     ///
@@ -253,6 +479,25 @@ impl Instance {
         Ok(())
     }
 
+    ///
+    /// Escape hatch for interop with libraries that work on raw ash
+    ///
+    /// # Safety
+    /// The instance must not be destroyed through it, and objects created through it
+    /// have to be destroyed before this Instance is dropped
+    ///
+    pub unsafe fn raw_ash_instance(&self) -> &ash::Instance {
+        &self.instance
+    }
+
+    ///
+    /// # Safety
+    /// See raw_ash_instance
+    ///
+    pub unsafe fn raw_entry(&self) -> &Entry {
+        &self.entry
+    }
+
     pub(in crate::vk) unsafe fn raw_handle(&self) -> ash::Instance {
         self.instance.clone()
     }
@@ -5,7 +5,10 @@ use ash::{
     vk::{self, SurfaceKHR},
 };
 
-use crate::vk::surface::PhysicalDeviceSurfaceInfo;
+use crate::vk::{
+    surface::PhysicalDeviceSurfaceInfo,
+    swapchain::{GET_SURFACE_CAPABILITIES_2_EXTENSION, SURFACE_PROTECTED_CAPABILITIES_EXTENSION},
+};
 
 use super::Instance;
 
@@ -13,14 +16,21 @@ pub struct SurfaceInstance {
     instance: Arc<Instance>, // Has to have a reference to the original Instance for ensuring
     // correctness of lifetimes
     surface_khr_instance: khr::surface::Instance,
+    // Only loaded when the extensions needed for protected capabilities are enabled
+    surface_capabilities2_instance: Option<khr::get_surface_capabilities2::Instance>,
 }
 
 impl SurfaceInstance {
     pub fn new(instance: Arc<Instance>) -> Self {
         let surface_khr_instance = unsafe { instance.make_surface_instance() };
+        let surface_capabilities2_instance = (instance
+            .is_extension_enabled(GET_SURFACE_CAPABILITIES_2_EXTENSION)
+            && instance.is_extension_enabled(SURFACE_PROTECTED_CAPABILITIES_EXTENSION))
+        .then(|| unsafe { instance.make_surface_capabilities2_instance() });
         let s = Self {
             instance,
             surface_khr_instance,
+            surface_capabilities2_instance,
         };
         log::info!("Created {:?}", s);
         s
@@ -37,6 +47,11 @@ impl SurfaceInstance {
         }
     }
 
+    ///
+    /// Formats and present modes are complete: ash repeats the two-call enumeration
+    /// until it stops returning INCOMPLETE, so a count changing between the calls
+    /// (e.g. a hot-plugged GPU) doesn't truncate them
+    ///
     pub unsafe fn get_physical_device_surface_info(
         &self,
         device: vk::PhysicalDevice,
@@ -60,6 +75,35 @@ impl SurfaceInstance {
         }
     }
 
+    ///
+    /// VkSurfaceProtectedCapabilitiesKHR::supportsProtected, None if the instance
+    /// extensions to query it are not enabled
+    ///
+    /// # Safety
+    /// surface has to be a live surface created with this instance
+    ///
+    pub unsafe fn get_physical_device_surface_protected(
+        &self,
+        device: vk::PhysicalDevice,
+        surface: SurfaceKHR,
+    ) -> Result<Option<bool>, vk::Result> {
+        let Some(surface_capabilities2_instance) = &self.surface_capabilities2_instance else {
+            return Ok(None);
+        };
+        let surface_info = vk::PhysicalDeviceSurfaceInfo2KHR::default().surface(surface);
+        let mut protected_capabilities = vk::SurfaceProtectedCapabilitiesKHR::default();
+        let mut capabilities =
+            vk::SurfaceCapabilities2KHR::default().push_next(&mut protected_capabilities);
+        unsafe {
+            surface_capabilities2_instance.get_physical_device_surface_capabilities2(
+                device,
+                &surface_info,
+                &mut capabilities,
+            )?;
+        }
+        Ok(Some(protected_capabilities.supports_protected == vk::TRUE))
+    }
+
     pub unsafe fn destroy_surface(&self, surface: SurfaceKHR) {
         unsafe {
             self.surface_khr_instance.destroy_surface(surface, None);
@@ -0,0 +1,343 @@
+#[cfg(unix)]
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd};
+use std::{
+    ffi::CStr,
+    ops::{Deref, DerefMut, Range},
+    slice,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use ash::vk;
+
+use super::{device::Device, error::fatal_vk_error};
+
+#[derive(Debug, thiserror::Error)]
+pub enum AllocationError {
+    #[error("no memory type with properties {0:?} is suitable for the resource")]
+    NoSuitableMemoryType(vk::MemoryPropertyFlags),
+    #[error("failed to allocate memory: {0}")]
+    Allocation(#[from] vk::Result),
+    #[error("importing memory requires VK_KHR_external_memory_fd to be enabled")]
+    ExternalMemoryNotEnabled,
+    #[error("SHADER_DEVICE_ADDRESS usage requires the buffer_device_address feature to be enabled")]
+    BufferDeviceAddressNotEnabled,
+    #[error("memory type {0} does not exist or is not suitable for the resource")]
+    InvalidMemoryType(u32),
+    #[error("the resource needs {required} bytes, but only {size} were imported")]
+    ImportTooSmall { required: u64, size: u64 },
+}
+
+pub const EXTERNAL_MEMORY_FD_EXTENSION: &CStr = c"VK_KHR_external_memory_fd";
+
+pub struct Allocation {
+    device: Arc<Device>,
+    memory: vk::DeviceMemory,
+    size: u64,
+    memory_type_index: u32,
+    properties: vk::MemoryPropertyFlags,
+    external: vk::ExternalMemoryHandleTypeFlags,
+    non_coherent_atom_size: u64,
+    mapped: AtomicBool,
+}
+
+impl Allocation {
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_properties(&self) -> vk::MemoryPropertyFlags {
+        self.properties
+    }
+
+    pub fn get_memory_type_index(&self) -> u32 {
+        self.memory_type_index
+    }
+
+    ///
+    /// Handle type the memory was imported from, empty for memory allocated by Allocator::allocate
+    ///
+    pub fn get_external_handle_type(&self) -> vk::ExternalMemoryHandleTypeFlags {
+        self.external
+    }
+
+    ///
+    /// Maps the whole allocation. Fails with ERROR_MEMORY_MAP_FAILED if the memory
+    /// is not HOST_VISIBLE or is already mapped
+    ///
+    pub fn map(&self) -> Result<MappedMemory<'_>, vk::Result> {
+        self.map_len(self.size)
+    }
+
+    pub(in crate::vk) fn map_len(&self, len: u64) -> Result<MappedMemory<'_>, vk::Result> {
+        let ptr = unsafe { self.map_raw()? };
+        Ok(MappedMemory {
+            allocation: self,
+            data: unsafe { slice::from_raw_parts_mut(ptr, len as usize) },
+        })
+    }
+
+    ///
+    /// # Safety
+    /// The mapping has to be released with unmap_raw and the pointer must not be used after
+    ///
+    pub(in crate::vk) unsafe fn map_raw(&self) -> Result<*mut u8, vk::Result> {
+        if !self
+            .properties
+            .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
+            || self.mapped.swap(true, Ordering::Acquire)
+        {
+            return Err(vk::Result::ERROR_MEMORY_MAP_FAILED);
+        }
+        let ptr = unsafe {
+            self.device.raw_handle().map_memory(
+                self.memory,
+                0,
+                vk::WHOLE_SIZE,
+                vk::MemoryMapFlags::empty(),
+            )
+        };
+        ptr.map(|ptr| ptr as *mut u8).inspect_err(|_| {
+            self.mapped.store(false, Ordering::Release);
+        })
+    }
+
+    pub(in crate::vk) unsafe fn unmap_raw(&self) {
+        unsafe {
+            self.device.raw_handle().unmap_memory(self.memory);
+        }
+        self.mapped.store(false, Ordering::Release);
+    }
+
+    fn mapped_range(&self, range: Range<u64>) -> vk::MappedMemoryRange<'_> {
+        let atom = self.non_coherent_atom_size;
+        let offset = range.start / atom * atom;
+        let end = range.end.div_ceil(atom) * atom;
+        let size = if end >= self.size {
+            vk::WHOLE_SIZE
+        } else {
+            end - offset
+        };
+        vk::MappedMemoryRange::default()
+            .memory(self.memory)
+            .offset(offset)
+            .size(size)
+    }
+
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::DeviceMemory {
+        self.memory
+    }
+}
+
+///
+/// Mapped host-visible memory, unmapped on drop.
+/// For memory without HOST_COHERENT, writes have to be flushed before the GPU reads them
+/// and GPU writes have to be invalidated before reading them on the host
+///
+pub struct MappedMemory<'a> {
+    allocation: &'a Allocation,
+    data: &'a mut [u8],
+}
+
+impl MappedMemory<'_> {
+    fn is_coherent(&self) -> bool {
+        self.allocation
+            .properties
+            .contains(vk::MemoryPropertyFlags::HOST_COHERENT)
+    }
+
+    pub fn flush(&self, range: Range<u64>) {
+        if self.is_coherent() {
+            return;
+        }
+        unsafe {
+            self.allocation
+                .device
+                .raw_handle()
+                .flush_mapped_memory_ranges(&[self.allocation.mapped_range(range)])
+                .unwrap_or_else(|error| {
+                    fatal_vk_error("failed to flush_mapped_memory_ranges", error)
+                });
+        }
+    }
+
+    pub fn invalidate(&self, range: Range<u64>) {
+        if self.is_coherent() {
+            return;
+        }
+        unsafe {
+            self.allocation
+                .device
+                .raw_handle()
+                .invalidate_mapped_memory_ranges(&[self.allocation.mapped_range(range)])
+                .unwrap_or_else(|error| {
+                    fatal_vk_error("failed to invalidate_mapped_memory_ranges", error)
+                });
+        }
+    }
+}
+
+impl Deref for MappedMemory<'_> {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        self.data
+    }
+}
+
+impl DerefMut for MappedMemory<'_> {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        self.data
+    }
+}
+
+impl Drop for MappedMemory<'_> {
+    fn drop(&mut self) {
+        unsafe {
+            self.allocation.unmap_raw();
+        }
+    }
+}
+
+impl Drop for Allocation {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw_handle().free_memory(self.memory, None);
+        }
+    }
+}
+
+///
+/// Allocates a dedicated vk::DeviceMemory for every resource
+///
+pub struct Allocator {
+    device: Arc<Device>,
+    memory_properties: vk::PhysicalDeviceMemoryProperties,
+    non_coherent_atom_size: u64,
+}
+
+impl Allocator {
+    pub fn new(device: Arc<Device>) -> Self {
+        let memory_properties = device.get_memory_properties();
+        let non_coherent_atom_size = device.get_properties().limits.non_coherent_atom_size;
+        Self {
+            device,
+            memory_properties,
+            non_coherent_atom_size,
+        }
+    }
+
+    fn find_memory_type(
+        &self,
+        type_bits: u32,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Option<(u32, vk::MemoryPropertyFlags)> {
+        self.memory_properties
+            .memory_types_as_slice()
+            .iter()
+            .enumerate()
+            .find(|(index, memory_type)| {
+                type_bits & (1 << index) != 0 && memory_type.property_flags.contains(properties)
+            })
+            .map(|(index, memory_type)| (index as u32, memory_type.property_flags))
+    }
+
+    pub fn allocate(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+    ) -> Result<Allocation, AllocationError> {
+        self.allocate_with_flags(requirements, properties, vk::MemoryAllocateFlags::empty())
+    }
+
+    ///
+    /// flags are e.g. DEVICE_ADDRESS for buffers with SHADER_DEVICE_ADDRESS usage
+    ///
+    pub fn allocate_with_flags(
+        &self,
+        requirements: vk::MemoryRequirements,
+        properties: vk::MemoryPropertyFlags,
+        flags: vk::MemoryAllocateFlags,
+    ) -> Result<Allocation, AllocationError> {
+        let (memory_type_index, properties) = self
+            .find_memory_type(requirements.memory_type_bits, properties)
+            .ok_or(AllocationError::NoSuitableMemoryType(properties))?;
+        let mut flags_info = vk::MemoryAllocateFlagsInfo::default().flags(flags);
+        let mut allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(requirements.size)
+            .memory_type_index(memory_type_index);
+        if !flags.is_empty() {
+            allocate_info = allocate_info.push_next(&mut flags_info);
+        }
+        let memory = unsafe {
+            self.device
+                .raw_handle()
+                .allocate_memory(&allocate_info, None)?
+        };
+
+        Ok(Allocation {
+            device: Arc::clone(&self.device),
+            memory,
+            size: requirements.size,
+            memory_type_index,
+            properties,
+            external: vk::ExternalMemoryHandleTypeFlags::empty(),
+            non_coherent_atom_size: self.non_coherent_atom_size,
+            mapped: AtomicBool::new(false),
+        })
+    }
+
+    ///
+    /// Imports size bytes of OPAQUE_FD memory exported by another API or process,
+    /// e.g. CUDA or a video decoder. On success Vulkan takes ownership of fd.
+    /// Resources are bound to it with e.g. Buffer::import
+    ///
+    #[cfg(unix)]
+    pub fn import_fd(
+        &self,
+        fd: OwnedFd,
+        size: u64,
+        memory_type_index: u32,
+    ) -> Result<Allocation, AllocationError> {
+        if !self
+            .device
+            .is_extension_enabled(EXTERNAL_MEMORY_FD_EXTENSION)
+        {
+            return Err(AllocationError::ExternalMemoryNotEnabled);
+        }
+        let properties = self
+            .memory_properties
+            .memory_types_as_slice()
+            .get(memory_type_index as usize)
+            .ok_or(AllocationError::InvalidMemoryType(memory_type_index))?
+            .property_flags;
+
+        let handle_type = vk::ExternalMemoryHandleTypeFlags::OPAQUE_FD;
+        let mut import_info = vk::ImportMemoryFdInfoKHR::default()
+            .handle_type(handle_type)
+            .fd(fd.as_raw_fd());
+        let allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(size)
+            .memory_type_index(memory_type_index)
+            .push_next(&mut import_info);
+        let memory = unsafe {
+            self.device
+                .raw_handle()
+                .allocate_memory(&allocate_info, None)?
+        };
+        // The fd is owned by the driver now, it must not be closed by us
+        let _ = fd.into_raw_fd();
+
+        Ok(Allocation {
+            device: Arc::clone(&self.device),
+            memory,
+            size,
+            memory_type_index,
+            properties,
+            external: handle_type,
+            non_coherent_atom_size: self.non_coherent_atom_size,
+            mapped: AtomicBool::new(false),
+        })
+    }
+}
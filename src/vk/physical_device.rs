@@ -1,73 +1,139 @@
 pub mod features;
+pub mod properties;
 
-use std::sync::Arc;
+use std::ffi::{CStr, CString};
 
-use ash::vk::{PhysicalDevice, PhysicalDeviceType};
+use ash::vk::{self, ExtensionProperties, PhysicalDevice, QueueFamilyProperties};
 
 use crate::vk::{
-    device::{self, device_extensions, queues::QueueFamilySelector},
+    device::{
+        self, DeviceRater, PhysicalDeviceInfo, device_extensions, queues::QueueFamilySelector,
+    },
     error::fatal_vk_error,
     instance::Instance,
+    swapchain::{PRESENT_ID_EXTENSION, PRESENT_WAIT_EXTENSION},
 };
+use features::{FeaturesInfo, PresentWaitFeatures};
+
+///
+/// Instance queries physical device selection is based on,
+/// implemented by mocks to test selection without a GPU
+///
+pub trait PhysicalDeviceQueries {
+    fn enumerate_physical_devices(&self) -> Result<Vec<PhysicalDevice>, vk::Result>;
+
+    unsafe fn get_physical_device_info(&self, device: PhysicalDevice) -> PhysicalDeviceInfo;
+
+    unsafe fn enumerate_device_extension_properties(
+        &self,
+        device: PhysicalDevice,
+    ) -> Result<Vec<ExtensionProperties>, vk::Result>;
+
+    unsafe fn get_physical_device_queue_family_properties(
+        &self,
+        device: PhysicalDevice,
+    ) -> Vec<QueueFamilyProperties>;
+
+    unsafe fn get_physical_device_present_wait_features(
+        &self,
+        device: PhysicalDevice,
+    ) -> PresentWaitFeatures;
+}
+
+impl PhysicalDeviceQueries for Instance {
+    fn enumerate_physical_devices(&self) -> Result<Vec<PhysicalDevice>, vk::Result> {
+        Instance::enumerate_physical_devices(self)
+    }
+
+    unsafe fn get_physical_device_info(&self, device: PhysicalDevice) -> PhysicalDeviceInfo {
+        unsafe { Instance::get_physical_device_info(self, device) }
+    }
+
+    unsafe fn enumerate_device_extension_properties(
+        &self,
+        device: PhysicalDevice,
+    ) -> Result<Vec<ExtensionProperties>, vk::Result> {
+        unsafe { Instance::enumerate_device_extension_properties(self, device) }
+    }
+
+    unsafe fn get_physical_device_queue_family_properties(
+        &self,
+        device: PhysicalDevice,
+    ) -> Vec<QueueFamilyProperties> {
+        unsafe { Instance::get_physical_device_queue_family_properties(self, device) }
+    }
+
+    unsafe fn get_physical_device_present_wait_features(
+        &self,
+        device: PhysicalDevice,
+    ) -> PresentWaitFeatures {
+        unsafe { Instance::get_physical_device_present_wait_features(self, device) }
+    }
+}
 
 fn rate_physical_device<T: QueueFamilySelector>(
-    instance: &Arc<Instance>,
+    instance: &impl PhysicalDeviceQueries,
     device: PhysicalDevice,
     mut qfc: T,
+    required_features: &FeaturesInfo,
+    extensions: &[CString],
+    rater: &dyn DeviceRater,
 ) -> PhysicalDeviceChoice<T> {
     let info = unsafe { instance.get_physical_device_info(device) };
-    let props = info.properties;
-    let features = info.features;
+    let features = &info.features;
     let mut queue_counts = Vec::new();
+    let mut queue_flags = Vec::new();
 
-    if !(props.device_type == PhysicalDeviceType::DISCRETE_GPU
-        || props.device_type == PhysicalDeviceType::INTEGRATED_GPU)
+    if device_extensions::check_extensions(instance, device, &device::REQUIRED_DEVICE_EXTENSIONS)
+        .is_err()
+        || device_extensions::check_extensions(instance, device, extensions).is_err()
     {
         log::debug!(
-            "Physical device {device:?} was discarded because it's neither discrete nor integrated GPU"
+            "Physical device {device:?} was discarded because it doesn't have required extensions or check has failed"
         );
         return PhysicalDeviceChoice {
             rating: 0,
             queue_counts,
+            queue_flags,
             device,
             queue_family_selector: qfc,
         };
     }
 
-    if device_extensions::check_extensions(instance, device, &device::REQUIRED_DEVICE_EXTENSIONS)
-        .is_err()
-    {
+    if features.check_required(required_features).is_err() {
         log::debug!(
-            "Physical device {device:?} was discarded because it doesn't have required extensions or check has failed"
+            "Physical device {device:?} was discarded because it doesn't have required features or check has failed"
         );
+
         return PhysicalDeviceChoice {
             rating: 0,
             queue_counts,
+            queue_flags,
             device,
             queue_family_selector: qfc,
         };
     }
 
-    if features.check_required().is_err() {
+    if !supports_present_wait(instance, device, extensions) {
         log::debug!(
-            "Physical device {device:?} was discarded because it doesn't have required features or check has failed"
+            "Physical device {device:?} was discarded because it doesn't support the present id or present wait feature"
         );
 
         return PhysicalDeviceChoice {
             rating: 0,
             queue_counts,
+            queue_flags,
             device,
             queue_family_selector: qfc,
         };
     }
 
-    unsafe { instance.get_physical_device_queue_family_properties(device) }
-        .into_iter()
-        .enumerate()
-        .for_each(|(id, prop)| {
-            queue_counts.push(prop.queue_count);
-            qfc.inspect_queue_family(device, id.try_into().unwrap(), prop)
-        });
+    let queue_props = unsafe { instance.get_physical_device_queue_family_properties(device) };
+    queue_props.iter().enumerate().for_each(|(id, prop)| {
+        queue_counts.push(prop.queue_count);
+        queue_flags.push(prop.queue_flags);
+        qfc.inspect_queue_family(device, id.try_into().unwrap(), *prop)
+    });
 
     if !qfc.is_complete() {
         log::debug!("Physical device {device:?} was discarded by queue family selector");
@@ -75,20 +141,46 @@ fn rate_physical_device<T: QueueFamilySelector>(
         return PhysicalDeviceChoice {
             rating: 0,
             queue_counts,
+            queue_flags,
             device,
             queue_family_selector: qfc,
         };
     }
-    log::debug!("Physical device {device:?} is rated 1");
+    let rating = rater.rate(&info, &queue_props);
+    if rating <= 0 {
+        log::debug!("Physical device {device:?} was discarded by device rater");
+    } else {
+        log::debug!("Physical device {device:?} is rated {rating}");
+    }
 
     return PhysicalDeviceChoice {
-        rating: 1,
+        rating,
         queue_counts,
+        queue_flags,
         device,
         queue_family_selector: qfc,
     };
 }
 
+///
+/// The extensions only expose their commands once the features are enabled as well,
+/// so a device that has requested present extensions has to support the features too
+///
+fn supports_present_wait(
+    instance: &impl PhysicalDeviceQueries,
+    device: PhysicalDevice,
+    extensions: &[CString],
+) -> bool {
+    let is_requested = |name: &CStr| extensions.iter().any(|extension| **extension == *name);
+    let present_id = is_requested(PRESENT_ID_EXTENSION);
+    let present_wait = is_requested(PRESENT_WAIT_EXTENSION);
+    if !present_id && !present_wait {
+        return true;
+    }
+    let features = unsafe { instance.get_physical_device_present_wait_features(device) };
+    (!present_id || features.present_id) && (!present_wait || features.present_wait)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum PhysicalDeviceChoiceError {
     #[error("no physical device found")]
@@ -103,16 +195,29 @@ pub struct PhysicalDeviceChoice<T: QueueFamilySelector> {
     pub device: PhysicalDevice,
     pub queue_family_selector: T,
     pub queue_counts: Vec<u32>,
+    pub queue_flags: Vec<vk::QueueFlags>,
 }
 pub fn select_physical_device<T: QueueFamilySelector>(
-    instance: &Arc<Instance>,
+    instance: &impl PhysicalDeviceQueries,
     queue_family_selector: T,
+    required_features: &FeaturesInfo,
+    extensions: &[CString],
+    rater: &dyn DeviceRater,
 ) -> Result<PhysicalDeviceChoice<T>, PhysicalDeviceChoiceError> {
     let Some(physical_device_choice) = instance
         .enumerate_physical_devices()
         .unwrap_or_else(|e| fatal_vk_error("failed to enumerate_physical_devices", e))
         .into_iter()
-        .map(|device| rate_physical_device(instance, device, queue_family_selector.clone()))
+        .map(|device| {
+            rate_physical_device(
+                instance,
+                device,
+                queue_family_selector.clone(),
+                required_features,
+                extensions,
+                rater,
+            )
+        })
         .max_by_key(|s| s.rating)
     else {
         log::error!("No Physical device was found");
@@ -131,3 +236,308 @@ pub fn select_physical_device<T: QueueFamilySelector>(
 
     Ok(physical_device_choice)
 }
+
+#[cfg(test)]
+mod test {
+    use std::ffi::CStr;
+
+    use ash::vk::Handle;
+
+    use ash::vk::PhysicalDeviceType;
+
+    use super::*;
+    use crate::vk::{
+        device::{
+            DefaultDeviceRater,
+            queues::{Queue, Queues},
+        },
+        physical_device::properties::{DriverInfo, SubgroupInfo},
+    };
+
+    struct MockDevice {
+        device_type: PhysicalDeviceType,
+        features: FeaturesInfo,
+        extensions: Vec<&'static CStr>,
+        queue_families: Vec<vk::QueueFlags>,
+        present_wait: PresentWaitFeatures,
+    }
+
+    impl MockDevice {
+        fn new(device_type: PhysicalDeviceType) -> Self {
+            Self {
+                device_type,
+                features: FeaturesInfo::required(vk::PhysicalDeviceFeatures::default()),
+                extensions: device::REQUIRED_DEVICE_EXTENSIONS.to_vec(),
+                queue_families: vec![vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE],
+                present_wait: PresentWaitFeatures {
+                    present_id: true,
+                    present_wait: true,
+                },
+            }
+        }
+    }
+
+    ///
+    /// Physical device i has the handle i + 1
+    ///
+    struct MockInstance {
+        devices: Vec<MockDevice>,
+    }
+
+    impl MockInstance {
+        fn device(&self, device: PhysicalDevice) -> &MockDevice {
+            &self.devices[device.as_raw() as usize - 1]
+        }
+    }
+
+    impl PhysicalDeviceQueries for MockInstance {
+        fn enumerate_physical_devices(&self) -> Result<Vec<PhysicalDevice>, vk::Result> {
+            Ok((1..=self.devices.len() as u64)
+                .map(PhysicalDevice::from_raw)
+                .collect())
+        }
+
+        unsafe fn get_physical_device_info(&self, device: PhysicalDevice) -> PhysicalDeviceInfo {
+            let device = self.device(device);
+            PhysicalDeviceInfo {
+                properties: vk::PhysicalDeviceProperties::default().device_type(device.device_type),
+                driver: DriverInfo {
+                    id: vk::DriverId::default(),
+                    name: String::new(),
+                    info: String::new(),
+                    conformance_version: vk::ConformanceVersion::default(),
+                },
+                subgroup: SubgroupInfo {
+                    size: 32,
+                    supported_stages: vk::ShaderStageFlags::ALL,
+                    supported_operations: vk::SubgroupFeatureFlags::BASIC,
+                    quad_operations_in_all_stages: false,
+                },
+                features: FeaturesInfo {
+                    features: device.features.features,
+                    ..device.features
+                },
+            }
+        }
+
+        unsafe fn enumerate_device_extension_properties(
+            &self,
+            device: PhysicalDevice,
+        ) -> Result<Vec<ExtensionProperties>, vk::Result> {
+            Ok(self
+                .device(device)
+                .extensions
+                .iter()
+                .map(|name| ExtensionProperties::default().extension_name(name).unwrap())
+                .collect())
+        }
+
+        unsafe fn get_physical_device_queue_family_properties(
+            &self,
+            device: PhysicalDevice,
+        ) -> Vec<QueueFamilyProperties> {
+            self.device(device)
+                .queue_families
+                .iter()
+                .map(|&queue_flags| {
+                    QueueFamilyProperties::default()
+                        .queue_flags(queue_flags)
+                        .queue_count(1)
+                })
+                .collect()
+        }
+
+        unsafe fn get_physical_device_present_wait_features(
+            &self,
+            device: PhysicalDevice,
+        ) -> PresentWaitFeatures {
+            self.device(device).present_wait
+        }
+    }
+
+    struct NoQueues;
+
+    impl Queues for NoQueues {}
+
+    ///
+    /// Complete once it has seen a graphics family
+    ///
+    #[derive(Clone, Default)]
+    struct GraphicsSelector {
+        graphics: Option<u32>,
+    }
+
+    impl QueueFamilySelector for GraphicsSelector {
+        type Q = NoQueues;
+
+        fn inspect_queue_family(
+            &mut self,
+            _physical_device: PhysicalDevice,
+            queue_family_id: u32,
+            queue_family_properties: QueueFamilyProperties,
+        ) {
+            if queue_family_properties
+                .queue_flags
+                .contains(vk::QueueFlags::GRAPHICS)
+            {
+                self.graphics.get_or_insert(queue_family_id);
+            }
+        }
+
+        fn is_complete(&self) -> bool {
+            self.graphics.is_some()
+        }
+
+        fn requirements(&self) -> Vec<(u32, Vec<f32>)> {
+            vec![(self.graphics.unwrap(), vec![0.0])]
+        }
+
+        fn fill_queues(&self, _queues_raw: Vec<(u32, Vec<Queue>)>) -> NoQueues {
+            NoQueues
+        }
+    }
+
+    fn select_with(
+        instance: &MockInstance,
+        extensions: &[CString],
+        rater: &dyn DeviceRater,
+    ) -> Result<PhysicalDeviceChoice<GraphicsSelector>, PhysicalDeviceChoiceError> {
+        select_physical_device(
+            instance,
+            GraphicsSelector::default(),
+            &FeaturesInfo::required(vk::PhysicalDeviceFeatures::default()),
+            extensions,
+            rater,
+        )
+    }
+
+    fn select(
+        instance: &MockInstance,
+        extensions: &[CString],
+        preferred_type: Option<PhysicalDeviceType>,
+    ) -> Result<PhysicalDeviceChoice<GraphicsSelector>, PhysicalDeviceChoiceError> {
+        select_with(
+            instance,
+            extensions,
+            &DefaultDeviceRater::new(preferred_type),
+        )
+    }
+
+    #[test]
+    fn no_devices() {
+        let instance = MockInstance {
+            devices: Vec::new(),
+        };
+        assert!(matches!(
+            select(&instance, &[], None),
+            Err(PhysicalDeviceChoiceError::DeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn discarded_devices() {
+        let mut missing_extension = MockDevice::new(PhysicalDeviceType::DISCRETE_GPU);
+        missing_extension.extensions.pop();
+        let mut missing_feature = MockDevice::new(PhysicalDeviceType::DISCRETE_GPU);
+        missing_feature.features.vulkan_memory_model = false;
+        let mut no_graphics = MockDevice::new(PhysicalDeviceType::DISCRETE_GPU);
+        no_graphics.queue_families = vec![vk::QueueFlags::COMPUTE];
+        let instance = MockInstance {
+            devices: vec![
+                MockDevice::new(PhysicalDeviceType::CPU),
+                missing_extension,
+                missing_feature,
+                no_graphics,
+            ],
+        };
+        assert!(matches!(
+            select(&instance, &[], None),
+            Err(PhysicalDeviceChoiceError::SuitableDeviceNotFound)
+        ));
+    }
+
+    #[test]
+    fn selects_suitable_device() {
+        let mut graphics_second = MockDevice::new(PhysicalDeviceType::INTEGRATED_GPU);
+        graphics_second.queue_families = vec![vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS];
+        let instance = MockInstance {
+            devices: vec![MockDevice::new(PhysicalDeviceType::CPU), graphics_second],
+        };
+        let choice = select(&instance, &[], None).unwrap();
+        assert_eq!(choice.device.as_raw(), 2);
+        assert_eq!(choice.queue_family_selector.graphics, Some(1));
+        assert_eq!(choice.queue_counts, vec![1, 1]);
+        assert_eq!(
+            choice.queue_flags,
+            vec![vk::QueueFlags::TRANSFER, vk::QueueFlags::GRAPHICS]
+        );
+    }
+
+    #[test]
+    fn required_extension() {
+        let extension = c"VK_EXT_memory_budget";
+        let mut with_extension = MockDevice::new(PhysicalDeviceType::INTEGRATED_GPU);
+        with_extension.extensions.push(extension);
+        let instance = MockInstance {
+            devices: vec![
+                MockDevice::new(PhysicalDeviceType::DISCRETE_GPU),
+                with_extension,
+            ],
+        };
+        let choice = select(&instance, &[extension.to_owned()], None).unwrap();
+        assert_eq!(choice.device.as_raw(), 2);
+    }
+
+    #[test]
+    fn present_wait_feature() {
+        let extensions = [PRESENT_ID_EXTENSION, PRESENT_WAIT_EXTENSION];
+        let mut without_feature = MockDevice::new(PhysicalDeviceType::DISCRETE_GPU);
+        without_feature.extensions.extend(extensions);
+        without_feature.present_wait.present_wait = false;
+        let mut with_feature = MockDevice::new(PhysicalDeviceType::INTEGRATED_GPU);
+        with_feature.extensions.extend(extensions);
+        let instance = MockInstance {
+            devices: vec![without_feature, with_feature],
+        };
+        let extensions = extensions.map(CStr::to_owned);
+        let choice = select(&instance, &extensions, None).unwrap();
+        assert_eq!(choice.device.as_raw(), 2);
+    }
+
+    #[test]
+    fn preferred_device_type() {
+        let instance = MockInstance {
+            devices: vec![
+                MockDevice::new(PhysicalDeviceType::DISCRETE_GPU),
+                MockDevice::new(PhysicalDeviceType::INTEGRATED_GPU),
+            ],
+        };
+        let choice = select(&instance, &[], Some(PhysicalDeviceType::INTEGRATED_GPU)).unwrap();
+        assert_eq!(choice.device.as_raw(), 2);
+    }
+
+    ///
+    /// Accepts any device type, rates by number of queue families
+    ///
+    struct QueueFamilyRater;
+
+    impl DeviceRater for QueueFamilyRater {
+        fn rate(&self, _info: &PhysicalDeviceInfo, queue_props: &[QueueFamilyProperties]) -> i32 {
+            queue_props.len() as i32
+        }
+    }
+
+    #[test]
+    fn custom_rater() {
+        let mut two_families = MockDevice::new(PhysicalDeviceType::CPU);
+        two_families.queue_families = vec![vk::QueueFlags::GRAPHICS, vk::QueueFlags::TRANSFER];
+        let instance = MockInstance {
+            devices: vec![
+                MockDevice::new(PhysicalDeviceType::DISCRETE_GPU),
+                two_families,
+            ],
+        };
+        let choice = select_with(&instance, &[], &QueueFamilyRater).unwrap();
+        assert_eq!(choice.device.as_raw(), 2);
+    }
+}
@@ -1,14 +1,93 @@
+use std::ffi::c_void;
+
 use ash::{
     Instance,
     vk::{self, ExtendsDeviceCreateInfo},
 };
 
+/// Calls `$m!` with the names of every field of vk::PhysicalDeviceFeatures
+macro_rules! for_each_core_feature {
+    ($m:ident) => {
+        $m!(
+            robust_buffer_access,
+            full_draw_index_uint32,
+            image_cube_array,
+            independent_blend,
+            geometry_shader,
+            tessellation_shader,
+            sample_rate_shading,
+            dual_src_blend,
+            logic_op,
+            multi_draw_indirect,
+            draw_indirect_first_instance,
+            depth_clamp,
+            depth_bias_clamp,
+            fill_mode_non_solid,
+            depth_bounds,
+            wide_lines,
+            large_points,
+            alpha_to_one,
+            multi_viewport,
+            sampler_anisotropy,
+            texture_compression_etc2,
+            texture_compression_astc_ldr,
+            texture_compression_bc,
+            occlusion_query_precise,
+            pipeline_statistics_query,
+            vertex_pipeline_stores_and_atomics,
+            fragment_stores_and_atomics,
+            shader_tessellation_and_geometry_point_size,
+            shader_image_gather_extended,
+            shader_storage_image_extended_formats,
+            shader_storage_image_multisample,
+            shader_storage_image_read_without_format,
+            shader_storage_image_write_without_format,
+            shader_uniform_buffer_array_dynamic_indexing,
+            shader_sampled_image_array_dynamic_indexing,
+            shader_storage_buffer_array_dynamic_indexing,
+            shader_storage_image_array_dynamic_indexing,
+            shader_clip_distance,
+            shader_cull_distance,
+            shader_float64,
+            shader_int64,
+            shader_int16,
+            shader_resource_residency,
+            shader_resource_min_lod,
+            sparse_binding,
+            sparse_residency_buffer,
+            sparse_residency_image2_d,
+            sparse_residency_image3_d,
+            sparse_residency2_samples,
+            sparse_residency4_samples,
+            sparse_residency8_samples,
+            sparse_residency16_samples,
+            sparse_residency_aliased,
+            variable_multisample_rate,
+            inherited_queries,
+        )
+    };
+}
+
+///
+/// presentId and presentWait support, queried only for devices that have
+/// VK_KHR_present_id and VK_KHR_present_wait
+///
+#[derive(Default, Debug, Clone, Copy)]
+pub struct PresentWaitFeatures {
+    pub present_id: bool,
+    pub present_wait: bool,
+}
+
 #[derive(Default, Debug)]
 pub struct FeaturesInfo {
     pub features: vk::PhysicalDeviceFeatures,
     pub vulkan_memory_model: bool,
     pub vulkan_memory_model_device_scope: bool,
     pub vulkan_memory_model_availability_visibility_chains: bool,
+    pub multiview: bool,
+    pub buffer_device_address: bool,
+    pub protected_memory: bool,
+    pub separate_depth_stencil_layouts: bool,
 }
 #[derive(Debug, thiserror::Error)]
 #[error("not all required device features are available")]
@@ -22,6 +101,11 @@ impl FeaturesInfo {
     pub fn from_features2(features2: PhysicalDeviceFeatures2) -> Self {
         let mut s = Self::default();
         let vulkan_memory_model_features = features2.vulkan_memory_model_features;
+        let multiview_features = features2.multiview_features;
+        let buffer_device_address_features = features2.buffer_device_address_features;
+        let protected_memory_features = features2.protected_memory_features;
+        let separate_depth_stencil_layouts_features =
+            features2.separate_depth_stencil_layouts_features;
         let features2 = features2.features2;
         s.features = features2.features;
         s.vulkan_memory_model = vulkan_memory_model_features.vulkan_memory_model > 0;
@@ -29,107 +113,92 @@ impl FeaturesInfo {
             vulkan_memory_model_features.vulkan_memory_model_device_scope > 0;
         s.vulkan_memory_model_availability_visibility_chains =
             vulkan_memory_model_features.vulkan_memory_model_availability_visibility_chains > 0;
+        s.multiview = multiview_features.multiview > 0;
+        s.buffer_device_address = buffer_device_address_features.buffer_device_address > 0;
+        s.protected_memory = protected_memory_features.protected_memory > 0;
+        s.separate_depth_stencil_layouts =
+            separate_depth_stencil_layouts_features.separate_depth_stencil_layouts > 0;
         s
     }
 
-    pub fn check_required(&self) -> Result<(), MissingDeviceFeature> {
-        let required = Self::from_features2(PhysicalDeviceFeatures2::new_required());
-        if (required.vulkan_memory_model && !self.vulkan_memory_model)
-            || (required.vulkan_memory_model_device_scope && !self.vulkan_memory_model_device_scope)
-            || (required.vulkan_memory_model_availability_visibility_chains
-                && !self.vulkan_memory_model_availability_visibility_chains)
-            || (required.features.robust_buffer_access > self.features.robust_buffer_access)
-            || (required.features.full_draw_index_uint32 > self.features.full_draw_index_uint32)
-            || (required.features.image_cube_array > self.features.image_cube_array)
-            || (required.features.independent_blend > self.features.independent_blend)
-            || (required.features.geometry_shader > self.features.geometry_shader)
-            || (required.features.tessellation_shader > self.features.tessellation_shader)
-            || (required.features.sample_rate_shading > self.features.sample_rate_shading)
-            || (required.features.dual_src_blend > self.features.dual_src_blend)
-            || (required.features.logic_op > self.features.logic_op)
-            || (required.features.multi_draw_indirect > self.features.multi_draw_indirect)
-            || (required.features.draw_indirect_first_instance
-                > self.features.draw_indirect_first_instance)
-            || (required.features.depth_clamp > self.features.depth_clamp)
-            || (required.features.depth_bias_clamp > self.features.depth_bias_clamp)
-            || (required.features.fill_mode_non_solid > self.features.fill_mode_non_solid)
-            || (required.features.depth_bounds > self.features.depth_bounds)
-            || (required.features.wide_lines > self.features.wide_lines)
-            || (required.features.large_points > self.features.large_points)
-            || (required.features.alpha_to_one > self.features.alpha_to_one)
-            || (required.features.multi_viewport > self.features.multi_viewport)
-            || (required.features.sampler_anisotropy > self.features.sampler_anisotropy)
-            || (required.features.texture_compression_etc2 > self.features.texture_compression_etc2)
-            || (required.features.texture_compression_astc_ldr
-                > self.features.texture_compression_astc_ldr)
-            || (required.features.texture_compression_bc > self.features.texture_compression_bc)
-            || (required.features.occlusion_query_precise > self.features.occlusion_query_precise)
-            || (required.features.pipeline_statistics_query
-                > self.features.pipeline_statistics_query)
-            || (required.features.vertex_pipeline_stores_and_atomics
-                > self.features.vertex_pipeline_stores_and_atomics)
-            || (required.features.fragment_stores_and_atomics
-                > self.features.fragment_stores_and_atomics)
-            || (required
-                .features
-                .shader_tessellation_and_geometry_point_size
-                > self.features.shader_tessellation_and_geometry_point_size)
-            || (required.features.shader_image_gather_extended
-                > self.features.shader_image_gather_extended)
-            || (required.features.shader_storage_image_extended_formats
-                > self.features.shader_storage_image_extended_formats)
-            || (required.features.shader_storage_image_multisample
-                > self.features.shader_storage_image_multisample)
-            || (required.features.shader_storage_image_read_without_format
-                > self.features.shader_storage_image_read_without_format)
-            || (required.features.shader_storage_image_write_without_format
-                > self.features.shader_storage_image_write_without_format)
-            || (required
-                .features
-                .shader_uniform_buffer_array_dynamic_indexing
-                > self.features.shader_uniform_buffer_array_dynamic_indexing)
-            || (required
-                .features
-                .shader_sampled_image_array_dynamic_indexing
-                > self.features.shader_sampled_image_array_dynamic_indexing)
-            || (required
-                .features
-                .shader_storage_buffer_array_dynamic_indexing
-                > self.features.shader_storage_buffer_array_dynamic_indexing)
-            || (required
-                .features
-                .shader_storage_image_array_dynamic_indexing
-                > self.features.shader_storage_image_array_dynamic_indexing)
-            || (required.features.shader_clip_distance > self.features.shader_clip_distance)
-            || (required.features.shader_cull_distance > self.features.shader_cull_distance)
-            || (required.features.shader_float64 > self.features.shader_float64)
-            || (required.features.shader_int64 > self.features.shader_int64)
-            || (required.features.shader_int16 > self.features.shader_int16)
-            || (required.features.shader_resource_residency
-                > self.features.shader_resource_residency)
-            || (required.features.shader_resource_min_lod > self.features.shader_resource_min_lod)
-            || (required.features.sparse_binding > self.features.sparse_binding)
-            || (required.features.sparse_residency_buffer > self.features.sparse_residency_buffer)
-            || (required.features.sparse_residency_image2_d
-                > self.features.sparse_residency_image2_d)
-            || (required.features.sparse_residency_image3_d
-                > self.features.sparse_residency_image3_d)
-            || (required.features.sparse_residency2_samples
-                > self.features.sparse_residency2_samples)
-            || (required.features.sparse_residency4_samples
-                > self.features.sparse_residency4_samples)
-            || (required.features.sparse_residency8_samples
-                > self.features.sparse_residency8_samples)
-            || (required.features.sparse_residency16_samples
-                > self.features.sparse_residency16_samples)
-            || (required.features.sparse_residency_aliased > self.features.sparse_residency_aliased)
-            || (required.features.variable_multisample_rate
-                > self.features.variable_multisample_rate)
-            || (required.features.inherited_queries > self.features.inherited_queries)
-        {
-            Err(MissingDeviceFeature)
-        } else {
+    pub fn enabled_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        macro_rules! push_enabled {
+            ($($feature:ident,)*) => {
+                $(
+                    if self.features.$feature > 0 {
+                        names.push(stringify!($feature));
+                    }
+                )*
+            };
+        }
+        for_each_core_feature!(push_enabled);
+        if self.vulkan_memory_model {
+            names.push("vulkan_memory_model");
+        }
+        if self.vulkan_memory_model_device_scope {
+            names.push("vulkan_memory_model_device_scope");
+        }
+        if self.vulkan_memory_model_availability_visibility_chains {
+            names.push("vulkan_memory_model_availability_visibility_chains");
+        }
+        if self.multiview {
+            names.push("multiview");
+        }
+        if self.buffer_device_address {
+            names.push("buffer_device_address");
+        }
+        if self.protected_memory {
+            names.push("protected_memory");
+        }
+        if self.separate_depth_stencil_layouts {
+            names.push("separate_depth_stencil_layouts");
+        }
+        names
+    }
+
+    ///
+    /// Features every device has to support, merged with the caller's features
+    ///
+    pub fn required(features: vk::PhysicalDeviceFeatures) -> Self {
+        let mut required = Self {
+            features,
+            vulkan_memory_model: true,
+            ..Default::default()
+        };
+        required.features.geometry_shader = vk::TRUE;
+        required
+    }
+
+    ///
+    /// True if every feature enabled in self is also enabled in available
+    ///
+    pub fn is_subset_of(&self, available: &FeaturesInfo) -> bool {
+        macro_rules! core_subset {
+            ($($feature:ident,)*) => {
+                $(
+                    (self.features.$feature == vk::FALSE
+                        || available.features.$feature != vk::FALSE)
+                )&&*
+            };
+        }
+        for_each_core_feature!(core_subset)
+            && (!self.vulkan_memory_model || available.vulkan_memory_model)
+            && (!self.vulkan_memory_model_device_scope
+                || available.vulkan_memory_model_device_scope)
+            && (!self.vulkan_memory_model_availability_visibility_chains
+                || available.vulkan_memory_model_availability_visibility_chains)
+            && (!self.multiview || available.multiview)
+            && (!self.buffer_device_address || available.buffer_device_address)
+            && (!self.protected_memory || available.protected_memory)
+            && (!self.separate_depth_stencil_layouts || available.separate_depth_stencil_layouts)
+    }
+
+    pub fn check_required(&self, required: &FeaturesInfo) -> Result<(), MissingDeviceFeature> {
+        if required.is_subset_of(self) {
             Ok(())
+        } else {
+            Err(MissingDeviceFeature)
         }
     }
 }
@@ -138,6 +207,11 @@ impl FeaturesInfo {
 pub struct PhysicalDeviceFeatures2<'a> {
     features2: Box<vk::PhysicalDeviceFeatures2<'a>>,
     vulkan_memory_model_features: Box<vk::PhysicalDeviceVulkanMemoryModelFeatures<'a>>,
+    multiview_features: Box<vk::PhysicalDeviceMultiviewFeatures<'a>>,
+    buffer_device_address_features: Box<vk::PhysicalDeviceBufferDeviceAddressFeatures<'a>>,
+    protected_memory_features: Box<vk::PhysicalDeviceProtectedMemoryFeatures<'a>>,
+    separate_depth_stencil_layouts_features:
+        Box<vk::PhysicalDeviceSeparateDepthStencilLayoutsFeatures<'a>>,
 }
 
 impl<'a> Default for PhysicalDeviceFeatures2<'a> {
@@ -148,18 +222,7 @@ impl<'a> Default for PhysicalDeviceFeatures2<'a> {
 
 impl<'a> PhysicalDeviceFeatures2<'a> {
     pub fn new() -> Self {
-        let mut vulkan_memory_model_features =
-            Box::new(vk::PhysicalDeviceVulkanMemoryModelFeatures::default());
-
-        let next_ptr = vulkan_memory_model_features.as_mut()
-            as *mut vk::PhysicalDeviceVulkanMemoryModelFeatures;
-        let features2 =
-            Box::new(vk::PhysicalDeviceFeatures2::default().push_next(unsafe { &mut *next_ptr }));
-
-        Self {
-            vulkan_memory_model_features,
-            features2,
-        }
+        Self::from_info(&FeaturesInfo::default())
     }
 
     pub unsafe fn fill(&mut self, device: vk::PhysicalDevice, instance: &Instance) {
@@ -174,21 +237,65 @@ impl<'a> PhysicalDeviceFeatures2<'a> {
     }
 
     pub fn new_required() -> Self {
-        let vulkan_memory_model_features =
-            vk::PhysicalDeviceVulkanMemoryModelFeatures::default().vulkan_memory_model(true);
+        Self::from_info(&FeaturesInfo::required(
+            vk::PhysicalDeviceFeatures::default(),
+        ))
+    }
+
+    pub fn from_info(info: &FeaturesInfo) -> Self {
+        let vulkan_memory_model_features = vk::PhysicalDeviceVulkanMemoryModelFeatures::default()
+            .vulkan_memory_model(info.vulkan_memory_model)
+            .vulkan_memory_model_device_scope(info.vulkan_memory_model_device_scope)
+            .vulkan_memory_model_availability_visibility_chains(
+                info.vulkan_memory_model_availability_visibility_chains,
+            );
         let mut vulkan_memory_model_features = Box::new(vulkan_memory_model_features);
+        let mut multiview_features =
+            Box::new(vk::PhysicalDeviceMultiviewFeatures::default().multiview(info.multiview));
+
+        let mut buffer_device_address_features = Box::new(
+            vk::PhysicalDeviceBufferDeviceAddressFeatures::default()
+                .buffer_device_address(info.buffer_device_address),
+        );
+
+        let mut protected_memory_features = Box::new(
+            vk::PhysicalDeviceProtectedMemoryFeatures::default()
+                .protected_memory(info.protected_memory),
+        );
 
+        let mut separate_depth_stencil_layouts_features = Box::new(
+            vk::PhysicalDeviceSeparateDepthStencilLayoutsFeatures::default()
+                .separate_depth_stencil_layouts(info.separate_depth_stencil_layouts),
+        );
+
+        // features2 -> vulkan_memory_model_features -> multiview_features
+        //   -> buffer_device_address_features -> protected_memory_features
+        //   -> separate_depth_stencil_layouts_features
+        protected_memory_features.p_next = separate_depth_stencil_layouts_features.as_mut()
+            as *mut vk::PhysicalDeviceSeparateDepthStencilLayoutsFeatures
+            as *mut c_void;
+        buffer_device_address_features.p_next = protected_memory_features.as_mut()
+            as *mut vk::PhysicalDeviceProtectedMemoryFeatures
+            as *mut c_void;
+        multiview_features.p_next = buffer_device_address_features.as_mut()
+            as *mut vk::PhysicalDeviceBufferDeviceAddressFeatures
+            as *mut c_void;
+        vulkan_memory_model_features.p_next =
+            multiview_features.as_mut() as *mut vk::PhysicalDeviceMultiviewFeatures as *mut c_void;
         let next_ptr = vulkan_memory_model_features.as_mut()
             as *mut vk::PhysicalDeviceVulkanMemoryModelFeatures;
 
-        let features = vk::PhysicalDeviceFeatures::default().geometry_shader(true);
         let features2 = vk::PhysicalDeviceFeatures2::default()
-            .features(features)
+            .features(info.features)
             .push_next(unsafe { &mut *next_ptr });
         let features2 = Box::new(features2);
 
         Self {
             vulkan_memory_model_features,
+            multiview_features,
+            buffer_device_address_features,
+            protected_memory_features,
+            separate_depth_stencil_layouts_features,
             features2,
         }
     }
@@ -197,7 +304,34 @@ impl<'a> PhysicalDeviceFeatures2<'a> {
         self.features2.as_ref().features
     }
 
+    ///
+    /// The extension feature structs, chained after each other
+    ///
     pub fn next(&self) -> impl ExtendsDeviceCreateInfo {
         *self.vulkan_memory_model_features.as_ref()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn required_subset() {
+        let required =
+            FeaturesInfo::required(vk::PhysicalDeviceFeatures::default().sampler_anisotropy(true));
+
+        let mut available = FeaturesInfo::required(vk::PhysicalDeviceFeatures::default());
+        assert!(!required.is_subset_of(&available));
+        assert!(available.check_required(&required).is_err());
+
+        available.features.sampler_anisotropy = vk::TRUE;
+        available.features.wide_lines = vk::TRUE;
+        available.vulkan_memory_model_device_scope = true;
+        assert!(required.is_subset_of(&available));
+        assert!(available.check_required(&required).is_ok());
+
+        available.vulkan_memory_model = false;
+        assert!(!required.is_subset_of(&available));
+    }
+}
@@ -0,0 +1,96 @@
+use std::ffi::c_void;
+
+use ash::{Instance, vk};
+
+#[derive(Debug, Clone)]
+pub struct DriverInfo {
+    pub id: vk::DriverId,
+    pub name: String,
+    pub info: String,
+    pub conformance_version: vk::ConformanceVersion,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SubgroupInfo {
+    pub size: u32,
+    pub supported_stages: vk::ShaderStageFlags,
+    pub supported_operations: vk::SubgroupFeatureFlags,
+    pub quad_operations_in_all_stages: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct PropertiesInfo {
+    pub properties: vk::PhysicalDeviceProperties,
+    pub driver: DriverInfo,
+    pub subgroup: SubgroupInfo,
+}
+
+impl PropertiesInfo {
+    pub fn from_properties2(properties2: PhysicalDeviceProperties2) -> Self {
+        let driver = properties2.driver_properties;
+        let subgroup = properties2.subgroup_properties;
+        Self {
+            properties: properties2.properties2.properties,
+            driver: DriverInfo {
+                id: driver.driver_id,
+                name: driver
+                    .driver_name_as_c_str()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                info: driver
+                    .driver_info_as_c_str()
+                    .map(|info| info.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                conformance_version: driver.conformance_version,
+            },
+            subgroup: SubgroupInfo {
+                size: subgroup.subgroup_size,
+                supported_stages: subgroup.supported_stages,
+                supported_operations: subgroup.supported_operations,
+                quad_operations_in_all_stages: subgroup.quad_operations_in_all_stages > 0,
+            },
+        }
+    }
+}
+
+///
+/// vk::PhysicalDeviceProperties2 together with the structs chained to it
+///
+pub struct PhysicalDeviceProperties2<'a> {
+    properties2: Box<vk::PhysicalDeviceProperties2<'a>>,
+    driver_properties: Box<vk::PhysicalDeviceDriverProperties<'a>>,
+    subgroup_properties: Box<vk::PhysicalDeviceSubgroupProperties<'a>>,
+}
+
+impl<'a> Default for PhysicalDeviceProperties2<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> PhysicalDeviceProperties2<'a> {
+    pub fn new() -> Self {
+        let mut driver_properties = Box::new(vk::PhysicalDeviceDriverProperties::default());
+        let mut subgroup_properties = Box::new(vk::PhysicalDeviceSubgroupProperties::default());
+
+        // properties2 -> driver_properties -> subgroup_properties
+        driver_properties.p_next = subgroup_properties.as_mut()
+            as *mut vk::PhysicalDeviceSubgroupProperties
+            as *mut c_void;
+        let next_ptr = driver_properties.as_mut() as *mut vk::PhysicalDeviceDriverProperties;
+        let properties2 =
+            Box::new(vk::PhysicalDeviceProperties2::default().push_next(unsafe { &mut *next_ptr }));
+
+        Self {
+            properties2,
+            driver_properties,
+            subgroup_properties,
+        }
+    }
+
+    pub unsafe fn fill(&mut self, device: vk::PhysicalDevice, instance: &Instance) {
+        unsafe {
+            instance.get_physical_device_properties2(device, self.properties2.as_mut());
+        }
+    }
+}
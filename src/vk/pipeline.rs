@@ -1,29 +1,174 @@
 mod fixed_function_state;
 pub mod layout;
 pub mod render_pass;
+pub mod vertex_layout;
 use ash::vk;
 use layout::PipelineLayout;
-use render_pass::RenderPass;
-use std::{collections::HashMap, error::Error, sync::Arc};
+use render_pass::{DEFAULT_CLEAR_COLOR, RenderPass, has_stencil_component};
+use std::{any::Any, collections::HashMap, ffi::CStr, mem, sync::Arc};
+use vertex_layout::VertexLayout;
 
-use fixed_function_state::FixedFuctionState;
+use fixed_function_state::{FixedFuctionState, default_color_blend_attachment};
 
 use crate::vk::{
     command_buffer::CommandBuffer,
     command_pool::CommandPool,
+    descriptor::DescriptorSetLayout,
     device::Device,
-    framebuffer::Framebuffer,
-    shader::{MissingShaderStageError, ShaderStage, ShaderStageInfo},
-    swapchain::Swapchain,
+    framebuffer::SwapchainFramebuffers,
+    shader::{ShaderStage, ShaderStageInfo},
+    swapchain::{ColorSpaceMode, Swapchain},
 };
 
-use super::command_buffer::DrawInfo;
+use super::command_buffer::{CommandRecordingError, DrawInfo};
+#[cfg(feature = "reflect")]
+use crate::vk::shader::reflect::ReflectError;
+use render_pass::RenderPassCreationError;
+use vertex_layout::VertexLayoutError;
+
+#[derive(Debug, thiserror::Error)]
+#[error("stencil test requires subpass {0} to have a depth attachment with a stencil component")]
+pub struct MissingStencilAttachmentError(pub u32);
+
+#[derive(Debug, thiserror::Error)]
+#[error("depth test requires subpass {0} to have a depth attachment")]
+pub struct MissingDepthAttachmentError(pub u32);
+
+#[derive(Debug, thiserror::Error)]
+#[error("conservative rasterization requires VK_EXT_conservative_rasterization to be enabled")]
+pub struct ConservativeRasterizationNotEnabledError;
+
+#[derive(Debug, thiserror::Error)]
+#[error("polygon mode {0:?} requires the fill_mode_non_solid device feature")]
+pub struct FillModeNonSolidNotEnabledError(pub vk::PolygonMode);
+
+#[derive(Debug, thiserror::Error)]
+#[error("a depth bias clamp of {0} requires the depth_bias_clamp device feature")]
+pub struct DepthBiasClampNotEnabledError(pub f32);
+
+#[derive(Debug, thiserror::Error)]
+#[error("primitive restart is only supported for strip and fan topologies, got {0:?}")]
+pub struct PrimitiveRestartError(pub vk::PrimitiveTopology);
+
+///
+/// MissingStage, DuplicateStage and the validation errors are programming errors,
+/// PipelineCreation can be e.g. a recoverable out of memory
+///
+#[derive(Debug, thiserror::Error)]
+pub enum PipelineBuildError {
+    #[error("shader stage {0} is required but missing")]
+    MissingStage(ShaderStage),
+    #[error("shader stage {0} was added more than once")]
+    DuplicateStage(ShaderStage),
+    #[error(transparent)]
+    VertexLayout(#[from] VertexLayoutError),
+    #[error(transparent)]
+    PrimitiveRestart(#[from] PrimitiveRestartError),
+    #[error(transparent)]
+    FillModeNonSolidNotEnabled(#[from] FillModeNonSolidNotEnabledError),
+    #[error(transparent)]
+    DepthBiasClampNotEnabled(#[from] DepthBiasClampNotEnabledError),
+    #[error(transparent)]
+    ConservativeRasterizationNotEnabled(#[from] ConservativeRasterizationNotEnabledError),
+    #[error(transparent)]
+    MissingStencilAttachment(#[from] MissingStencilAttachmentError),
+    #[error(transparent)]
+    MissingDepthAttachment(#[from] MissingDepthAttachmentError),
+    #[error("subpass {subpass} does not exist, the render pass has {count}")]
+    InvalidSubpass { subpass: u32, count: u32 },
+    #[error(
+        "{blend_states} color blend states were given for the {attachments} color attachments of subpass {subpass}"
+    )]
+    ColorBlendAttachmentCount {
+        subpass: u32,
+        attachments: u32,
+        blend_states: usize,
+    },
+    #[error("failed to create render pass: {0}")]
+    RenderPassCreation(#[from] RenderPassCreationError),
+    #[cfg(feature = "reflect")]
+    #[error("failed to reflect pipeline layout: {0}")]
+    Reflect(#[from] ReflectError),
+    #[error("failed to create pipeline: {0}")]
+    PipelineCreation(vk::Result),
+    #[error("failed to record command buffers: {0}")]
+    Recording(#[from] CommandRecordingError),
+}
+
+///
+/// requested blend states if there is one per color attachment, by default
+/// default_color_blend_attachment for every attachment
+///
+fn color_blend_attachments(
+    requested: Option<&[vk::PipelineColorBlendAttachmentState]>,
+    attachments: u32,
+) -> Option<Vec<vk::PipelineColorBlendAttachmentState>> {
+    match requested {
+        Some(requested) if requested.len() == attachments as usize => Some(requested.to_vec()),
+        Some(_) => None,
+        None => Some(vec![default_color_blend_attachment(); attachments as usize]),
+    }
+}
+
+///
+/// Without wide_lines only 1 is allowed, otherwise width is clamped to range
+///
+fn clamp_line_width(width: f32, wide_lines: bool, range: [f32; 2]) -> f32 {
+    if wide_lines {
+        width.clamp(range[0], range[1])
+    } else {
+        1.0
+    }
+}
+
+///
+/// Offsets the depth of fragments, factors are in the units of vkCmdSetDepthBias.
+/// A clamp other than 0 needs the depth_bias_clamp feature
+///
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DepthBias {
+    pub constant_factor: f32,
+    pub slope_factor: f32,
+    pub clamp: f32,
+}
+
+///
+/// Pulls the lines of a wireframe overlay in front of the solid surface they lie on,
+/// for a LESS depth compare
+///
+pub const WIREFRAME_OVERLAY_DEPTH_BIAS: DepthBias = DepthBias {
+    constant_factor: -1.0,
+    slope_factor: -1.0,
+    clamp: 0.0,
+};
+
+pub const CONSERVATIVE_RASTERIZATION_EXTENSION: &CStr = c"VK_EXT_conservative_rasterization";
 
 pub struct GraphicsPipelineBuilder {
     device: Arc<Device>,
     command_pool: Arc<CommandPool>,
     swapchain: Arc<Swapchain>,
     shader_stages: HashMap<String, ShaderStageInfo>,
+    set_layouts: Vec<Arc<DescriptorSetLayout>>,
+    vertex_layouts: Vec<VertexLayout>,
+    render_pass: Option<Arc<RenderPass>>,
+    subpass: u32,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    line_width: f32,
+    dynamic_line_width: bool,
+    polygon_mode: vk::PolygonMode,
+    depth_bias: Option<DepthBias>,
+    depth_test: Option<(vk::CompareOp, bool)>,
+    flip_viewport_y: bool,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    dynamic_stencil: bool,
+    color_blend_attachments: Option<Vec<vk::PipelineColorBlendAttachmentState>>,
+    draws: Vec<DrawInfo>,
+    clear_color: [f32; 4],
+    conservative_rasterization: Option<(vk::ConservativeRasterizationModeEXT, f32)>,
+    #[cfg(feature = "reflect")]
+    reflect_layout: bool,
 }
 
 impl GraphicsPipelineBuilder {
@@ -37,149 +182,740 @@ impl GraphicsPipelineBuilder {
             command_pool,
             swapchain,
             shader_stages: HashMap::new(),
+            set_layouts: Vec::new(),
+            vertex_layouts: Vec::new(),
+            render_pass: None,
+            subpass: 0,
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            line_width: 1.0,
+            dynamic_line_width: false,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            depth_test: None,
+            flip_viewport_y: false,
+            stencil: None,
+            dynamic_stencil: false,
+            color_blend_attachments: None,
+            draws: vec![DrawInfo {
+                vertex_count: 3,
+                instance_count: 1,
+                ..Default::default()
+            }],
+            clear_color: DEFAULT_CLEAR_COLOR,
+            conservative_rasterization: None,
+            #[cfg(feature = "reflect")]
+            reflect_layout: false,
         }
     }
     pub fn add_stage(mut self, name: String, stage: ShaderStageInfo) -> Self {
         self.shader_stages.insert(name, stage);
         self
     }
-    fn require_stage(&self, stage: ShaderStage) -> Result<(), MissingShaderStageError> {
+    ///
+    /// TRIANGLE_LIST by default. Point sizes other than 1 are written to gl_PointSize
+    /// and need the large_points feature enabled with DeviceBuilder::features
+    ///
+    pub fn topology(mut self, topology: vk::PrimitiveTopology) -> Self {
+        self.topology = topology;
+        self
+    }
+    ///
+    /// Lets the special index 0xFFFF/0xFFFFFFFF start a new strip or fan
+    ///
+    pub fn primitive_restart(mut self, primitive_restart: bool) -> Self {
+        self.primitive_restart = primitive_restart;
+        self
+    }
+    ///
+    /// Widths other than 1 need the wide_lines feature enabled with DeviceBuilder::features,
+    /// without it the width falls back to 1 with a warning.
+    /// Clamped to the line_width_range limit of the device
+    ///
+    pub fn line_width(mut self, line_width: f32) -> Self {
+        self.line_width = line_width;
+        self
+    }
+    ///
+    /// Makes the line width dynamic, it is then set with CommandBuffer::cmd_set_line_width
+    /// and line_width is ignored
+    ///
+    pub fn dynamic_line_width(mut self, dynamic_line_width: bool) -> Self {
+        self.dynamic_line_width = dynamic_line_width;
+        self
+    }
+    ///
+    /// FILL by default, LINE and POINT need the fill_mode_non_solid feature enabled
+    /// with DeviceBuilder::features
+    ///
+    pub fn polygon_mode(mut self, polygon_mode: vk::PolygonMode) -> Self {
+        self.polygon_mode = polygon_mode;
+        self
+    }
+    ///
+    /// Only has an effect on pipelines that test depth, see depth_test
+    ///
+    pub fn depth_bias(mut self, depth_bias: DepthBias) -> Self {
+        self.depth_bias = Some(depth_bias);
+        self
+    }
+    ///
+    /// Enables the depth test with compare_op, write also stores passing depths.
+    /// The subpass needs a depth attachment
+    ///
+    pub fn depth_test(mut self, compare_op: vk::CompareOp, write: bool) -> Self {
+        self.depth_test = Some((compare_op, write));
+        self
+    }
+    ///
+    /// LINE polygon mode with WIREFRAME_OVERLAY_DEPTH_BIAS and a LESS depth test without
+    /// depth writes, for drawing a mesh as wireframe over a solid pass of the same mesh
+    ///
+    pub fn wireframe_overlay(self) -> Self {
+        self.polygon_mode(vk::PolygonMode::LINE)
+            .depth_bias(WIREFRAME_OVERLAY_DEPTH_BIAS)
+            .depth_test(vk::CompareOp::LESS, false)
+    }
+    ///
+    /// Uses a negative-height viewport so that clip-space Y points up
+    ///
+    pub fn flip_viewport_y(mut self, flip_viewport_y: bool) -> Self {
+        self.flip_viewport_y = flip_viewport_y;
+        self
+    }
+    ///
+    /// Enables the stencil test with separate front and back face operations
+    ///
+    pub fn stencil(mut self, front: vk::StencilOpState, back: vk::StencilOpState) -> Self {
+        self.stencil = Some((front, back));
+        self
+    }
+    ///
+    /// Makes stencil reference, compare and write masks dynamic,
+    /// the values in the StencilOpStates are then ignored
+    ///
+    pub fn dynamic_stencil(mut self, dynamic_stencil: bool) -> Self {
+        self.dynamic_stencil = dynamic_stencil;
+        self
+    }
+    ///
+    /// One blend state per color attachment of the subpass. By default blending is
+    /// disabled and every component of every attachment is written
+    ///
+    pub fn color_blend_attachments(
+        mut self,
+        states: Vec<vk::PipelineColorBlendAttachmentState>,
+    ) -> Self {
+        self.color_blend_attachments = Some(states);
+        self
+    }
+    ///
+    /// Draws recorded by create_command_buffers, a single 3-vertex draw by default.
+    /// Replaced by GraphicsPipeline::set_recording
+    ///
+    pub fn draws(mut self, draws: Vec<DrawInfo>) -> Self {
+        self.draws = draws;
+        self
+    }
+    ///
+    /// Rasterizes every pixel a primitive touches (OVERESTIMATE) or only the ones it covers
+    /// fully (UNDERESTIMATE). extra_size grows overestimation by that many pixels.
+    /// Needs CONSERVATIVE_RASTERIZATION_EXTENSION enabled with DeviceBuilder::extension
+    ///
+    pub fn conservative_rasterization(
+        mut self,
+        mode: vk::ConservativeRasterizationModeEXT,
+        extra_size: f32,
+    ) -> Self {
+        self.conservative_rasterization = Some((mode, extra_size));
+        self
+    }
+    pub fn clear_color(mut self, clear_color: [f32; 4]) -> Self {
+        self.clear_color = clear_color;
+        self
+    }
+    pub fn descriptor_set_layout(mut self, set_layout: Arc<DescriptorSetLayout>) -> Self {
+        self.set_layouts.push(set_layout);
+        self
+    }
+    ///
+    /// Builds the pipeline layout from the descriptor bindings and push constants
+    /// the shader stages use, set layouts added with descriptor_set_layout are ignored.
+    /// GraphicsPipeline::get_layout gives access to the created set layouts
+    ///
+    #[cfg(feature = "reflect")]
+    pub fn reflect_layout(mut self) -> Self {
+        self.reflect_layout = true;
+        self
+    }
+    ///
+    /// Builds the pipeline against a render pass shared with other pipelines instead of
+    /// creating one for the swapchain. The pipeline then has no framebuffers and
+    /// command buffers of its own, it is bound with cmd_bind_graphics_pipeline inside
+    /// the caller's render pass
+    ///
+    pub fn render_pass(mut self, render_pass: Arc<RenderPass>) -> Self {
+        self.render_pass = Some(render_pass);
+        self
+    }
+    ///
+    /// Subpass of the render pass the pipeline is used in, 0 by default
+    ///
+    pub fn subpass(mut self, subpass: u32) -> Self {
+        self.subpass = subpass;
+        self
+    }
+    ///
+    /// Vertex buffer binding read by the vertex shader, built with VertexLayoutBuilder
+    ///
+    pub fn vertex_layout(mut self, vertex_layout: VertexLayout) -> Self {
+        self.vertex_layouts.push(vertex_layout);
+        self
+    }
+    fn require_stage(&self, stage: ShaderStage) -> Result<(), PipelineBuildError> {
         if !self
             .shader_stages
             .iter()
             .any(|(_, info)| info.stage() == stage)
         {
-            Err(MissingShaderStageError::new(stage))
+            Err(PipelineBuildError::MissingStage(stage))
         } else {
             Ok(())
         }
     }
-    pub fn build(self) -> Result<GraphicsPipeline, Box<dyn Error>> {
+    fn check_duplicate_stages(&self) -> Result<(), PipelineBuildError> {
+        let stages: Vec<_> = self
+            .shader_stages
+            .values()
+            .map(|info| info.stage())
+            .collect();
+        for (i, stage) in stages.iter().enumerate() {
+            if stages[..i].contains(stage) {
+                return Err(PipelineBuildError::DuplicateStage(*stage));
+            }
+        }
+        Ok(())
+    }
+    fn create_layout(&self) -> Result<PipelineLayout, PipelineBuildError> {
+        #[cfg(feature = "reflect")]
+        if self.reflect_layout {
+            let stages = self
+                .shader_stages
+                .values()
+                .map(|info| Ok((info.stage().into(), info.reflect()?)))
+                .collect::<Result<Vec<_>, ReflectError>>()?;
+            return Ok(PipelineLayout::from_reflections(
+                Arc::clone(&self.device),
+                &stages,
+            )?);
+        }
+        Ok(PipelineLayout::with_set_layouts(
+            Arc::clone(&self.device),
+            self.set_layouts.clone(),
+        ))
+    }
+    pub fn build(self) -> Result<GraphicsPipeline, PipelineBuildError> {
         self.require_stage(ShaderStage::Vertex)?;
         self.require_stage(ShaderStage::Fragment)?;
-        let fixed_function_state = FixedFuctionState::new();
-        let (
-            vertex_input_state,
-            input_assembly_state,
-            viewport_state,
-            rasterization_state,
-            multisample_state,
-            color_blend_state,
-            dynamic_state,
-        ) = (
-            fixed_function_state.get_vertex_input_state(),
-            fixed_function_state.get_input_assembly_state(),
-            fixed_function_state.get_viewport_state(),
-            fixed_function_state.get_rasterization_state(),
-            fixed_function_state.get_multisample_state(),
-            fixed_function_state.get_color_blend_state(),
-            fixed_function_state.get_dynamic_state(),
-        );
-
-        let render_pass = Arc::new(RenderPass::new(
-            Arc::clone(&self.device),
-            Arc::clone(&self.swapchain),
-        )?);
-
-        let layout = PipelineLayout::new(Arc::clone(&self.device));
-
-        let stages: Vec<_> = self.shader_stages.values().map(|val| val.info()).collect();
+        self.check_duplicate_stages()?;
+        let mut fixed_function_state = FixedFuctionState::new();
+        fixed_function_state.set_vertex_layouts(&self.vertex_layouts)?;
+        if self.primitive_restart
+            && !matches!(
+                self.topology,
+                vk::PrimitiveTopology::LINE_STRIP
+                    | vk::PrimitiveTopology::TRIANGLE_STRIP
+                    | vk::PrimitiveTopology::TRIANGLE_FAN
+                    | vk::PrimitiveTopology::LINE_STRIP_WITH_ADJACENCY
+                    | vk::PrimitiveTopology::TRIANGLE_STRIP_WITH_ADJACENCY
+            )
+        {
+            return Err(PrimitiveRestartError(self.topology).into());
+        }
+        fixed_function_state.set_topology(self.topology, self.primitive_restart);
+        if self.dynamic_line_width {
+            fixed_function_state.set_dynamic_line_width();
+        } else {
+            let line_width = clamp_line_width(
+                self.line_width,
+                self.device.get_enabled_features().wide_lines == vk::TRUE,
+                self.device.get_properties().limits.line_width_range,
+            );
+            if line_width != self.line_width {
+                log::warn!(
+                    "Line width {} is unsupported, clamped to {}",
+                    self.line_width,
+                    line_width
+                );
+            }
+            fixed_function_state.set_line_width(line_width);
+        }
+        let features = self.device.get_enabled_features();
+        if self.polygon_mode != vk::PolygonMode::FILL && features.fill_mode_non_solid == vk::FALSE {
+            return Err(FillModeNonSolidNotEnabledError(self.polygon_mode).into());
+        }
+        fixed_function_state.set_polygon_mode(self.polygon_mode);
+        if let Some(depth_bias) = self.depth_bias {
+            if depth_bias.clamp != 0.0 && features.depth_bias_clamp == vk::FALSE {
+                return Err(DepthBiasClampNotEnabledError(depth_bias.clamp).into());
+            }
+            fixed_function_state.set_depth_bias(
+                depth_bias.constant_factor,
+                depth_bias.slope_factor,
+                depth_bias.clamp,
+            );
+        }
+        if let Some((compare_op, write)) = self.depth_test {
+            fixed_function_state.set_depth_test(compare_op, write);
+        }
+        if let Some((front, back)) = self.stencil {
+            fixed_function_state.set_stencil(front, back);
+        }
+        if self.dynamic_stencil {
+            fixed_function_state.set_dynamic_stencil();
+        }
+        if let Some((mode, extra_size)) = self.conservative_rasterization {
+            if !self
+                .device
+                .is_extension_enabled(CONSERVATIVE_RASTERIZATION_EXTENSION)
+            {
+                return Err(ConservativeRasterizationNotEnabledError.into());
+            }
+            fixed_function_state.set_conservative_rasterization(mode, extra_size);
+        }
+        let layout = Arc::new(self.create_layout()?);
+        let external_render_pass = self.render_pass.is_some();
+        let render_pass = match self.render_pass {
+            Some(render_pass) => render_pass,
+            None => Arc::new(RenderPass::new(
+                Arc::clone(&self.device),
+                Arc::clone(&self.swapchain),
+            )?),
+        };
+        if self.subpass >= render_pass.get_subpass_count() {
+            return Err(PipelineBuildError::InvalidSubpass {
+                subpass: self.subpass,
+                count: render_pass.get_subpass_count(),
+            });
+        }
 
-        let pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
-            .stages(&stages)
-            .vertex_input_state(&vertex_input_state)
-            .input_assembly_state(&input_assembly_state)
-            .viewport_state(&viewport_state)
-            .rasterization_state(&rasterization_state)
-            .multisample_state(&multisample_state)
-            .color_blend_state(&color_blend_state)
-            .dynamic_state(&dynamic_state)
-            .layout(unsafe { layout.raw_handle() })
-            .render_pass(unsafe { render_pass.raw_handle() })
-            .subpass(0);
+        if self.stencil.is_some()
+            && !render_pass
+                .get_depth_format(self.subpass)
+                .is_some_and(has_stencil_component)
+        {
+            return Err(MissingStencilAttachmentError(self.subpass).into());
+        }
+        if self.depth_test.is_some() && render_pass.get_depth_format(self.subpass).is_none() {
+            return Err(MissingDepthAttachmentError(self.subpass).into());
+        }
+        let attachments = render_pass.get_color_attachment_count(self.subpass);
+        let Some(blend_states) =
+            color_blend_attachments(self.color_blend_attachments.as_deref(), attachments)
+        else {
+            return Err(PipelineBuildError::ColorBlendAttachmentCount {
+                subpass: self.subpass,
+                attachments,
+                blend_states: self
+                    .color_blend_attachments
+                    .map_or(0, |states| states.len()),
+            });
+        };
+        fixed_function_state.set_color_blend_attachments(blend_states);
 
-        let pipeline = unsafe { self.device.create_graphics_pipeline(pipeline_create_info)? };
+        let pipeline = create_pipeline(
+            &self.device,
+            &fixed_function_state,
+            &self.shader_stages,
+            &layout,
+            &render_pass,
+            self.subpass,
+        )
+        .map_err(PipelineBuildError::PipelineCreation)?;
 
         let mut pipeline = GraphicsPipeline {
             device: self.device,
             swapchain: self.swapchain,
             shader_stages: self.shader_stages,
+            fixed_function_state,
             layout,
             render_pass,
+            subpass: self.subpass,
+            external_render_pass,
             pipeline,
-            framebuffers: Vec::new(),
+            framebuffers: SwapchainFramebuffers::default(),
             command_pool: self.command_pool,
             command_buffers: Vec::new(),
+            retired_command_buffers: Vec::new(),
+            flip_viewport_y: self.flip_viewport_y,
+            recording: draw_recording(self.draws),
+            clear_color: self.clear_color,
+            stale: Vec::new(),
         };
 
         pipeline.create_framebuffers();
-        pipeline.create_command_buffers();
+        pipeline.create_command_buffers()?;
 
         Ok(pipeline)
     }
 }
 
+type Recording = Box<dyn Fn(&mut CommandBuffer) -> Result<(), CommandRecordingError>>;
+
+fn draw_recording(draws: Vec<DrawInfo>) -> Recording {
+    Box::new(move |command_buffer| {
+        for &draw in draws.iter() {
+            command_buffer.cmd_draw(draw)?;
+        }
+        Ok(())
+    })
+}
+
+fn create_pipeline(
+    device: &Arc<Device>,
+    fixed_function_state: &FixedFuctionState,
+    shader_stages: &HashMap<String, ShaderStageInfo>,
+    layout: &PipelineLayout,
+    render_pass: &RenderPass,
+    subpass: u32,
+) -> Result<Arc<PipelineHandle>, vk::Result> {
+    let (
+        vertex_input_state,
+        input_assembly_state,
+        viewport_state,
+        rasterization_state,
+        multisample_state,
+        depth_stencil_state,
+        color_blend_state,
+        dynamic_state,
+    ) = (
+        fixed_function_state.get_vertex_input_state(),
+        fixed_function_state.get_input_assembly_state(),
+        fixed_function_state.get_viewport_state(),
+        fixed_function_state.get_rasterization_state(),
+        fixed_function_state.get_multisample_state(),
+        fixed_function_state.get_depth_stencil_state(),
+        fixed_function_state.get_color_blend_state(),
+        fixed_function_state.get_dynamic_state(),
+    );
+    let mut conservative_rasterization_state =
+        fixed_function_state.get_conservative_rasterization_state();
+    let rasterization_state = match conservative_rasterization_state.as_mut() {
+        Some(state) => rasterization_state.push_next(state),
+        None => rasterization_state,
+    };
+
+    let stages: Vec<_> = shader_stages.values().map(|val| val.info()).collect();
+
+    let pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+        .stages(&stages)
+        .vertex_input_state(&vertex_input_state)
+        .input_assembly_state(&input_assembly_state)
+        .viewport_state(&viewport_state)
+        .rasterization_state(&rasterization_state)
+        .multisample_state(&multisample_state)
+        .depth_stencil_state(&depth_stencil_state)
+        .color_blend_state(&color_blend_state)
+        .dynamic_state(&dynamic_state)
+        .layout(unsafe { layout.raw_handle() })
+        .render_pass(unsafe { render_pass.raw_handle() })
+        .subpass(subpass);
+
+    let pipeline = unsafe { device.create_graphics_pipeline(pipeline_create_info)? };
+    Ok(Arc::new(PipelineHandle {
+        device: Arc::clone(device),
+        pipeline,
+    }))
+}
+
+///
+/// Owns the vk::Pipeline separately from GraphicsPipeline, so that command buffers
+/// can keep it alive without referencing the GraphicsPipeline that owns them
+///
+struct PipelineHandle {
+    device: Arc<Device>,
+    pipeline: vk::Pipeline,
+}
+
+impl Drop for PipelineHandle {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline);
+        }
+    }
+}
+
 #[allow(dead_code)]
 pub struct GraphicsPipeline {
     device: Arc<Device>,
     command_pool: Arc<CommandPool>,
     swapchain: Arc<Swapchain>,
     shader_stages: HashMap<String, ShaderStageInfo>,
-    layout: PipelineLayout,
+    fixed_function_state: FixedFuctionState,
+    layout: Arc<PipelineLayout>,
     render_pass: Arc<RenderPass>,
-    pipeline: vk::Pipeline,
-    framebuffers: Vec<Arc<Framebuffer>>,
+    subpass: u32,
+    external_render_pass: bool,
+    pipeline: Arc<PipelineHandle>,
+    framebuffers: SwapchainFramebuffers,
     command_buffers: Vec<Arc<CommandBuffer>>,
+    retired_command_buffers: Vec<Arc<CommandBuffer>>,
+    flip_viewport_y: bool,
+    recording: Recording,
+    clear_color: [f32; 4],
+    stale: Vec<bool>,
 }
 
 impl GraphicsPipeline {
+    ///
+    /// Does nothing for pipelines built with GraphicsPipelineBuilder::render_pass
+    ///
     pub fn create_framebuffers(&mut self) {
+        if self.external_render_pass {
+            return;
+        }
         self.framebuffers = self
             .swapchain
             .create_framebuffers(Arc::clone(&self.render_pass));
     }
 
-    pub fn create_command_buffers(&mut self) {
-        self.command_buffers = self
-            .framebuffers
-            .iter()
-            .map(|framebuffer| {
+    fn record(
+        &self,
+        command_buffer: &mut CommandBuffer,
+        index: usize,
+        f: impl FnOnce(&mut CommandBuffer) -> Result<(), CommandRecordingError>,
+    ) -> Result<(), CommandRecordingError> {
+        command_buffer.begin()?;
+        command_buffer.cmd_begin_render_pass_with_clear_color(
+            Arc::clone(&self.render_pass),
+            Arc::clone(self.framebuffers.framebuffer_for_image(index as u32)),
+            self.clear_color,
+        )?;
+        command_buffer.cmd_bind_graphics_pipeline(self)?;
+        let (viewport, scissor) = self.swapchain.make_viewport(self.flip_viewport_y)?;
+        command_buffer.cmd_set_viewport(viewport)?;
+        command_buffer.cmd_set_scissor(scissor)?;
+        f(command_buffer)?;
+        command_buffer.cmd_end_render_pass()?;
+        Ok(command_buffer.end()?)
+    }
+
+    ///
+    /// Records a command buffer per framebuffer with the set_recording closure.
+    /// The previous command buffers are freed once nothing else references them,
+    /// they must not be pending execution by then
+    ///
+    pub fn create_command_buffers(&mut self) -> Result<(), CommandRecordingError> {
+        let mut command_buffers = self
+            .command_pool
+            .allocate_command_buffers(self.framebuffers.len() as u32);
+        let result =
+            command_buffers
+                .iter_mut()
+                .enumerate()
+                .try_for_each(|(index, command_buffer)| {
+                    self.record(command_buffer, index, &*self.recording)
+                });
+        if let Err(error) = result {
+            for command_buffer in command_buffers {
+                self.command_pool.free_command_buffer(command_buffer);
+            }
+            return Err(error);
+        }
+        let previous = mem::replace(
+            &mut self.command_buffers,
+            command_buffers.into_iter().map(Arc::new).collect(),
+        );
+        self.retired_command_buffers.extend(previous);
+        self.free_retired_command_buffers();
+        self.stale = vec![false; self.command_buffers.len()];
+        Ok(())
+    }
+
+    ///
+    /// Frees replaced command buffers that are no longer referenced elsewhere,
+    /// e.g. by a frame in flight
+    ///
+    fn free_retired_command_buffers(&mut self) {
+        for command_buffer in mem::take(&mut self.retired_command_buffers) {
+            match Arc::try_unwrap(command_buffer) {
+                Ok(command_buffer) => self.command_pool.free_command_buffer(command_buffer),
+                Err(command_buffer) => self.retired_command_buffers.push(command_buffer),
+            }
+        }
+    }
+
+    ///
+    /// Re-records the command buffer of the framebuffer at index.
+    /// Begin, render pass, pipeline bind, viewport and scissor are recorded around f.
+    /// The previous recording must not be pending execution on the GPU. If it is still
+    /// referenced elsewhere, a new command buffer is recorded and the old one is freed
+    /// by a later re-recording once that reference is gone.
+    /// This is a one-off, refresh_command_buffer records the set_recording closure again
+    ///
+    pub fn record_command_buffer(
+        &mut self,
+        index: u32,
+        f: impl FnOnce(&mut CommandBuffer) -> Result<(), CommandRecordingError>,
+    ) -> Result<(), CommandRecordingError> {
+        let index = index as usize;
+        self.free_retired_command_buffers();
+        let mut command_buffers = mem::take(&mut self.command_buffers);
+        let result = match Arc::get_mut(&mut command_buffers[index]) {
+            Some(command_buffer) => self.record(command_buffer, index, f),
+            None => {
+                // The old command buffer is still referenced elsewhere, record a new one
                 let mut command_buffer = self.command_pool.allocate_command_buffer();
-                command_buffer.begin().unwrap();
-                command_buffer
-                    .cmd_begin_render_pass(Arc::clone(&self.render_pass), Arc::clone(&framebuffer))
-                    .unwrap();
-                command_buffer.cmd_bind_graphics_pipeline(&self).unwrap();
-                let (viewport, scissor) = self.swapchain.make_viewport().unwrap();
-                command_buffer.cmd_set_viewport(viewport).unwrap();
-                command_buffer.cmd_set_scissor(scissor).unwrap();
-                command_buffer
-                    .cmd_draw(DrawInfo {
-                        vertex_count: 3,
-                        instance_count: 1,
-                        ..Default::default()
-                    })
-                    .unwrap();
-                command_buffer.cmd_end_render_pass().unwrap();
-                command_buffer.end().unwrap();
-                Arc::new(command_buffer)
-            })
-            .collect();
+                let result = self.record(&mut command_buffer, index, f);
+                let previous = mem::replace(&mut command_buffers[index], Arc::new(command_buffer));
+                self.retired_command_buffers.push(previous);
+                result
+            }
+        };
+        self.command_buffers = command_buffers;
+        if result.is_ok() {
+            self.stale[index] = false;
+        }
+        result
+    }
+
+    ///
+    /// Takes effect as command buffers are re-recorded, see refresh_command_buffer
+    ///
+    pub fn set_clear_color(&mut self, clear_color: [f32; 4]) {
+        self.clear_color = clear_color;
+        self.stale.fill(true);
+    }
+
+    ///
+    /// Replaces what is recorded inside the render pass, the builder's draws by default,
+    /// and re-records every command buffer with f. f is kept and replayed by
+    /// refresh_command_buffer. None of the command buffers may be pending execution
+    ///
+    pub fn set_recording(
+        &mut self,
+        f: impl Fn(&mut CommandBuffer) -> Result<(), CommandRecordingError> + 'static,
+    ) -> Result<(), CommandRecordingError> {
+        self.recording = Box::new(f);
+        self.stale.fill(true);
+        for index in 0..self.get_command_buffer_count() {
+            self.refresh_command_buffer(index)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Re-records the command buffer at index with the set_recording closure if it is out
+    /// of date, e.g. after set_clear_color. Meant to be called for the acquired image index
+    /// once its previous frame has finished, like in the FrameRenderer::draw_frame closure
+    ///
+    pub fn refresh_command_buffer(&mut self, index: u32) -> Result<(), CommandRecordingError> {
+        if !self.stale[index as usize] {
+            return Ok(());
+        }
+        let recording = mem::replace(&mut self.recording, Box::new(|_| Ok(())));
+        let result = self.record_command_buffer(index, &*recording);
+        self.recording = recording;
+        result
+    }
+
+    ///
+    /// Rebuilds only the vk::Pipeline with new shader stages, e.g. for shader hot-reload,
+    /// keeping the render pass, layout and framebuffers. stages must still contain
+    /// a vertex and a fragment stage.
+    /// On failure the current pipeline is kept. On success the old one is destroyed once
+    /// no command buffer references it, command buffers pick up the new one as they are
+    /// re-recorded with the set_recording closure, see refresh_command_buffer
+    ///
+    pub fn recreate_pipeline_only(
+        &mut self,
+        stages: HashMap<String, ShaderStageInfo>,
+    ) -> Result<(), vk::Result> {
+        self.pipeline = create_pipeline(
+            &self.device,
+            &self.fixed_function_state,
+            &stages,
+            &self.layout,
+            &self.render_pass,
+            self.subpass,
+        )?;
+        self.shader_stages = stages;
+        self.stale.fill(true);
+        Ok(())
+    }
+
+    ///
+    /// Whether fragment shaders of this pipeline should output linear or sRGB colors
+    ///
+    pub fn get_color_space_mode(&self) -> ColorSpaceMode {
+        self.swapchain.get_color_space_mode()
+    }
+
+    pub fn get_render_pass(&self) -> Arc<RenderPass> {
+        Arc::clone(&self.render_pass)
+    }
+
+    pub fn get_subpass(&self) -> u32 {
+        self.subpass
+    }
+
+    pub fn get_layout(&self) -> Arc<PipelineLayout> {
+        Arc::clone(&self.layout)
+    }
+
+    pub fn get_command_buffer_count(&self) -> u32 {
+        self.command_buffers.len() as u32
     }
 
     pub fn get_command_buffer(&self, index: u32) -> Arc<CommandBuffer> {
         Arc::clone(&self.command_buffers[index as usize])
     }
 
+    pub(in crate::vk) fn handle_marker(&self) -> Arc<dyn Any + Send + Sync> {
+        Arc::clone(&self.pipeline) as Arc<dyn Any + Send + Sync>
+    }
+
     pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::Pipeline {
-        self.pipeline
+        self.pipeline.pipeline
     }
 }
 
-impl Drop for GraphicsPipeline {
-    fn drop(&mut self) {
-        unsafe {
-            self.device.destroy_pipeline(self.pipeline);
-        }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn line_width() {
+        let range = [1.0, 8.0];
+        assert_eq!(clamp_line_width(4.0, true, range), 4.0);
+        assert_eq!(clamp_line_width(16.0, true, range), 8.0);
+        assert_eq!(clamp_line_width(0.5, true, range), 1.0);
+        assert_eq!(clamp_line_width(4.0, false, range), 1.0);
+    }
+
+    #[test]
+    fn blend_states_per_attachment() {
+        assert_eq!(color_blend_attachments(None, 2).unwrap().len(), 2);
+        assert!(color_blend_attachments(None, 0).unwrap().is_empty());
+        let additive = default_color_blend_attachment()
+            .blend_enable(true)
+            .src_color_blend_factor(vk::BlendFactor::ONE)
+            .dst_color_blend_factor(vk::BlendFactor::ONE);
+        assert_eq!(
+            color_blend_attachments(Some(&[additive]), 1).unwrap()[0].blend_enable,
+            vk::TRUE
+        );
+        assert!(color_blend_attachments(Some(&[additive]), 2).is_none());
+    }
+
+    #[test]
+    fn depth_test() {
+        let mut state = FixedFuctionState::new();
+        assert_eq!(state.get_depth_stencil_state().depth_test_enable, vk::FALSE);
+        state.set_depth_test(vk::CompareOp::LESS, false);
+        let depth_stencil = state.get_depth_stencil_state();
+        assert_eq!(depth_stencil.depth_test_enable, vk::TRUE);
+        assert_eq!(depth_stencil.depth_write_enable, vk::FALSE);
+        assert_eq!(depth_stencil.depth_compare_op, vk::CompareOp::LESS);
     }
 }
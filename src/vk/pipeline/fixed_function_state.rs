@@ -1,8 +1,32 @@
 use ash::vk;
 
+use super::vertex_layout::{VertexLayout, VertexLayoutError};
+
+///
+/// No blending, all components written
+///
+pub fn default_color_blend_attachment() -> vk::PipelineColorBlendAttachmentState {
+    vk::PipelineColorBlendAttachmentState::default().color_write_mask(
+        vk::ColorComponentFlags::R
+            | vk::ColorComponentFlags::G
+            | vk::ColorComponentFlags::B
+            | vk::ColorComponentFlags::A,
+    )
+}
+
 pub struct FixedFuctionState {
     dynamic_states: Vec<vk::DynamicState>,
+    vertex_bindings: Vec<vk::VertexInputBindingDescription>,
+    vertex_attributes: Vec<vk::VertexInputAttributeDescription>,
+    topology: vk::PrimitiveTopology,
+    primitive_restart: bool,
+    line_width: f32,
+    polygon_mode: vk::PolygonMode,
+    depth_bias: Option<(f32, f32, f32)>,
+    depth_test: Option<(vk::CompareOp, bool)>,
     color_blend_attachment_states: Vec<vk::PipelineColorBlendAttachmentState>,
+    stencil: Option<(vk::StencilOpState, vk::StencilOpState)>,
+    conservative_rasterization: Option<(vk::ConservativeRasterizationModeEXT, f32)>,
 }
 
 impl Default for FixedFuctionState {
@@ -15,15 +39,116 @@ impl FixedFuctionState {
     pub fn new() -> Self {
         Self {
             dynamic_states: vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR],
-            color_blend_attachment_states: vec![
-                vk::PipelineColorBlendAttachmentState::default().color_write_mask(
-                    vk::ColorComponentFlags::R
-                        | vk::ColorComponentFlags::G
-                        | vk::ColorComponentFlags::B
-                        | vk::ColorComponentFlags::A,
-                ),
-            ],
+            vertex_bindings: Vec::new(),
+            vertex_attributes: Vec::new(),
+            topology: vk::PrimitiveTopology::TRIANGLE_LIST,
+            primitive_restart: false,
+            line_width: 1.0,
+            polygon_mode: vk::PolygonMode::FILL,
+            depth_bias: None,
+            depth_test: None,
+            color_blend_attachment_states: vec![default_color_blend_attachment()],
+            stencil: None,
+            conservative_rasterization: None,
+        }
+    }
+
+    ///
+    /// Every layout needs its own binding, locations have to be unique across all of them
+    ///
+    pub fn set_vertex_layouts(
+        &mut self,
+        layouts: &[VertexLayout],
+    ) -> Result<(), VertexLayoutError> {
+        self.vertex_bindings.clear();
+        self.vertex_attributes.clear();
+        for layout in layouts {
+            let binding = layout.get_binding_description();
+            if self
+                .vertex_bindings
+                .iter()
+                .any(|other| other.binding == binding.binding)
+            {
+                return Err(VertexLayoutError::DuplicateBinding(binding.binding));
+            }
+            for attribute in layout.get_attribute_descriptions() {
+                if self
+                    .vertex_attributes
+                    .iter()
+                    .any(|other| other.location == attribute.location)
+                {
+                    return Err(VertexLayoutError::DuplicateLocation(attribute.location));
+                }
+                self.vertex_attributes.push(*attribute);
+            }
+            self.vertex_bindings.push(binding);
         }
+        Ok(())
+    }
+
+    pub fn set_topology(&mut self, topology: vk::PrimitiveTopology, primitive_restart: bool) {
+        self.topology = topology;
+        self.primitive_restart = primitive_restart;
+    }
+
+    pub fn set_line_width(&mut self, line_width: f32) {
+        self.line_width = line_width;
+    }
+
+    pub fn set_polygon_mode(&mut self, polygon_mode: vk::PolygonMode) {
+        self.polygon_mode = polygon_mode;
+    }
+
+    pub fn set_depth_bias(&mut self, constant_factor: f32, slope_factor: f32, clamp: f32) {
+        self.depth_bias = Some((constant_factor, slope_factor, clamp));
+    }
+
+    pub fn set_depth_test(&mut self, compare_op: vk::CompareOp, write: bool) {
+        self.depth_test = Some((compare_op, write));
+    }
+
+    ///
+    /// One state per color attachment of the subpass
+    ///
+    pub fn set_color_blend_attachments(
+        &mut self,
+        states: Vec<vk::PipelineColorBlendAttachmentState>,
+    ) {
+        self.color_blend_attachment_states = states;
+    }
+
+    pub fn set_stencil(&mut self, front: vk::StencilOpState, back: vk::StencilOpState) {
+        self.stencil = Some((front, back));
+    }
+
+    ///
+    /// extra_size is only used by OVERESTIMATE, in pixels on top of the device's
+    /// base overestimation
+    ///
+    pub fn set_conservative_rasterization(
+        &mut self,
+        mode: vk::ConservativeRasterizationModeEXT,
+        extra_size: f32,
+    ) {
+        self.conservative_rasterization = Some((mode, extra_size));
+    }
+
+    ///
+    /// Stencil reference, compare and write masks are then set with cmd_set_stencil_*
+    ///
+    pub fn set_dynamic_stencil(&mut self) {
+        self.dynamic_states.extend([
+            vk::DynamicState::STENCIL_REFERENCE,
+            vk::DynamicState::STENCIL_COMPARE_MASK,
+            vk::DynamicState::STENCIL_WRITE_MASK,
+        ]);
+    }
+
+    ///
+    /// Line width is then set with cmd_set_line_width
+    ///
+    pub fn set_dynamic_line_width(&mut self) {
+        self.dynamic_states.push(vk::DynamicState::LINE_WIDTH);
     }
 
     pub fn get_dynamic_state(&self) -> vk::PipelineDynamicStateCreateInfo<'_> {
@@ -31,10 +156,13 @@ impl FixedFuctionState {
     }
     pub fn get_vertex_input_state(&self) -> vk::PipelineVertexInputStateCreateInfo<'_> {
         vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&self.vertex_bindings)
+            .vertex_attribute_descriptions(&self.vertex_attributes)
     }
     pub fn get_input_assembly_state(&self) -> vk::PipelineInputAssemblyStateCreateInfo<'_> {
         vk::PipelineInputAssemblyStateCreateInfo::default()
-            .topology(vk::PrimitiveTopology::TRIANGLE_LIST)
+            .topology(self.topology)
+            .primitive_restart_enable(self.primitive_restart)
     }
     pub fn get_viewport_state(&self) -> vk::PipelineViewportStateCreateInfo<'_> {
         vk::PipelineViewportStateCreateInfo::default()
@@ -42,15 +170,49 @@ impl FixedFuctionState {
             .scissor_count(1)
     }
     pub fn get_rasterization_state(&self) -> vk::PipelineRasterizationStateCreateInfo<'_> {
-        vk::PipelineRasterizationStateCreateInfo::default()
-            .polygon_mode(vk::PolygonMode::FILL)
-            .line_width(1.0f32)
+        let state = vk::PipelineRasterizationStateCreateInfo::default()
+            .polygon_mode(self.polygon_mode)
+            .line_width(self.line_width);
+        match self.depth_bias {
+            Some((constant_factor, slope_factor, clamp)) => state
+                .depth_bias_enable(true)
+                .depth_bias_constant_factor(constant_factor)
+                .depth_bias_slope_factor(slope_factor)
+                .depth_bias_clamp(clamp),
+            None => state,
+        }
+    }
+    ///
+    /// Has to be chained onto the rasterization state if present
+    ///
+    pub fn get_conservative_rasterization_state(
+        &self,
+    ) -> Option<vk::PipelineRasterizationConservativeStateCreateInfoEXT<'_>> {
+        self.conservative_rasterization.map(|(mode, extra_size)| {
+            vk::PipelineRasterizationConservativeStateCreateInfoEXT::default()
+                .conservative_rasterization_mode(mode)
+                .extra_primitive_overestimation_size(extra_size)
+        })
     }
     pub fn get_multisample_state(&self) -> vk::PipelineMultisampleStateCreateInfo<'_> {
         vk::PipelineMultisampleStateCreateInfo::default()
             .rasterization_samples(vk::SampleCountFlags::TYPE_1)
     }
 
+    pub fn get_depth_stencil_state(&self) -> vk::PipelineDepthStencilStateCreateInfo<'_> {
+        let mut state = vk::PipelineDepthStencilStateCreateInfo::default();
+        if let Some((compare_op, write)) = self.depth_test {
+            state = state
+                .depth_test_enable(true)
+                .depth_write_enable(write)
+                .depth_compare_op(compare_op);
+        }
+        match self.stencil {
+            Some((front, back)) => state.stencil_test_enable(true).front(front).back(back),
+            None => state,
+        }
+    }
+
     pub fn get_color_blend_state(&self) -> vk::PipelineColorBlendStateCreateInfo<'_> {
         vk::PipelineColorBlendStateCreateInfo::default()
             .attachments(&self.color_blend_attachment_states)
@@ -2,19 +2,93 @@ use std::sync::Arc;
 
 use ash::vk;
 
-use crate::vk::device::Device;
+use crate::vk::{descriptor::DescriptorSetLayout, device::Device};
+#[cfg(feature = "reflect")]
+use crate::vk::{
+    descriptor::DescriptorSetLayoutBuilder,
+    shader::reflect::{ReflectError, ShaderReflection, merge_reflections},
+};
 
 pub struct PipelineLayout {
     device: Arc<Device>,
+    set_layouts: Vec<Arc<DescriptorSetLayout>>,
     layout: vk::PipelineLayout,
 }
 
 impl PipelineLayout {
     pub fn new(device: Arc<Device>) -> Self {
-        let layout_info = vk::PipelineLayoutCreateInfo::default();
+        Self::with_set_layouts(device, Vec::new())
+    }
+
+    pub fn with_set_layouts(
+        device: Arc<Device>,
+        set_layouts: Vec<Arc<DescriptorSetLayout>>,
+    ) -> Self {
+        Self::with_push_constant_ranges(device, set_layouts, &[])
+    }
+
+    pub fn with_push_constant_ranges(
+        device: Arc<Device>,
+        set_layouts: Vec<Arc<DescriptorSetLayout>>,
+        push_constant_ranges: &[vk::PushConstantRange],
+    ) -> Self {
+        let raw_set_layouts: Vec<_> = set_layouts
+            .iter()
+            .map(|set_layout| unsafe { set_layout.raw_handle() })
+            .collect();
+        let layout_info = vk::PipelineLayoutCreateInfo::default()
+            .set_layouts(&raw_set_layouts)
+            .push_constant_ranges(push_constant_ranges);
         let layout = unsafe { device.create_pipeline_layout(layout_info) };
 
-        Self { device, layout }
+        Self {
+            device,
+            set_layouts,
+            layout,
+        }
+    }
+
+    ///
+    /// Creates a set layout for every set the stages use, sets in between them are empty,
+    /// and a push constant range for every stage with push constants.
+    /// Fails for runtime sized arrays, layouts with those are built with
+    /// DescriptorSetLayoutBuilder and an explicit count
+    ///
+    #[cfg(feature = "reflect")]
+    pub fn from_reflections(
+        device: Arc<Device>,
+        stages: &[(vk::ShaderStageFlags, ShaderReflection)],
+    ) -> Result<Self, ReflectError> {
+        let (sets, push_constant_ranges) = merge_reflections(stages)?;
+        let set_layouts = sets
+            .into_iter()
+            .map(|bindings| {
+                let builder = bindings.into_iter().fold(
+                    DescriptorSetLayoutBuilder::new(Arc::clone(&device)),
+                    |builder, binding| {
+                        builder.binding_for_stages(
+                            binding.binding,
+                            binding.descriptor_type,
+                            binding.descriptor_count,
+                            binding.stage_flags,
+                        )
+                    },
+                );
+                Arc::new(builder.build())
+            })
+            .collect();
+        Ok(Self::with_push_constant_ranges(
+            device,
+            set_layouts,
+            &push_constant_ranges,
+        ))
+    }
+
+    ///
+    /// Layout of set, e.g. to allocate descriptor sets for a reflected layout
+    ///
+    pub fn get_set_layout(&self, set: u32) -> Option<Arc<DescriptorSetLayout>> {
+        self.set_layouts.get(set as usize).map(Arc::clone)
     }
 
     pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::PipelineLayout {
@@ -1,56 +1,662 @@
 use std::sync::Arc;
 
-use crate::vk::{device::Device, swapchain::Swapchain};
+use crate::vk::{
+    device::{Device, NoDepthFormatError},
+    swapchain::Swapchain,
+};
 use ash::vk;
 
-pub struct RenderPass {
+#[derive(Debug, thiserror::Error)]
+pub enum RenderPassCreationError {
+    #[error("render pass has no subpasses")]
+    NoSubpasses,
+    #[error("subpass {subpass} references attachment {attachment}, but only {count} are declared")]
+    InvalidAttachment {
+        subpass: usize,
+        attachment: u32,
+        count: usize,
+    },
+    #[error("subpass {subpass} uses attachment {attachment} as depth, but it has no depth format")]
+    NotDepthAttachment { subpass: usize, attachment: u32 },
+    #[error(transparent)]
+    UnsupportedDepthFormat(#[from] UnsupportedDepthFormatError),
+    #[error(transparent)]
+    NoDepthFormat(#[from] NoDepthFormatError),
+    #[error("multiview is not enabled on the device")]
+    MultiviewNotEnabled,
+    #[error("{masks} view masks were given for {subpasses} subpasses")]
+    ViewMaskCount { masks: usize, subpasses: usize },
+    #[error("separate depth and stencil layouts are not enabled on the device")]
+    SeparateDepthStencilLayoutsNotEnabled,
+    #[error("stencil layouts were given for attachment {0}, which has no stencil component")]
+    NotStencilAttachment(u32),
+    #[error("failed to create render pass: {0}")]
+    Creation(#[from] vk::Result),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("format {0:?} can't be used as a depth attachment on this device")]
+pub struct UnsupportedDepthFormatError(pub vk::Format);
+
+pub fn has_stencil_component(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::S8_UINT
+    )
+}
+
+pub fn is_depth_format(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::D16_UNORM
+            | vk::Format::X8_D24_UNORM_PACK32
+            | vk::Format::D32_SFLOAT
+            | vk::Format::D16_UNORM_S8_UINT
+            | vk::Format::D24_UNORM_S8_UINT
+            | vk::Format::D32_SFLOAT_S8_UINT
+            | vk::Format::S8_UINT
+    )
+}
+
+///
+/// Attachment indices used by a single subpass.
+/// Color attachments are referenced in COLOR_ATTACHMENT_OPTIMAL layout,
+/// input attachments in SHADER_READ_ONLY_OPTIMAL layout,
+/// the depth attachment in DEPTH_STENCIL_ATTACHMENT_OPTIMAL layout unless
+/// depth_stencil_layouts is set
+///
+#[derive(Default, Clone, Debug)]
+pub struct SubpassInfo {
+    pub color_attachments: Vec<u32>,
+    pub input_attachments: Vec<u32>,
+    pub depth_attachment: Option<u32>,
+    pub depth_stencil_layouts: Option<(vk::ImageLayout, vk::ImageLayout)>,
+}
+
+impl SubpassInfo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn color(mut self, attachment: u32) -> Self {
+        self.color_attachments.push(attachment);
+        self
+    }
+    pub fn input(mut self, attachment: u32) -> Self {
+        self.input_attachments.push(attachment);
+        self
+    }
+    pub fn depth(mut self, attachment: u32) -> Self {
+        self.depth_attachment = Some(attachment);
+        self
+    }
+    ///
+    /// Separate layouts of the depth and stencil aspects of the depth attachment,
+    /// e.g. DEPTH_ATTACHMENT_OPTIMAL and STENCIL_READ_ONLY_OPTIMAL.
+    /// Needs DeviceBuilder::separate_depth_stencil_layouts
+    ///
+    pub fn depth_stencil_layouts(
+        mut self,
+        depth_layout: vk::ImageLayout,
+        stencil_layout: vk::ImageLayout,
+    ) -> Self {
+        self.depth_stencil_layouts = Some((depth_layout, stencil_layout));
+        self
+    }
+}
+
+fn check_subpasses(
+    formats: &[vk::Format],
+    subpasses: &[SubpassInfo],
+) -> Result<(), RenderPassCreationError> {
+    if subpasses.is_empty() {
+        return Err(RenderPassCreationError::NoSubpasses);
+    }
+    for (subpass, info) in subpasses.iter().enumerate() {
+        if let Some(&attachment) = info
+            .color_attachments
+            .iter()
+            .chain(info.input_attachments.iter())
+            .chain(info.depth_attachment.iter())
+            .find(|&&a| a as usize >= formats.len())
+        {
+            return Err(RenderPassCreationError::InvalidAttachment {
+                subpass,
+                attachment,
+                count: formats.len(),
+            });
+        }
+        if let Some(attachment) = info.depth_attachment
+            && !is_depth_format(formats[attachment as usize])
+        {
+            return Err(RenderPassCreationError::NotDepthAttachment {
+                subpass,
+                attachment,
+            });
+        }
+    }
+    Ok(())
+}
+
+///
+/// Checks that stencil layouts are only given for attachments with a stencil component
+///
+fn check_stencil_layouts(
+    formats: &[vk::Format],
+    stencil_layouts: &[(u32, vk::ImageLayout, vk::ImageLayout)],
+) -> Result<(), RenderPassCreationError> {
+    match stencil_layouts.iter().find(|&&(attachment, _, _)| {
+        formats
+            .get(attachment as usize)
+            .is_none_or(|&format| !has_stencil_component(format))
+    }) {
+        Some(&(attachment, _, _)) => Err(RenderPassCreationError::NotStencilAttachment(attachment)),
+        None => Ok(()),
+    }
+}
+
+fn make_references(attachments: &[u32], layout: vk::ImageLayout) -> Vec<vk::AttachmentReference> {
+    attachments
+        .iter()
+        .map(|&attachment| {
+            vk::AttachmentReference::default()
+                .attachment(attachment)
+                .layout(layout)
+        })
+        .collect()
+}
+
+fn make_references2(
+    attachments: &[u32],
+    layout: vk::ImageLayout,
+    formats: &[vk::Format],
+) -> Vec<vk::AttachmentReference2<'static>> {
+    attachments
+        .iter()
+        .map(|&attachment| {
+            vk::AttachmentReference2::default()
+                .attachment(attachment)
+                .layout(layout)
+                .aspect_mask(aspect_mask(formats[attachment as usize]))
+        })
+        .collect()
+}
+
+///
+/// Every aspect of format
+///
+pub(in crate::vk) fn aspect_mask(format: vk::Format) -> vk::ImageAspectFlags {
+    if format == vk::Format::S8_UINT {
+        vk::ImageAspectFlags::STENCIL
+    } else if has_stencil_component(format) {
+        vk::ImageAspectFlags::DEPTH | vk::ImageAspectFlags::STENCIL
+    } else if is_depth_format(format) {
+        vk::ImageAspectFlags::DEPTH
+    } else {
+        vk::ImageAspectFlags::COLOR
+    }
+}
+
+pub struct RenderPassBuilder {
     device: Arc<Device>,
-    _swapchain: Arc<Swapchain>,
-    render_pass: vk::RenderPass,
+    swapchain: Option<Arc<Swapchain>>,
+    attachments: Vec<vk::AttachmentDescription>,
+    subpasses: Vec<SubpassInfo>,
+    dependencies: Vec<vk::SubpassDependency>,
+    view_masks: Vec<u32>,
+    correlation_masks: Vec<u32>,
+    stencil_layouts: Vec<(u32, vk::ImageLayout, vk::ImageLayout)>,
+    missing_depth_format: Option<NoDepthFormatError>,
 }
 
-impl RenderPass {
-    pub fn new(device: Arc<Device>, swapchain: Arc<Swapchain>) -> Result<Self, vk::Result> {
-        let attachment_description = [vk::AttachmentDescription::default()
-            .samples(vk::SampleCountFlags::TYPE_1)
-            .format(swapchain.get_format().format)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
-            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
-            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-            .initial_layout(vk::ImageLayout::UNDEFINED)
-            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)];
-
-        let attachment_reference = [vk::AttachmentReference::default()
-            .attachment(0)
-            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
-
-        let subpass_description = [vk::SubpassDescription::default()
-            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-            .color_attachments(&attachment_reference)];
-
-        let dependency = [vk::SubpassDependency::default()
-            .src_subpass(vk::SUBPASS_EXTERNAL)
-            .dst_subpass(0)
-            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .src_access_mask(vk::AccessFlags::empty())
-            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
-            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)];
-
-        let render_pass_info = vk::RenderPassCreateInfo::default()
-            .attachments(&attachment_description)
-            .subpasses(&subpass_description)
-            .dependencies(&dependency);
-
-        let render_pass = unsafe { device.create_render_pass(&render_pass_info)? };
-
-        Ok(Self {
+impl RenderPassBuilder {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
             device,
-            _swapchain: swapchain,
+            swapchain: None,
+            attachments: Vec::new(),
+            subpasses: Vec::new(),
+            dependencies: Vec::new(),
+            view_masks: Vec::new(),
+            correlation_masks: Vec::new(),
+            stencil_layouts: Vec::new(),
+            missing_depth_format: None,
+        }
+    }
+
+    ///
+    /// Adds an attachment that is cleared and transitioned to PRESENT_SRC_KHR,
+    /// using the swapchain image format
+    ///
+    pub fn swapchain_attachment(mut self, swapchain: Arc<Swapchain>) -> Self {
+        self.attachments.push(
+            vk::AttachmentDescription::default()
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .format(swapchain.get_format().format)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+        );
+        self.swapchain = Some(swapchain);
+        self
+    }
+
+    ///
+    /// Adds a cleared depth attachment that is discarded after the render pass.
+    /// The stencil component, if format has one, is cleared as well
+    ///
+    pub fn depth_attachment(mut self, format: vk::Format) -> Self {
+        let stencil_load_op = if has_stencil_component(format) {
+            vk::AttachmentLoadOp::CLEAR
+        } else {
+            vk::AttachmentLoadOp::DONT_CARE
+        };
+        self.attachments.push(
+            vk::AttachmentDescription::default()
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .format(format)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(stencil_load_op)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        );
+        self
+    }
+
+    ///
+    /// Adds a depth attachment like depth_attachment, using Device::default_depth_format.
+    /// build returns NoDepthFormat if the device has no suitable format
+    ///
+    pub fn default_depth_attachment(mut self, need_stencil: bool) -> Self {
+        match self.device.default_depth_format(need_stencil) {
+            Ok(format) => self.depth_attachment(format),
+            Err(error) => {
+                self.missing_depth_format = Some(error);
+                self.depth_attachment(vk::Format::UNDEFINED)
+            }
+        }
+    }
+
+    pub fn attachment(mut self, attachment: vk::AttachmentDescription) -> Self {
+        self.attachments.push(attachment);
+        self
+    }
+
+    pub fn subpass(mut self, subpass: SubpassInfo) -> Self {
+        self.subpasses.push(subpass);
+        self
+    }
+
+    pub fn dependency(mut self, dependency: vk::SubpassDependency) -> Self {
+        self.dependencies.push(dependency);
+        self
+    }
+
+    ///
+    /// Makes subpass dst wait for color writes of subpass src before reading them as
+    /// input attachments
+    ///
+    pub fn input_dependency(self, src: u32, dst: u32) -> Self {
+        self.dependency(
+            vk::SubpassDependency::default()
+                .src_subpass(src)
+                .dst_subpass(dst)
+                .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(vk::AccessFlags::INPUT_ATTACHMENT_READ)
+                .dependency_flags(vk::DependencyFlags::BY_REGION),
+        )
+    }
+
+    ///
+    /// Renders every subpass to several array layers at once, bit i of a subpass view mask
+    /// selects layer i, shaders tell the views apart by gl_ViewIndex.
+    /// Views in a correlation mask may be rendered concurrently.
+    /// Needs DeviceBuilder::multiview and one view mask per subpass, attachments are then
+    /// array views with a layer per view while the framebuffer itself has a single layer
+    ///
+    pub fn multiview(mut self, view_masks: Vec<u32>, correlation_masks: Vec<u32>) -> Self {
+        self.view_masks = view_masks;
+        self.correlation_masks = correlation_masks;
+        self
+    }
+
+    ///
+    /// Gives the stencil aspect of attachment its own initial and final layouts,
+    /// the layouts of the attachment description then only apply to the depth aspect.
+    /// Needs DeviceBuilder::separate_depth_stencil_layouts
+    ///
+    pub fn stencil_layouts(
+        mut self,
+        attachment: u32,
+        initial_layout: vk::ImageLayout,
+        final_layout: vk::ImageLayout,
+    ) -> Self {
+        self.stencil_layouts
+            .push((attachment, initial_layout, final_layout));
+        self
+    }
+
+    fn has_separate_depth_stencil_layouts(&self) -> bool {
+        !self.stencil_layouts.is_empty()
+            || self
+                .subpasses
+                .iter()
+                .any(|subpass| subpass.depth_stencil_layouts.is_some())
+    }
+
+    pub fn build(mut self) -> Result<RenderPass, RenderPassCreationError> {
+        if let Some(error) = self.missing_depth_format.take() {
+            return Err(error.into());
+        }
+        let formats: Vec<_> = self.attachments.iter().map(|a| a.format).collect();
+        check_subpasses(&formats, &self.subpasses)?;
+        check_stencil_layouts(&formats, &self.stencil_layouts)?;
+        let separate_layouts = self.has_separate_depth_stencil_layouts();
+        if separate_layouts && !self.device.is_separate_depth_stencil_layouts_enabled() {
+            return Err(RenderPassCreationError::SeparateDepthStencilLayoutsNotEnabled);
+        }
+        if !self.view_masks.is_empty() {
+            if !self.device.is_multiview_enabled() {
+                return Err(RenderPassCreationError::MultiviewNotEnabled);
+            }
+            if self.view_masks.len() != self.subpasses.len() {
+                return Err(RenderPassCreationError::ViewMaskCount {
+                    masks: self.view_masks.len(),
+                    subpasses: self.subpasses.len(),
+                });
+            }
+        }
+        if let Some(&format) = formats
+            .iter()
+            .find(|&&f| is_depth_format(f) && !self.device.is_depth_format_supported(f))
+        {
+            return Err(UnsupportedDepthFormatError(format).into());
+        }
+
+        let render_pass = if separate_layouts {
+            self.create_render_pass2(&formats)?
+        } else {
+            self.create_render_pass()?
+        };
+
+        Ok(RenderPass {
+            device: self.device,
+            _swapchain: self.swapchain,
             render_pass,
+            depth_formats: self
+                .subpasses
+                .iter()
+                .map(|subpass| subpass.depth_attachment.map(|a| formats[a as usize]))
+                .collect(),
+            color_attachment_counts: self
+                .subpasses
+                .iter()
+                .map(|subpass| subpass.color_attachments.len() as u32)
+                .collect(),
+            formats,
         })
     }
 
+    fn create_render_pass(&self) -> Result<vk::RenderPass, vk::Result> {
+        let references: Vec<_> = self
+            .subpasses
+            .iter()
+            .map(|subpass| {
+                (
+                    make_references(
+                        &subpass.color_attachments,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ),
+                    make_references(
+                        &subpass.input_attachments,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    subpass.depth_attachment.map(|attachment| {
+                        vk::AttachmentReference::default()
+                            .attachment(attachment)
+                            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+                    }),
+                )
+            })
+            .collect();
+
+        let subpass_descriptions: Vec<_> = references
+            .iter()
+            .map(|(color, input, depth)| {
+                let description = vk::SubpassDescription::default()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .color_attachments(color)
+                    .input_attachments(input);
+                match depth {
+                    Some(depth) => description.depth_stencil_attachment(depth),
+                    None => description,
+                }
+            })
+            .collect();
+
+        let mut multiview_info = vk::RenderPassMultiviewCreateInfo::default()
+            .view_masks(&self.view_masks)
+            .correlation_masks(&self.correlation_masks);
+        let mut render_pass_info = vk::RenderPassCreateInfo::default()
+            .attachments(&self.attachments)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&self.dependencies);
+        if !self.view_masks.is_empty() {
+            render_pass_info = render_pass_info.push_next(&mut multiview_info);
+        }
+
+        unsafe { self.device.create_render_pass(&render_pass_info) }
+    }
+
+    ///
+    /// Same as create_render_pass, but with the Vulkan 1.2 structs that can carry
+    /// separate stencil layouts
+    ///
+    fn create_render_pass2(&self, formats: &[vk::Format]) -> Result<vk::RenderPass, vk::Result> {
+        let mut attachment_stencil_layouts: Vec<_> = self
+            .attachments
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                self.stencil_layouts
+                    .iter()
+                    .rfind(|&&(attachment, _, _)| attachment as usize == i)
+                    .map(|&(_, initial_layout, final_layout)| {
+                        vk::AttachmentDescriptionStencilLayout::default()
+                            .stencil_initial_layout(initial_layout)
+                            .stencil_final_layout(final_layout)
+                    })
+            })
+            .collect();
+        let attachments: Vec<_> = self
+            .attachments
+            .iter()
+            .zip(attachment_stencil_layouts.iter_mut())
+            .map(|(attachment, stencil_layout)| {
+                let description = vk::AttachmentDescription2::default()
+                    .flags(attachment.flags)
+                    .format(attachment.format)
+                    .samples(attachment.samples)
+                    .load_op(attachment.load_op)
+                    .store_op(attachment.store_op)
+                    .stencil_load_op(attachment.stencil_load_op)
+                    .stencil_store_op(attachment.stencil_store_op)
+                    .initial_layout(attachment.initial_layout)
+                    .final_layout(attachment.final_layout);
+                match stencil_layout {
+                    Some(stencil_layout) => description.push_next(stencil_layout),
+                    None => description,
+                }
+            })
+            .collect();
+
+        let mut reference_stencil_layouts: Vec<_> = self
+            .subpasses
+            .iter()
+            .map(|subpass| {
+                subpass.depth_stencil_layouts.map(|(_, stencil_layout)| {
+                    vk::AttachmentReferenceStencilLayout::default().stencil_layout(stencil_layout)
+                })
+            })
+            .collect();
+        let references: Vec<_> = self
+            .subpasses
+            .iter()
+            .zip(reference_stencil_layouts.iter_mut())
+            .map(|(subpass, stencil_layout)| {
+                (
+                    make_references2(
+                        &subpass.color_attachments,
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                        formats,
+                    ),
+                    make_references2(
+                        &subpass.input_attachments,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                        formats,
+                    ),
+                    subpass.depth_attachment.map(|attachment| {
+                        let layout = subpass.depth_stencil_layouts.map_or(
+                            vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                            |(depth, _)| depth,
+                        );
+                        let reference = vk::AttachmentReference2::default()
+                            .attachment(attachment)
+                            .layout(layout)
+                            .aspect_mask(aspect_mask(formats[attachment as usize]));
+                        match stencil_layout {
+                            Some(stencil_layout) => reference.push_next(stencil_layout),
+                            None => reference,
+                        }
+                    }),
+                )
+            })
+            .collect();
+
+        let subpass_descriptions: Vec<_> = references
+            .iter()
+            .enumerate()
+            .map(|(i, (color, input, depth))| {
+                let description = vk::SubpassDescription2::default()
+                    .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+                    .view_mask(self.view_masks.get(i).copied().unwrap_or(0))
+                    .color_attachments(color)
+                    .input_attachments(input);
+                match depth {
+                    Some(depth) => description.depth_stencil_attachment(depth),
+                    None => description,
+                }
+            })
+            .collect();
+
+        let dependencies: Vec<_> = self
+            .dependencies
+            .iter()
+            .map(|dependency| {
+                vk::SubpassDependency2::default()
+                    .src_subpass(dependency.src_subpass)
+                    .dst_subpass(dependency.dst_subpass)
+                    .src_stage_mask(dependency.src_stage_mask)
+                    .dst_stage_mask(dependency.dst_stage_mask)
+                    .src_access_mask(dependency.src_access_mask)
+                    .dst_access_mask(dependency.dst_access_mask)
+                    .dependency_flags(dependency.dependency_flags)
+            })
+            .collect();
+
+        let render_pass_info = vk::RenderPassCreateInfo2::default()
+            .attachments(&attachments)
+            .subpasses(&subpass_descriptions)
+            .dependencies(&dependencies)
+            .correlated_view_masks(&self.correlation_masks);
+        unsafe { self.device.create_render_pass2(&render_pass_info) }
+    }
+}
+
+pub const DEFAULT_CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+pub struct RenderPass {
+    device: Arc<Device>,
+    _swapchain: Option<Arc<Swapchain>>,
+    render_pass: vk::RenderPass,
+    depth_formats: Vec<Option<vk::Format>>,
+    color_attachment_counts: Vec<u32>,
+    formats: Vec<vk::Format>,
+}
+
+impl RenderPass {
+    pub fn new(
+        device: Arc<Device>,
+        swapchain: Arc<Swapchain>,
+    ) -> Result<Self, RenderPassCreationError> {
+        RenderPassBuilder::new(device)
+            .swapchain_attachment(swapchain)
+            .subpass(SubpassInfo::new().color(0))
+            .dependency(
+                vk::SubpassDependency::default()
+                    .src_subpass(vk::SUBPASS_EXTERNAL)
+                    .dst_subpass(0)
+                    .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(vk::AccessFlags::empty())
+                    .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE),
+            )
+            .build()
+    }
+
+    pub fn get_subpass_count(&self) -> u32 {
+        self.depth_formats.len() as u32
+    }
+
+    ///
+    /// Number of color attachments subpass writes, 0 for subpasses that don't exist
+    ///
+    pub fn get_color_attachment_count(&self, subpass: u32) -> u32 {
+        self.color_attachment_counts
+            .get(subpass as usize)
+            .copied()
+            .unwrap_or(0)
+    }
+
+    ///
+    /// Format of the depth attachment used by subpass, if it has one
+    ///
+    pub fn get_depth_format(&self, subpass: u32) -> Option<vk::Format> {
+        self.depth_formats.get(subpass as usize).copied().flatten()
+    }
+
+    ///
+    /// One clear value per attachment: color for color, depth 1.0 for depth attachments
+    ///
+    pub(in crate::vk) fn get_clear_values(&self, color: [f32; 4]) -> Vec<vk::ClearValue> {
+        self.formats
+            .iter()
+            .map(|&format| {
+                if is_depth_format(format) {
+                    vk::ClearValue {
+                        depth_stencil: vk::ClearDepthStencilValue {
+                            depth: 1.0,
+                            stencil: 0,
+                        },
+                    }
+                } else {
+                    vk::ClearValue {
+                        color: vk::ClearColorValue { float32: color },
+                    }
+                }
+            })
+            .collect()
+    }
+
     pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::RenderPass {
         self.render_pass
     }
@@ -63,3 +669,75 @@ impl Drop for RenderPass {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const COLOR: vk::Format = vk::Format::B8G8R8A8_SRGB;
+
+    #[test]
+    fn input_attachment_from_previous_subpass() {
+        let subpasses = [
+            SubpassInfo::new().color(1),
+            SubpassInfo::new().color(0).input(1),
+        ];
+        assert!(check_subpasses(&[COLOR; 2], &subpasses).is_ok());
+    }
+
+    #[test]
+    fn input_attachment_out_of_range() {
+        let subpasses = [
+            SubpassInfo::new().color(0),
+            SubpassInfo::new().color(0).input(1),
+        ];
+        assert!(matches!(
+            check_subpasses(&[COLOR], &subpasses),
+            Err(RenderPassCreationError::InvalidAttachment {
+                subpass: 1,
+                attachment: 1,
+                count: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn no_subpasses() {
+        assert!(matches!(
+            check_subpasses(&[COLOR], &[]),
+            Err(RenderPassCreationError::NoSubpasses)
+        ));
+    }
+
+    #[test]
+    fn stencil_layouts() {
+        let formats = [COLOR, vk::Format::D24_UNORM_S8_UINT, vk::Format::D32_SFLOAT];
+        let layouts = |attachment| {
+            [(
+                attachment,
+                vk::ImageLayout::UNDEFINED,
+                vk::ImageLayout::STENCIL_ATTACHMENT_OPTIMAL,
+            )]
+        };
+        assert!(check_stencil_layouts(&formats, &layouts(1)).is_ok());
+        for attachment in [0, 2, 3] {
+            assert!(matches!(
+                check_stencil_layouts(&formats, &layouts(attachment)),
+                Err(RenderPassCreationError::NotStencilAttachment(a)) if a == attachment
+            ));
+        }
+    }
+
+    #[test]
+    fn depth_attachment() {
+        let subpasses = [SubpassInfo::new().color(0).depth(1)];
+        assert!(check_subpasses(&[COLOR, vk::Format::D32_SFLOAT], &subpasses).is_ok());
+        assert!(matches!(
+            check_subpasses(&[COLOR, COLOR], &subpasses),
+            Err(RenderPassCreationError::NotDepthAttachment {
+                subpass: 0,
+                attachment: 1
+            })
+        ));
+    }
+}
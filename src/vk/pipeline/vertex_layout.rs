@@ -0,0 +1,189 @@
+use ash::vk;
+
+#[derive(Debug, thiserror::Error)]
+pub enum VertexLayoutError {
+    #[error("vertex format {0:?} is not supported by VertexLayout")]
+    UnsupportedFormat(vk::Format),
+    #[error("attribute at location {location} ends at byte {end}, past the stride of {stride}")]
+    AttributeOutOfBounds {
+        location: u32,
+        end: u32,
+        stride: u32,
+    },
+    #[error("location {0} is used by more than one attribute")]
+    DuplicateLocation(u32),
+    #[error("binding {0} is used by more than one vertex layout")]
+    DuplicateBinding(u32),
+}
+
+///
+/// Size in bytes of a vertex attribute format, None for formats VertexLayout doesn't know
+///
+fn vertex_format_size(format: vk::Format) -> Option<u32> {
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM
+        | vk::Format::R8G8B8A8_UINT
+        | vk::Format::R8G8B8A8_SINT
+        | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R16G16_SFLOAT
+        | vk::Format::R16G16_UNORM
+        | vk::Format::R16G16_SNORM
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2B10G10R10_SNORM_PACK32
+        | vk::Format::R32_SFLOAT
+        | vk::Format::R32_UINT
+        | vk::Format::R32_SINT => Some(4),
+        vk::Format::R16G16B16A16_SFLOAT
+        | vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16B16A16_SNORM
+        | vk::Format::R32G32_SFLOAT
+        | vk::Format::R32G32_UINT
+        | vk::Format::R32G32_SINT => Some(8),
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_UINT | vk::Format::R32G32B32_SINT => {
+            Some(12)
+        }
+        vk::Format::R32G32B32A32_SFLOAT
+        | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R32G32B32A32_SINT => Some(16),
+        _ => None,
+    }
+}
+
+///
+/// Attributes of one vertex buffer binding, offsets are relative to the start of a vertex
+///
+pub struct VertexLayoutBuilder {
+    binding: u32,
+    stride: u32,
+    input_rate: vk::VertexInputRate,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexLayoutBuilder {
+    ///
+    /// stride is usually size_of of the vertex struct
+    ///
+    pub fn new(binding: u32, stride: u32) -> Self {
+        Self {
+            binding,
+            stride,
+            input_rate: vk::VertexInputRate::VERTEX,
+            attributes: Vec::new(),
+        }
+    }
+
+    ///
+    /// Advances the binding per instance instead of per vertex
+    ///
+    pub fn per_instance(mut self, per_instance: bool) -> Self {
+        self.input_rate = if per_instance {
+            vk::VertexInputRate::INSTANCE
+        } else {
+            vk::VertexInputRate::VERTEX
+        };
+        self
+    }
+
+    ///
+    /// offset is usually offset_of of the field
+    ///
+    pub fn attribute(mut self, location: u32, format: vk::Format, offset: u32) -> Self {
+        self.attributes.push(
+            vk::VertexInputAttributeDescription::default()
+                .location(location)
+                .binding(self.binding)
+                .format(format)
+                .offset(offset),
+        );
+        self
+    }
+
+    ///
+    /// Checks that every attribute fits into the stride and locations are unique
+    ///
+    pub fn build(self) -> Result<VertexLayout, VertexLayoutError> {
+        for (i, attribute) in self.attributes.iter().enumerate() {
+            let size = vertex_format_size(attribute.format)
+                .ok_or(VertexLayoutError::UnsupportedFormat(attribute.format))?;
+            let end = attribute.offset + size;
+            if end > self.stride {
+                return Err(VertexLayoutError::AttributeOutOfBounds {
+                    location: attribute.location,
+                    end,
+                    stride: self.stride,
+                });
+            }
+            if self.attributes[..i]
+                .iter()
+                .any(|other| other.location == attribute.location)
+            {
+                return Err(VertexLayoutError::DuplicateLocation(attribute.location));
+            }
+        }
+        Ok(VertexLayout {
+            binding: vk::VertexInputBindingDescription::default()
+                .binding(self.binding)
+                .stride(self.stride)
+                .input_rate(self.input_rate),
+            attributes: self.attributes,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct VertexLayout {
+    binding: vk::VertexInputBindingDescription,
+    attributes: Vec<vk::VertexInputAttributeDescription>,
+}
+
+impl VertexLayout {
+    pub fn get_binding_description(&self) -> vk::VertexInputBindingDescription {
+        self.binding
+    }
+
+    pub fn get_attribute_descriptions(&self) -> &[vk::VertexInputAttributeDescription] {
+        &self.attributes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attributes_fit_stride() {
+        let layout = VertexLayoutBuilder::new(0, 20)
+            .attribute(0, vk::Format::R32G32B32_SFLOAT, 0)
+            .attribute(1, vk::Format::R32G32_SFLOAT, 12)
+            .build()
+            .unwrap();
+        assert_eq!(layout.get_binding_description().stride, 20);
+        assert_eq!(layout.get_attribute_descriptions().len(), 2);
+
+        let result = VertexLayoutBuilder::new(0, 16)
+            .attribute(0, vk::Format::R32G32B32_SFLOAT, 0)
+            .attribute(1, vk::Format::R32G32_SFLOAT, 12)
+            .build();
+        assert!(matches!(
+            result,
+            Err(VertexLayoutError::AttributeOutOfBounds {
+                location: 1,
+                end: 20,
+                stride: 16
+            })
+        ));
+    }
+
+    #[test]
+    fn duplicate_location() {
+        let result = VertexLayoutBuilder::new(0, 32)
+            .attribute(0, vk::Format::R32G32B32A32_SFLOAT, 0)
+            .attribute(0, vk::Format::R32G32B32A32_SFLOAT, 16)
+            .build();
+        assert!(matches!(
+            result,
+            Err(VertexLayoutError::DuplicateLocation(0))
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+use std::{error::Error, sync::Arc};
+
+use super::{
+    command_buffer::{CommandBuffer, CommandRecordingError},
+    command_pool::CommandPool,
+    device::Device,
+    image::Image,
+    pipeline::{GraphicsPipeline, GraphicsPipelineBuilder},
+    sampled_pass::{SampledPass, builtin_stage},
+    sampler::Sampler,
+    shader::{ShaderStage, ShaderStageInfo},
+    swapchain::Swapchain,
+};
+
+///
+/// SPIR-V of the vertex shader covering the screen with a single triangle:
+///
+/// ```glsl
+/// #version 450
+/// layout(location = 0) out vec2 uv;
+/// void main() {
+///     uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+///     gl_Position = vec4(uv * 2.0 - 1.0, 0.0, 1.0);
+/// }
+/// ```
+///
+const FULLSCREEN_TRIANGLE_VERT: [u32; 173] = [
+    0x07230203, 0x00010000, 0x00000000, 0x00000021, 0x00000000, 0x00020011, 0x00000001, 0x0003000e,
+    0x00000000, 0x00000001, 0x0008000f, 0x00000000, 0x00000001, 0x6e69616d, 0x00000000, 0x00000002,
+    0x00000003, 0x00000004, 0x00040047, 0x00000002, 0x0000001e, 0x00000000, 0x00040047, 0x00000003,
+    0x0000000b, 0x0000002a, 0x00040047, 0x00000004, 0x0000000b, 0x00000000, 0x00020013, 0x00000005,
+    0x00030021, 0x00000006, 0x00000005, 0x00040015, 0x00000007, 0x00000020, 0x00000001, 0x00030016,
+    0x00000008, 0x00000020, 0x00040017, 0x00000009, 0x00000008, 0x00000002, 0x00040017, 0x0000000a,
+    0x00000008, 0x00000004, 0x00040020, 0x0000000b, 0x00000001, 0x00000007, 0x00040020, 0x0000000c,
+    0x00000003, 0x00000009, 0x00040020, 0x0000000d, 0x00000003, 0x0000000a, 0x0004003b, 0x0000000b,
+    0x00000003, 0x00000001, 0x0004003b, 0x0000000c, 0x00000002, 0x00000003, 0x0004003b, 0x0000000d,
+    0x00000004, 0x00000003, 0x0004002b, 0x00000007, 0x0000000e, 0x00000001, 0x0004002b, 0x00000007,
+    0x0000000f, 0x00000002, 0x0004002b, 0x00000008, 0x00000010, 0x00000000, 0x0004002b, 0x00000008,
+    0x00000011, 0x3f800000, 0x0004002b, 0x00000008, 0x00000012, 0x40000000, 0x0005002c, 0x00000009,
+    0x00000013, 0x00000011, 0x00000011, 0x00050036, 0x00000005, 0x00000001, 0x00000000, 0x00000006,
+    0x000200f8, 0x00000014, 0x0004003d, 0x00000007, 0x00000015, 0x00000003, 0x000500c4, 0x00000007,
+    0x00000016, 0x00000015, 0x0000000e, 0x000500c7, 0x00000007, 0x00000017, 0x00000016, 0x0000000f,
+    0x000500c7, 0x00000007, 0x00000018, 0x00000015, 0x0000000f, 0x0004006f, 0x00000008, 0x00000019,
+    0x00000017, 0x0004006f, 0x00000008, 0x0000001a, 0x00000018, 0x00050050, 0x00000009, 0x0000001b,
+    0x00000019, 0x0000001a, 0x0003003e, 0x00000002, 0x0000001b, 0x0005008e, 0x00000009, 0x0000001c,
+    0x0000001b, 0x00000012, 0x00050083, 0x00000009, 0x0000001d, 0x0000001c, 0x00000013, 0x00050051,
+    0x00000008, 0x0000001e, 0x0000001d, 0x00000000, 0x00050051, 0x00000008, 0x0000001f, 0x0000001d,
+    0x00000001, 0x00070050, 0x0000000a, 0x00000020, 0x0000001e, 0x0000001f, 0x00000010, 0x00000011,
+    0x0003003e, 0x00000004, 0x00000020, 0x000100fd, 0x00010038,
+];
+
+///
+/// Fragment-only pass over the swapchain images, e.g. tone mapping or FXAA.
+/// The fragment shader reads uv at location 0 and samples the input image
+/// through a combined image sampler at set 0, binding 0
+///
+pub struct PostProcessPass {
+    pass: SampledPass,
+}
+
+impl PostProcessPass {
+    ///
+    /// input has to be in SHADER_READ_ONLY_OPTIMAL layout whenever the command buffers execute
+    ///
+    pub fn new(
+        device: Arc<Device>,
+        swapchain: Arc<Swapchain>,
+        command_pool: Arc<CommandPool>,
+        fragment: ShaderStageInfo,
+        input: &Image,
+        sampler: Arc<Sampler>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let vertex = builtin_stage(&device, &FULLSCREEN_TRIANGLE_VERT, ShaderStage::Vertex)?;
+        let builder = GraphicsPipelineBuilder::new(Arc::clone(&device), swapchain, command_pool)
+            .add_stage(String::from("fullscreen_triangle"), vertex)
+            .add_stage(String::from("post_process"), fragment);
+        Ok(Self {
+            pass: SampledPass::new(device, builder, input, sampler, None, 3)?,
+        })
+    }
+
+    ///
+    /// Records begin pass -> bind -> draw(3) -> end for every swapchain image,
+    /// refreshing the pipeline's command buffers replays the same recording.
+    /// None of the command buffers may be pending execution
+    ///
+    pub fn record_command_buffers(&mut self) -> Result<(), CommandRecordingError> {
+        self.pass.record_command_buffers()
+    }
+
+    pub fn get_pipeline(&self) -> &GraphicsPipeline {
+        self.pass.get_pipeline()
+    }
+
+    pub fn get_command_buffer(&self, index: u32) -> Arc<CommandBuffer> {
+        self.pass.get_command_buffer(index)
+    }
+}
@@ -0,0 +1,124 @@
+use std::{error::Error, sync::Arc};
+
+use ash::vk;
+
+use super::{
+    buffer::Buffer,
+    command_buffer::{CommandBuffer, CommandRecordingError, DrawInfo},
+    descriptor::{DescriptorPoolBuilder, DescriptorSet, DescriptorSetLayoutBuilder},
+    device::Device,
+    image::Image,
+    pipeline::{GraphicsPipeline, GraphicsPipelineBuilder},
+    sampler::Sampler,
+    shader::{ShaderModule, ShaderStage, ShaderStageError, ShaderStageInfo},
+};
+
+///
+/// Stage of a shader built into the crate, with main as entry point
+///
+pub(in crate::vk) fn builtin_stage(
+    device: &Arc<Device>,
+    code: &[u32],
+    stage: ShaderStage,
+) -> Result<ShaderStageInfo, ShaderStageError> {
+    ShaderStageInfo::new(
+        Arc::new(ShaderModule::new(Arc::clone(device), code)),
+        stage,
+        String::from("main"),
+    )
+}
+
+///
+/// Pipeline sampling one image through a combined image sampler at set 0, binding 0,
+/// with an optional vertex buffer at binding 0. Every command buffer binds both and
+/// draws vertex_count vertices
+///
+pub(in crate::vk) struct SampledPass {
+    pipeline: GraphicsPipeline,
+    descriptor_set: Arc<DescriptorSet>,
+    vertex_buffer: Option<Arc<Buffer>>,
+    vertex_count: u32,
+}
+
+impl SampledPass {
+    ///
+    /// builder only needs the shader stages and vertex layout, the descriptor set layout
+    /// and recording are added here. image has to be in SHADER_READ_ONLY_OPTIMAL layout
+    /// whenever the command buffers execute
+    ///
+    pub fn new(
+        device: Arc<Device>,
+        builder: GraphicsPipelineBuilder,
+        image: &Image,
+        sampler: Arc<Sampler>,
+        vertex_buffer: Option<Arc<Buffer>>,
+        vertex_count: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let set_layout = Arc::new(
+            DescriptorSetLayoutBuilder::new(Arc::clone(&device))
+                .combined_image_sampler(0, ShaderStage::Fragment)
+                .build(),
+        );
+        let pool = DescriptorPoolBuilder::new(device, 1)
+            .pool_size(vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 1)
+            .build();
+        let descriptor_set = Arc::new(pool.allocate(Arc::clone(&set_layout))?);
+        descriptor_set.write_combined_image_sampler(
+            0,
+            image,
+            sampler,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        );
+
+        // Nothing can be drawn before the descriptor set is bound
+        let pipeline = builder
+            .descriptor_set_layout(set_layout)
+            .draws(Vec::new())
+            .build()?;
+
+        let mut pass = Self {
+            pipeline,
+            descriptor_set,
+            vertex_buffer,
+            vertex_count,
+        };
+        pass.record_command_buffers()?;
+        Ok(pass)
+    }
+
+    ///
+    /// Records begin pass -> bind -> draw -> end for every swapchain image,
+    /// refreshing the pipeline's command buffers replays the same recording.
+    /// None of the command buffers may be pending execution
+    ///
+    pub fn record_command_buffers(&mut self) -> Result<(), CommandRecordingError> {
+        let layout = self.pipeline.get_layout();
+        let descriptor_set = Arc::clone(&self.descriptor_set);
+        let vertex_buffer = self.vertex_buffer.clone();
+        let vertex_count = self.vertex_count;
+        self.pipeline.set_recording(move |command_buffer| {
+            command_buffer.cmd_bind_graphics_descriptor_set(
+                Arc::clone(&layout),
+                0,
+                Arc::clone(&descriptor_set),
+            )?;
+            if let Some(vertex_buffer) = &vertex_buffer {
+                command_buffer.cmd_bind_vertex_buffers(0, &[vertex_buffer], &[0])?;
+            }
+            command_buffer.cmd_draw(DrawInfo {
+                vertex_count,
+                instance_count: 1,
+                ..Default::default()
+            })?;
+            Ok(())
+        })
+    }
+
+    pub fn get_pipeline(&self) -> &GraphicsPipeline {
+        &self.pipeline
+    }
+
+    pub fn get_command_buffer(&self, index: u32) -> Arc<CommandBuffer> {
+        self.pipeline.get_command_buffer(index)
+    }
+}
@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use ash::vk;
+
+use super::{device::Device, error::fatal_vk_error};
+
+pub struct SamplerBuilder {
+    device: Arc<Device>,
+    filter: vk::Filter,
+    mipmap_mode: vk::SamplerMipmapMode,
+    address_mode: vk::SamplerAddressMode,
+    max_anisotropy: Option<f32>,
+    mip_lod_bias: f32,
+    min_lod: f32,
+    max_lod: f32,
+}
+
+impl SamplerBuilder {
+    pub fn new(device: Arc<Device>) -> Self {
+        Self {
+            device,
+            filter: vk::Filter::LINEAR,
+            mipmap_mode: vk::SamplerMipmapMode::LINEAR,
+            address_mode: vk::SamplerAddressMode::REPEAT,
+            max_anisotropy: None,
+            mip_lod_bias: 0.0f32,
+            min_lod: 0.0f32,
+            max_lod: vk::LOD_CLAMP_NONE,
+        }
+    }
+    pub fn filter(mut self, filter: vk::Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+    pub fn mipmap_mode(mut self, mipmap_mode: vk::SamplerMipmapMode) -> Self {
+        self.mipmap_mode = mipmap_mode;
+        self
+    }
+    pub fn address_mode(mut self, address_mode: vk::SamplerAddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+    ///
+    /// Falls back to no anisotropic filtering if the sampler_anisotropy feature is not
+    /// enabled on the device. Clamped to the device limit
+    ///
+    pub fn anisotropy(mut self, max_anisotropy: f32) -> Self {
+        self.max_anisotropy = Some(max_anisotropy);
+        self
+    }
+    ///
+    /// Negative bias selects more detailed mip levels
+    ///
+    pub fn mip_lod_bias(mut self, mip_lod_bias: f32) -> Self {
+        self.mip_lod_bias = mip_lod_bias;
+        self
+    }
+    pub fn lod_range(mut self, min_lod: f32, max_lod: f32) -> Self {
+        self.min_lod = min_lod;
+        self.max_lod = max_lod;
+        self
+    }
+
+    fn choose_anisotropy(&self) -> Option<f32> {
+        let requested = self.max_anisotropy?;
+        if self.device.get_enabled_features().sampler_anisotropy == vk::FALSE {
+            log::warn!(
+                "Anisotropy {} was requested, but sampler_anisotropy is not enabled. Falling back to no anisotropic filtering",
+                requested
+            );
+            return None;
+        }
+        let limit = self.device.get_properties().limits.max_sampler_anisotropy;
+        Some(requested.min(limit))
+    }
+
+    pub fn build(self) -> Sampler {
+        let anisotropy = self.choose_anisotropy();
+        let create_info = vk::SamplerCreateInfo::default()
+            .mag_filter(self.filter)
+            .min_filter(self.filter)
+            .mipmap_mode(self.mipmap_mode)
+            .address_mode_u(self.address_mode)
+            .address_mode_v(self.address_mode)
+            .address_mode_w(self.address_mode)
+            .anisotropy_enable(anisotropy.is_some())
+            .max_anisotropy(anisotropy.unwrap_or(1.0f32))
+            .mip_lod_bias(self.mip_lod_bias)
+            .min_lod(self.min_lod)
+            .max_lod(self.max_lod);
+        let sampler = unsafe { self.device.raw_handle().create_sampler(&create_info, None) }
+            .unwrap_or_else(|error| fatal_vk_error("failed to create_sampler", error));
+
+        Sampler {
+            device: self.device,
+            sampler,
+        }
+    }
+}
+
+pub struct Sampler {
+    device: Arc<Device>,
+    sampler: vk::Sampler,
+}
+
+impl Sampler {
+    pub(in crate::vk) unsafe fn raw_handle(&self) -> vk::Sampler {
+        self.sampler
+    }
+}
+
+impl Drop for Sampler {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.raw_handle().destroy_sampler(self.sampler, None);
+        }
+    }
+}
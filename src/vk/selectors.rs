@@ -110,4 +110,12 @@ impl QueueFamilySelector for DrawQueueFamilySelector {
             graphics: queues_raw.iter().find(|(id, _queues)| *id == g).unwrap().1[0].clone(),
         }
     }
+
+    fn graphics_family(&self) -> Option<u32> {
+        self.graphics
+    }
+
+    fn present_family(&self) -> Option<u32> {
+        self.present
+    }
 }
@@ -26,6 +26,21 @@ impl Semaphore {
     }
 }
 
+///
+/// Semaphore waited on by a submission before the commands reach stage
+///
+#[derive(Clone, Copy)]
+pub struct WaitSemaphore<'a> {
+    pub semaphore: &'a Semaphore,
+    pub stage: vk::PipelineStageFlags,
+}
+
+impl<'a> WaitSemaphore<'a> {
+    pub fn new(semaphore: &'a Semaphore, stage: vk::PipelineStageFlags) -> Self {
+        Self { semaphore, stage }
+    }
+}
+
 impl Drop for Semaphore {
     fn drop(&mut self) {
         unsafe {
@@ -1,23 +1,41 @@
+#[cfg(feature = "reflect")]
+pub mod reflect;
+
 use std::{ffi::CString, sync::Arc};
 
 use ash::vk;
 
 use super::device::Device;
+#[cfg(feature = "reflect")]
+use reflect::{EntryPoint, ReflectError, ShaderReflection};
 
+///
+/// The stage and entry point a ShaderStageInfo was declared with don't fit its module
+///
 #[derive(Debug, thiserror::Error)]
-#[error("Shader stage {stage} is required but missing")]
-pub struct MissingShaderStageError {
-    stage: ShaderStage,
-}
-impl MissingShaderStageError {
-    pub fn new(stage: ShaderStage) -> Self {
-        Self { stage }
-    }
+pub enum ShaderStageError {
+    #[error("entry point name {0:?} contains a nul byte")]
+    InvalidEntryPoint(String),
+    #[cfg(feature = "reflect")]
+    #[error("shader module has no entry point {0}")]
+    MissingEntryPoint(String),
+    #[cfg(feature = "reflect")]
+    #[error("entry point {entry_point} was declared as a {declared} shader, but is {actual:?}")]
+    StageMismatch {
+        entry_point: String,
+        declared: ShaderStage,
+        actual: Option<ShaderStage>,
+    },
+    #[cfg(feature = "reflect")]
+    #[error(transparent)]
+    Reflect(#[from] ReflectError),
 }
 
 pub struct ShaderModule {
     device: Arc<Device>,
     shader: vk::ShaderModule,
+    #[cfg(feature = "reflect")]
+    code: Vec<u32>,
 }
 
 impl ShaderModule {
@@ -25,8 +43,26 @@ impl ShaderModule {
         Self {
             shader: unsafe { device.create_shader_module(shader_raw) },
             device,
+            #[cfg(feature = "reflect")]
+            code: shader_raw.to_vec(),
         }
     }
+
+    ///
+    /// Descriptor bindings and push constants the SPIR-V code uses
+    ///
+    #[cfg(feature = "reflect")]
+    pub fn reflect(&self) -> Result<ShaderReflection, ReflectError> {
+        reflect::reflect(&self.code)
+    }
+
+    ///
+    /// Entry points the SPIR-V code declares, cheaper than reflect
+    ///
+    #[cfg(feature = "reflect")]
+    pub fn get_entry_points(&self) -> Result<Vec<EntryPoint>, ReflectError> {
+        reflect::entry_points(&self.code)
+    }
 }
 
 impl Drop for ShaderModule {
@@ -72,16 +108,31 @@ pub struct ShaderStageInfo {
 }
 
 impl ShaderStageInfo {
-    pub fn new(shader: Arc<ShaderModule>, stage: ShaderStage, entry_point: String) -> Self {
-        Self {
+    ///
+    /// With the reflect feature, checks that the module has entry_point and that
+    /// its execution model is one of the stages in stage. Resources are not reflected
+    ///
+    pub fn new(
+        shader: Arc<ShaderModule>,
+        stage: ShaderStage,
+        entry_point: String,
+    ) -> Result<Self, ShaderStageError> {
+        #[cfg(feature = "reflect")]
+        check_entry_point(&shader.get_entry_points()?, stage, &entry_point)?;
+        Ok(Self {
             stage,
-            entry_point: CString::new(entry_point).expect("invalid entry_point"),
+            entry_point: CString::new(entry_point.as_str())
+                .map_err(|_| ShaderStageError::InvalidEntryPoint(entry_point))?,
             shader,
-        }
+        })
     }
     pub fn stage(&self) -> ShaderStage {
         self.stage
     }
+    #[cfg(feature = "reflect")]
+    pub fn reflect(&self) -> Result<ShaderReflection, ReflectError> {
+        self.shader.reflect()
+    }
     pub fn info(&self) -> vk::PipelineShaderStageCreateInfo<'_> {
         vk::PipelineShaderStageCreateInfo::default()
             .module(self.shader.shader)
@@ -89,3 +140,71 @@ impl ShaderStageInfo {
             .stage(self.stage.into())
     }
 }
+
+#[cfg(feature = "reflect")]
+fn check_entry_point(
+    entry_points: &[EntryPoint],
+    stage: ShaderStage,
+    entry_point: &str,
+) -> Result<(), ShaderStageError> {
+    let Some(found) = entry_points.iter().find(|found| found.name == entry_point) else {
+        return Err(ShaderStageError::MissingEntryPoint(entry_point.to_owned()));
+    };
+    let declared = vk::ShaderStageFlags::from(stage);
+    let matches = match found.stage {
+        Some(actual) => declared.contains(actual.into()),
+        None => stage == ShaderStage::All,
+    };
+    if matches {
+        Ok(())
+    } else {
+        Err(ShaderStageError::StageMismatch {
+            entry_point: entry_point.to_owned(),
+            declared: stage,
+            actual: found.stage,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "reflect"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn entry_point_stages() {
+        let entry_points = [
+            EntryPoint {
+                name: "main".to_owned(),
+                stage: Some(ShaderStage::Fragment),
+            },
+            EntryPoint {
+                name: "raygen".to_owned(),
+                stage: None,
+            },
+        ];
+        for stage in [
+            ShaderStage::Fragment,
+            ShaderStage::AllGraphics,
+            ShaderStage::All,
+        ] {
+            assert!(check_entry_point(&entry_points, stage, "main").is_ok());
+        }
+        assert!(matches!(
+            check_entry_point(&entry_points, ShaderStage::Vertex, "main"),
+            Err(ShaderStageError::StageMismatch { .. })
+        ));
+        assert!(matches!(
+            check_entry_point(&entry_points, ShaderStage::Compute, "main"),
+            Err(ShaderStageError::StageMismatch { .. })
+        ));
+        assert!(check_entry_point(&entry_points, ShaderStage::All, "raygen").is_ok());
+        assert!(matches!(
+            check_entry_point(&entry_points, ShaderStage::AllGraphics, "raygen"),
+            Err(ShaderStageError::StageMismatch { .. })
+        ));
+        assert!(matches!(
+            check_entry_point(&entry_points, ShaderStage::All, "mai"),
+            Err(ShaderStageError::MissingEntryPoint(_))
+        ));
+    }
+}
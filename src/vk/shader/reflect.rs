@@ -0,0 +1,734 @@
+use std::collections::HashMap;
+
+use ash::vk;
+
+use super::ShaderStage;
+
+const MAGIC: u32 = 0x0723_0203;
+const HEADER_LEN: usize = 5;
+
+const OP_ENTRY_POINT: u32 = 15;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+
+const DECORATION_BLOCK: u32 = 2;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const EXECUTION_MODEL_VERTEX: u32 = 0;
+const EXECUTION_MODEL_TESSELLATION_CONTROL: u32 = 1;
+const EXECUTION_MODEL_TESSELLATION_EVALUATION: u32 = 2;
+const EXECUTION_MODEL_GEOMETRY: u32 = 3;
+const EXECUTION_MODEL_FRAGMENT: u32 = 4;
+const EXECUTION_MODEL_GL_COMPUTE: u32 = 5;
+
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReflectError {
+    #[error("code is not SPIR-V")]
+    InvalidMagic,
+    #[error("instruction at word {0} is truncated")]
+    Truncated(usize),
+    #[error("id {0} is not a type usable by a resource")]
+    UnsupportedType(u32),
+    #[error("binding {binding} of set {set} has a different type or count in another stage")]
+    BindingMismatch { set: u32, binding: u32 },
+    #[error("binding {binding} of set {set} is a runtime sized array without a descriptor count")]
+    RuntimeArray { set: u32, binding: u32 },
+    #[error("size of type {0} does not fit in 32 bits")]
+    SizeOverflow(u32),
+}
+
+///
+/// A descriptor binding a shader uses. count is 0 for runtime sized arrays
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    pub count: u32,
+}
+
+///
+/// An entry point of a shader module. stage is None for execution models
+/// ShaderStage has no variant for, e.g. mesh or ray tracing shaders
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryPoint {
+    pub name: String,
+    pub stage: Option<ShaderStage>,
+}
+
+///
+/// Resources used by a shader module, see ShaderModule::reflect
+///
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShaderReflection {
+    entry_points: Vec<EntryPoint>,
+    bindings: Vec<ReflectedBinding>,
+    push_constants: Option<(u32, u32)>,
+}
+
+impl ShaderReflection {
+    pub fn get_entry_points(&self) -> &[EntryPoint] {
+        &self.entry_points
+    }
+
+    pub fn get_entry_point(&self, name: &str) -> Option<&EntryPoint> {
+        self.entry_points
+            .iter()
+            .find(|entry_point| entry_point.name == name)
+    }
+
+    ///
+    /// Sorted by set and binding
+    ///
+    pub fn get_bindings(&self) -> &[ReflectedBinding] {
+        &self.bindings
+    }
+
+    ///
+    /// Range of the push constant block, without stage flags
+    ///
+    pub fn get_push_constant_range(&self) -> Option<vk::PushConstantRange> {
+        self.push_constants
+            .map(|(offset, size)| vk::PushConstantRange::default().offset(offset).size(size))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Type {
+    Scalar { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { storage_class: u32, pointee: u32 },
+    AccelerationStructure,
+}
+
+#[derive(Default)]
+struct Module {
+    entry_points: Vec<EntryPoint>,
+    types: HashMap<u32, Type>,
+    constants: HashMap<u32, u32>,
+    variables: Vec<(u32, u32)>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_offsets: HashMap<(u32, u32), u32>,
+    member_matrix_strides: HashMap<(u32, u32), u32>,
+}
+
+impl Module {
+    fn parse(code: &[u32]) -> Result<Self, ReflectError> {
+        let mut module = Self::default();
+        for_each_instruction(code, |opcode, operands| {
+            module.parse_instruction(opcode, operands)
+        })?;
+        Ok(module)
+    }
+
+    fn parse_instruction(&mut self, opcode: u32, operands: &[u32]) {
+        let operand = |i: usize| operands.get(i).copied().unwrap_or(0);
+        let ty = match opcode {
+            OP_ENTRY_POINT => {
+                self.entry_points.push(entry_point(operands));
+                return;
+            }
+            OP_DECORATE => {
+                self.decorations
+                    .insert((operand(0), operand(1)), operand(2));
+                return;
+            }
+            OP_MEMBER_DECORATE => {
+                let key = (operand(0), operand(1));
+                match operand(2) {
+                    DECORATION_OFFSET => self.member_offsets.insert(key, operand(3)),
+                    DECORATION_MATRIX_STRIDE => self.member_matrix_strides.insert(key, operand(3)),
+                    _ => None,
+                };
+                return;
+            }
+            OP_CONSTANT => {
+                self.constants.insert(operand(1), operand(2));
+                return;
+            }
+            OP_VARIABLE => {
+                self.variables.push((operand(0), operand(1)));
+                return;
+            }
+            OP_TYPE_BOOL => Type::Scalar { width: 32 },
+            OP_TYPE_INT | OP_TYPE_FLOAT => Type::Scalar { width: operand(1) },
+            OP_TYPE_VECTOR => Type::Vector {
+                component: operand(1),
+                count: operand(2),
+            },
+            OP_TYPE_MATRIX => Type::Matrix {
+                column: operand(1),
+                count: operand(2),
+            },
+            OP_TYPE_IMAGE => Type::Image {
+                dim: operand(2),
+                sampled: operand(6),
+            },
+            OP_TYPE_SAMPLER => Type::Sampler,
+            OP_TYPE_SAMPLED_IMAGE => Type::SampledImage,
+            OP_TYPE_ARRAY => Type::Array {
+                element: operand(1),
+                length: operand(2),
+            },
+            OP_TYPE_RUNTIME_ARRAY => Type::RuntimeArray {
+                element: operand(1),
+            },
+            OP_TYPE_STRUCT => Type::Struct {
+                members: operands.get(1..).unwrap_or_default().to_vec(),
+            },
+            OP_TYPE_POINTER => Type::Pointer {
+                storage_class: operand(1),
+                pointee: operand(2),
+            },
+            OP_TYPE_ACCELERATION_STRUCTURE => Type::AccelerationStructure,
+            _ => return,
+        };
+        self.types.insert(operand(0), ty);
+    }
+
+    fn get_type(&self, id: u32) -> Result<&Type, ReflectError> {
+        self.types.get(&id).ok_or(ReflectError::UnsupportedType(id))
+    }
+
+    fn has_decoration(&self, id: u32, decoration: u32) -> bool {
+        self.decorations.contains_key(&(id, decoration))
+    }
+
+    ///
+    /// Descriptor type and count of a resource of type id in storage_class
+    ///
+    fn descriptor(
+        &self,
+        id: u32,
+        storage_class: u32,
+    ) -> Result<(vk::DescriptorType, u32), ReflectError> {
+        let descriptor_type = match self.get_type(id)? {
+            Type::Array { element, length } => {
+                let (descriptor_type, _) = self.descriptor(*element, storage_class)?;
+                let length = self.constants.get(length).copied().unwrap_or(1);
+                return Ok((descriptor_type, length));
+            }
+            Type::RuntimeArray { element } => {
+                let (descriptor_type, _) = self.descriptor(*element, storage_class)?;
+                return Ok((descriptor_type, 0));
+            }
+            Type::Sampler => vk::DescriptorType::SAMPLER,
+            Type::SampledImage => vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            Type::Image { dim, sampled } => match (*dim, *sampled) {
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            Type::AccelerationStructure => vk::DescriptorType::ACCELERATION_STRUCTURE_KHR,
+            Type::Struct { .. } if storage_class == STORAGE_CLASS_STORAGE_BUFFER => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            Type::Struct { .. } if self.has_decoration(id, DECORATION_BUFFER_BLOCK) => {
+                vk::DescriptorType::STORAGE_BUFFER
+            }
+            Type::Struct { .. } if self.has_decoration(id, DECORATION_BLOCK) => {
+                vk::DescriptorType::UNIFORM_BUFFER
+            }
+            _ => return Err(ReflectError::UnsupportedType(id)),
+        };
+        Ok((descriptor_type, 1))
+    }
+
+    ///
+    /// Size in bytes of type id with the explicit layout of a block.
+    /// matrix_stride applies if the type is a matrix member of a struct.
+    /// Pointers are physical storage buffer addresses of 8 bytes
+    ///
+    fn size(&self, id: u32, matrix_stride: Option<u32>) -> Result<u32, ReflectError> {
+        let size = match self.get_type(id)? {
+            Type::Scalar { width } => Some(width / 8),
+            Type::Vector { component, count } => self.size(*component, None)?.checked_mul(*count),
+            Type::Matrix { column, count } => match matrix_stride {
+                Some(stride) => stride.checked_mul(*count),
+                None => self.size(*column, None)?.checked_mul(*count),
+            },
+            Type::Array { element, length } => {
+                let length = self.constants.get(length).copied().unwrap_or(1);
+                let stride = match self.decorations.get(&(id, DECORATION_ARRAY_STRIDE)) {
+                    Some(&stride) => stride,
+                    None => self.size(*element, matrix_stride)?,
+                };
+                stride.checked_mul(length)
+            }
+            Type::RuntimeArray { .. } => Some(0),
+            Type::Struct { members } => Some(self.member_range(id, members)?.1),
+            Type::Pointer { .. } => Some(8),
+            _ => return Err(ReflectError::UnsupportedType(id)),
+        };
+        size.ok_or(ReflectError::SizeOverflow(id))
+    }
+
+    ///
+    /// Offset of the first member and end of the last member of struct id
+    ///
+    fn member_range(&self, id: u32, members: &[u32]) -> Result<(u32, u32), ReflectError> {
+        let mut start = u32::MAX;
+        let mut end = 0;
+        for (i, &member) in members.iter().enumerate() {
+            let key = (id, i as u32);
+            let offset = self.member_offsets.get(&key).copied().unwrap_or(0);
+            let size = self.size(member, self.member_matrix_strides.get(&key).copied())?;
+            start = start.min(offset);
+            end = end.max(
+                offset
+                    .checked_add(size)
+                    .ok_or(ReflectError::SizeOverflow(id))?,
+            );
+        }
+        Ok((start.min(end), end))
+    }
+
+    fn reflect(&self) -> Result<ShaderReflection, ReflectError> {
+        let mut reflection = ShaderReflection {
+            entry_points: self.entry_points.clone(),
+            ..Default::default()
+        };
+        for &(pointer, variable) in &self.variables {
+            let Type::Pointer {
+                storage_class,
+                pointee,
+            } = *self.get_type(pointer)?
+            else {
+                continue;
+            };
+            match storage_class {
+                STORAGE_CLASS_UNIFORM_CONSTANT
+                | STORAGE_CLASS_UNIFORM
+                | STORAGE_CLASS_STORAGE_BUFFER => {
+                    let (Some(&set), Some(&binding)) = (
+                        self.decorations.get(&(variable, DECORATION_DESCRIPTOR_SET)),
+                        self.decorations.get(&(variable, DECORATION_BINDING)),
+                    ) else {
+                        continue;
+                    };
+                    let (descriptor_type, count) = self.descriptor(pointee, storage_class)?;
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        descriptor_type,
+                        count,
+                    });
+                }
+                STORAGE_CLASS_PUSH_CONSTANT => {
+                    if let Type::Struct { members } = self.get_type(pointee)? {
+                        let (offset, end) = self.member_range(pointee, members)?;
+                        reflection.push_constants = Some((offset, end - offset));
+                    }
+                }
+                _ => {}
+            }
+        }
+        reflection
+            .bindings
+            .sort_by_key(|binding| (binding.set, binding.binding));
+        Ok(reflection)
+    }
+}
+
+///
+/// Calls f with the opcode and operands of every instruction after the header
+///
+fn for_each_instruction(code: &[u32], mut f: impl FnMut(u32, &[u32])) -> Result<(), ReflectError> {
+    if code.len() < HEADER_LEN || code[0] != MAGIC {
+        return Err(ReflectError::InvalidMagic);
+    }
+    let mut i = HEADER_LEN;
+    while i < code.len() {
+        let word_count = (code[i] >> 16) as usize;
+        let opcode = code[i] & 0xffff;
+        if word_count == 0 || i + word_count > code.len() {
+            return Err(ReflectError::Truncated(i));
+        }
+        f(opcode, &code[i + 1..i + word_count]);
+        i += word_count;
+    }
+    Ok(())
+}
+
+fn entry_point(operands: &[u32]) -> EntryPoint {
+    EntryPoint {
+        name: literal_string(operands.get(2..).unwrap_or_default()),
+        stage: execution_model_stage(operands.first().copied().unwrap_or(0)),
+    }
+}
+
+///
+/// Nul-terminated UTF-8 string packed little-endian into words
+///
+fn literal_string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn execution_model_stage(execution_model: u32) -> Option<ShaderStage> {
+    match execution_model {
+        EXECUTION_MODEL_VERTEX => Some(ShaderStage::Vertex),
+        EXECUTION_MODEL_TESSELLATION_CONTROL => Some(ShaderStage::TessellationControl),
+        EXECUTION_MODEL_TESSELLATION_EVALUATION => Some(ShaderStage::TessellationEvaluation),
+        EXECUTION_MODEL_GEOMETRY => Some(ShaderStage::Geometry),
+        EXECUTION_MODEL_FRAGMENT => Some(ShaderStage::Fragment),
+        EXECUTION_MODEL_GL_COMPUTE => Some(ShaderStage::Compute),
+        _ => None,
+    }
+}
+
+///
+/// Bindings of every set, indexed by set number, and push constant ranges of the stages
+/// together. Stages using the same binding must agree on its type and count, runtime
+/// sized arrays are rejected as their count can't be reflected
+///
+pub(in crate::vk) fn merge_reflections(
+    stages: &[(vk::ShaderStageFlags, ShaderReflection)],
+) -> Result<
+    (
+        Vec<Vec<vk::DescriptorSetLayoutBinding<'static>>>,
+        Vec<vk::PushConstantRange>,
+    ),
+    ReflectError,
+> {
+    let mut sets: Vec<Vec<vk::DescriptorSetLayoutBinding>> = Vec::new();
+    let mut push_constant_ranges = Vec::new();
+    for (stage_flags, reflection) in stages {
+        for binding in &reflection.bindings {
+            if binding.count == 0 {
+                return Err(ReflectError::RuntimeArray {
+                    set: binding.set,
+                    binding: binding.binding,
+                });
+            }
+            let set = binding.set as usize;
+            if sets.len() <= set {
+                sets.resize_with(set + 1, Vec::new);
+            }
+            match sets[set].iter_mut().find(|b| b.binding == binding.binding) {
+                Some(merged)
+                    if merged.descriptor_type == binding.descriptor_type
+                        && merged.descriptor_count == binding.count =>
+                {
+                    merged.stage_flags |= *stage_flags;
+                }
+                Some(_) => {
+                    return Err(ReflectError::BindingMismatch {
+                        set: binding.set,
+                        binding: binding.binding,
+                    });
+                }
+                None => sets[set].push(
+                    vk::DescriptorSetLayoutBinding::default()
+                        .binding(binding.binding)
+                        .descriptor_type(binding.descriptor_type)
+                        .descriptor_count(binding.count)
+                        .stage_flags(*stage_flags),
+                ),
+            }
+        }
+        if let Some(range) = reflection.get_push_constant_range() {
+            push_constant_ranges.push(range.stage_flags(*stage_flags));
+        }
+    }
+    Ok((sets, push_constant_ranges))
+}
+
+///
+/// Bindings and push constant range used by the SPIR-V code.
+/// Every resource variable of the module is reported, whether an entry point uses it or not
+///
+pub fn reflect(code: &[u32]) -> Result<ShaderReflection, ReflectError> {
+    Module::parse(code)?.reflect()
+}
+
+///
+/// Entry points declared by the SPIR-V code, without reflecting its resources
+///
+pub fn entry_points(code: &[u32]) -> Result<Vec<EntryPoint>, ReflectError> {
+    let mut entry_points = Vec::new();
+    for_each_instruction(code, |opcode, operands| {
+        if opcode == OP_ENTRY_POINT {
+            entry_points.push(entry_point(operands));
+        }
+    })?;
+    Ok(entry_points)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER: u32 = 5349;
+
+    fn instruction(opcode: u32, operands: &[u32]) -> Vec<u32> {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    }
+
+    fn module(instructions: &[Vec<u32>]) -> Vec<u32> {
+        let mut code = vec![MAGIC, 0x0001_0000, 0, 100, 0];
+        for words in instructions {
+            code.extend_from_slice(words);
+        }
+        code
+    }
+
+    #[test]
+    fn rejects_invalid_code() {
+        assert!(matches!(reflect(&[0; 5]), Err(ReflectError::InvalidMagic)));
+        let mut code = module(&[]);
+        code.push(3 << 16 | OP_TYPE_FLOAT);
+        assert!(matches!(reflect(&code), Err(ReflectError::Truncated(5))));
+        assert!(reflect(&module(&[instruction(OP_TYPE_STRUCT, &[])])).is_ok());
+    }
+
+    #[test]
+    fn bindings() {
+        // layout(set = 1, binding = 2) uniform sampler2D textures[4];
+        // layout(set = 0, binding = 0) uniform Ubo { vec4 color; };
+        let code = module(&[
+            instruction(OP_DECORATE, &[10, DECORATION_DESCRIPTOR_SET, 1]),
+            instruction(OP_DECORATE, &[10, DECORATION_BINDING, 2]),
+            instruction(OP_DECORATE, &[20, DECORATION_DESCRIPTOR_SET, 0]),
+            instruction(OP_DECORATE, &[20, DECORATION_BINDING, 0]),
+            instruction(OP_DECORATE, &[6, DECORATION_BLOCK]),
+            instruction(OP_TYPE_FLOAT, &[1, 32]),
+            instruction(OP_TYPE_IMAGE, &[2, 1, 1, 0, 0, 0, 1, 0]),
+            instruction(OP_TYPE_SAMPLED_IMAGE, &[3, 2]),
+            instruction(OP_TYPE_INT, &[4, 32, 0]),
+            instruction(OP_CONSTANT, &[4, 5, 4]),
+            instruction(OP_TYPE_ARRAY, &[7, 3, 5]),
+            instruction(OP_TYPE_POINTER, &[8, STORAGE_CLASS_UNIFORM_CONSTANT, 7]),
+            instruction(OP_VARIABLE, &[8, 10, STORAGE_CLASS_UNIFORM_CONSTANT]),
+            instruction(OP_TYPE_VECTOR, &[11, 1, 4]),
+            instruction(OP_TYPE_STRUCT, &[6, 11]),
+            instruction(OP_TYPE_POINTER, &[12, STORAGE_CLASS_UNIFORM, 6]),
+            instruction(OP_VARIABLE, &[12, 20, STORAGE_CLASS_UNIFORM]),
+        ]);
+        let reflection = reflect(&code).unwrap();
+        assert_eq!(
+            reflection.get_bindings(),
+            [
+                ReflectedBinding {
+                    set: 0,
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    count: 1,
+                },
+                ReflectedBinding {
+                    set: 1,
+                    binding: 2,
+                    descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                    count: 4,
+                },
+            ]
+        );
+        assert!(reflection.get_push_constant_range().is_none());
+    }
+
+    #[test]
+    fn push_constants() {
+        // layout(push_constant) uniform Constants { layout(offset = 16) mat4 transform; vec2 scale; };
+        let code = module(&[
+            instruction(OP_MEMBER_DECORATE, &[5, 0, DECORATION_OFFSET, 16]),
+            instruction(OP_MEMBER_DECORATE, &[5, 0, DECORATION_MATRIX_STRIDE, 16]),
+            instruction(OP_MEMBER_DECORATE, &[5, 1, DECORATION_OFFSET, 80]),
+            instruction(OP_TYPE_FLOAT, &[1, 32]),
+            instruction(OP_TYPE_VECTOR, &[2, 1, 4]),
+            instruction(OP_TYPE_MATRIX, &[3, 2, 4]),
+            instruction(OP_TYPE_VECTOR, &[4, 1, 2]),
+            instruction(OP_TYPE_STRUCT, &[5, 3, 4]),
+            instruction(OP_TYPE_POINTER, &[6, STORAGE_CLASS_PUSH_CONSTANT, 5]),
+            instruction(OP_VARIABLE, &[6, 7, STORAGE_CLASS_PUSH_CONSTANT]),
+        ]);
+        let range = reflect(&code).unwrap().get_push_constant_range().unwrap();
+        assert_eq!((range.offset, range.size), (16, 72));
+    }
+
+    #[test]
+    fn pointer_push_constants() {
+        // layout(push_constant) uniform Constants { layout(offset = 8) Data data; };
+        let code = module(&[
+            instruction(OP_MEMBER_DECORATE, &[3, 0, DECORATION_OFFSET, 8]),
+            instruction(OP_TYPE_FLOAT, &[1, 32]),
+            instruction(
+                OP_TYPE_POINTER,
+                &[2, STORAGE_CLASS_PHYSICAL_STORAGE_BUFFER, 1],
+            ),
+            instruction(OP_TYPE_STRUCT, &[3, 2]),
+            instruction(OP_TYPE_POINTER, &[4, STORAGE_CLASS_PUSH_CONSTANT, 3]),
+            instruction(OP_VARIABLE, &[4, 5, STORAGE_CLASS_PUSH_CONSTANT]),
+        ]);
+        let range = reflect(&code).unwrap().get_push_constant_range().unwrap();
+        assert_eq!((range.offset, range.size), (8, 8));
+    }
+
+    #[test]
+    fn oversized_push_constants() {
+        let code = |offset, length| {
+            module(&[
+                instruction(OP_MEMBER_DECORATE, &[5, 0, DECORATION_OFFSET, offset]),
+                instruction(OP_DECORATE, &[4, DECORATION_ARRAY_STRIDE, 16]),
+                instruction(OP_TYPE_INT, &[1, 32, 0]),
+                instruction(OP_CONSTANT, &[1, 2, length]),
+                instruction(OP_TYPE_ARRAY, &[4, 1, 2]),
+                instruction(OP_TYPE_STRUCT, &[5, 4]),
+                instruction(OP_TYPE_POINTER, &[6, STORAGE_CLASS_PUSH_CONSTANT, 5]),
+                instruction(OP_VARIABLE, &[6, 7, STORAGE_CLASS_PUSH_CONSTANT]),
+            ])
+        };
+        assert!(matches!(
+            reflect(&code(0, u32::MAX)),
+            Err(ReflectError::SizeOverflow(4))
+        ));
+        assert!(matches!(
+            reflect(&code(u32::MAX - 8, 1)),
+            Err(ReflectError::SizeOverflow(5))
+        ));
+    }
+
+    #[test]
+    fn entry_points() {
+        let name = |s: &str| {
+            let mut bytes = s.as_bytes().to_vec();
+            bytes.resize(s.len() / 4 * 4 + 4, 0);
+            bytes
+                .chunks_exact(4)
+                .map(|chunk| u32::from_le_bytes(chunk.try_into().unwrap()))
+                .collect::<Vec<_>>()
+        };
+        let code = module(&[
+            instruction(
+                OP_ENTRY_POINT,
+                &[[EXECUTION_MODEL_VERTEX, 1].as_slice(), &name("main"), &[7]].concat(),
+            ),
+            instruction(
+                OP_ENTRY_POINT,
+                &[[EXECUTION_MODEL_FRAGMENT, 2].as_slice(), &name("frag_main")].concat(),
+            ),
+            instruction(
+                OP_ENTRY_POINT,
+                &[[5313, 3].as_slice(), &name("raygen")].concat(),
+            ),
+        ]);
+        let reflection = reflect(&code).unwrap();
+        assert_eq!(
+            reflection.get_entry_point("main").unwrap().stage,
+            Some(ShaderStage::Vertex)
+        );
+        assert_eq!(
+            reflection.get_entry_point("frag_main").unwrap().stage,
+            Some(ShaderStage::Fragment)
+        );
+        assert_eq!(reflection.get_entry_point("raygen").unwrap().stage, None);
+        assert!(reflection.get_entry_point("mai").is_none());
+        assert_eq!(
+            super::entry_points(&code).unwrap(),
+            reflection.get_entry_points()
+        );
+    }
+
+    #[test]
+    fn merged_stages() {
+        let uniform = ReflectedBinding {
+            set: 1,
+            binding: 0,
+            descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+            count: 1,
+        };
+        let vertex = ShaderReflection {
+            bindings: vec![uniform],
+            push_constants: Some((0, 64)),
+            ..Default::default()
+        };
+        let fragment = ShaderReflection {
+            bindings: vec![uniform],
+            ..Default::default()
+        };
+        let (sets, ranges) = merge_reflections(&[
+            (vk::ShaderStageFlags::VERTEX, vertex.clone()),
+            (vk::ShaderStageFlags::FRAGMENT, fragment),
+        ])
+        .unwrap();
+        assert_eq!(sets.len(), 2);
+        assert!(sets[0].is_empty());
+        assert_eq!(
+            sets[1][0].stage_flags,
+            vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT
+        );
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].stage_flags, vk::ShaderStageFlags::VERTEX);
+
+        let storage = ShaderReflection {
+            bindings: vec![ReflectedBinding {
+                descriptor_type: vk::DescriptorType::STORAGE_BUFFER,
+                ..uniform
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            merge_reflections(&[
+                (vk::ShaderStageFlags::VERTEX, vertex),
+                (vk::ShaderStageFlags::FRAGMENT, storage),
+            ]),
+            Err(ReflectError::BindingMismatch { set: 1, binding: 0 })
+        ));
+
+        let runtime_array = ShaderReflection {
+            bindings: vec![ReflectedBinding {
+                count: 0,
+                ..uniform
+            }],
+            ..Default::default()
+        };
+        assert!(matches!(
+            merge_reflections(&[(vk::ShaderStageFlags::FRAGMENT, runtime_array)]),
+            Err(ReflectError::RuntimeArray { set: 1, binding: 0 })
+        ));
+    }
+}
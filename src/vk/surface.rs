@@ -1,6 +1,7 @@
 use std::{fmt, sync::Arc};
 
 use ash::vk::{self, PhysicalDevice, SurfaceKHR};
+use sdl3::video::Window;
 
 use crate::window::WindowManager;
 
@@ -19,6 +20,18 @@ pub struct Surface {
 impl Surface {
     pub fn init(instance: Arc<Instance>, window: &WindowManager) -> Result<Self, sdl3::Error> {
         let surface = window.create_surface(&instance)?;
+        Ok(Self::from_raw(instance, surface))
+    }
+
+    ///
+    /// Surface of a window created with WindowManager::create_window
+    ///
+    pub fn from_window(instance: Arc<Instance>, window: &Window) -> Result<Self, sdl3::Error> {
+        let surface = instance.create_surface(window)?;
+        Ok(Self::from_raw(instance, surface))
+    }
+
+    fn from_raw(instance: Arc<Instance>, surface: SurfaceKHR) -> Self {
         let surface_instance = SurfaceInstance::new(instance);
         let surface = Self {
             instance: surface_instance,
@@ -36,7 +49,7 @@ instance: {:?}
             surface.instance,
         );
 
-        Ok(surface)
+        surface
     }
 
     pub fn get_physical_device_surface_support(
@@ -59,6 +72,21 @@ instance: {:?}
         }
     }
 
+    ///
+    /// Whether protected swapchains can be created for the surface, None unless
+    /// GET_SURFACE_CAPABILITIES_2_EXTENSION and SURFACE_PROTECTED_CAPABILITIES_EXTENSION
+    /// are enabled on the instance
+    ///
+    pub fn get_physical_device_surface_protected(
+        &self,
+        device: PhysicalDevice,
+    ) -> Result<Option<bool>, vk::Result> {
+        unsafe {
+            self.instance
+                .get_physical_device_surface_protected(device, self.surface)
+        }
+    }
+
     ///
     /// # Safety
     /// SurfaceKHR should not be destroyed via raw handle
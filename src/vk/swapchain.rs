@@ -1,66 +1,356 @@
 use std::{
     cell::LazyCell,
     error::Error,
+    ffi::CStr,
     ops::DerefMut,
-    sync::{Arc, LazyLock, OnceLock, RwLock},
+    sync::{
+        Arc, LazyLock, Mutex, OnceLock, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
 };
 
 use ash::{
     khr::swapchain,
     vk::{
-        self, ColorSpaceKHR, CompositeAlphaFlagsKHR, Extent2D, Format, ImageAspectFlags,
-        ImageUsageFlags, ImageViewCreateInfo, PresentModeKHR, SharingMode, SurfaceCapabilitiesKHR,
-        SurfaceFormatKHR, SurfaceTransformFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
+        self, ColorSpaceKHR, CompositeAlphaFlagsKHR, Extent2D, Format, ImageUsageFlags,
+        PresentModeKHR, SharingMode, SurfaceCapabilitiesKHR, SurfaceFormatKHR,
+        SurfaceTransformFlagsKHR, SwapchainCreateInfoKHR, SwapchainKHR,
     },
 };
 
 use crate::vk::{
-    device::Device, framebuffer::Framebuffer, pipeline::render_pass::RenderPass,
-    surface::PhysicalDeviceSurfaceInfo, surface::Surface,
+    device::{Device, ProtectedMemoryNotEnabledError},
+    framebuffer::{Framebuffer, SwapchainFramebuffers},
+    image::ImageViewBuilder,
+    pipeline::render_pass::RenderPass,
+    surface::PhysicalDeviceSurfaceInfo,
+    surface::Surface,
 };
 
 use thiserror;
 
 use super::{
-    error::fatal_vk_error,
+    error::{DeviceLostError, check_device_lost},
     fence::{self, Fence},
-    selectors::DrawQueueFamilySelector,
     semaphore::Semaphore,
 };
 
+///
+/// Distinguishes swapchains even when a driver reuses the handle of a destroyed one
+///
+static SWAPCHAIN_GENERATION: AtomicU64 = AtomicU64::new(1);
+
 #[derive(Debug, thiserror::Error)]
 #[error("the swapchain SwapchainManager currently has is missing or invalid")]
 pub struct InvalidSwapchainError;
 
+///
+/// Swapchain with its render pass and framebuffers, see SwapchainManager::create_renderable
+///
+pub struct RenderableSwapchain {
+    pub swapchain: Arc<Swapchain>,
+    pub render_pass: Arc<RenderPass>,
+    pub framebuffers: SwapchainFramebuffers,
+}
+
+///
+/// The surface can't have a swapchain right now, e.g. right after creation on some
+/// window managers, before the window is mapped.
+/// Recoverable: create_swapchain can be retried after the next event pump
+///
+#[derive(Debug, thiserror::Error)]
+pub enum SurfaceUnavailableError {
+    #[error("the surface was lost")]
+    Lost,
+    #[error("the surface has a zero extent")]
+    ZeroExtent,
+}
+
+///
+/// The surface offers nothing the swapchain can use, which is not expected to change
+///
+#[derive(Debug, thiserror::Error)]
+pub enum SurfaceSupportError {
+    #[error(
+        "the surface supports neither the requested surface formats nor DEFAULT_SURFACE_FORMAT"
+    )]
+    NoSuitableSurfaceFormat,
+    #[error("the surface supports none of the present modes of {0:?}")]
+    NoSuitablePresentMode(PresentMode),
+}
+
+///
+/// The device was built with a QueueFamilySelector that doesn't report the family
+///
+#[derive(Debug, thiserror::Error)]
+pub enum MissingQueueFamilyError {
+    #[error("the device has no graphics queue family")]
+    Graphics,
+    #[error("the device has no present queue family")]
+    Present,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AcquireError {
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+    #[error("acquiring an image needs a semaphore, a fence or both")]
+    NoSyncObject,
+    #[error("another image acquire on the swapchain is in progress")]
+    InProgress,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("the present queue family of the device can't present to the surface")]
+pub struct PresentNotSupportedError;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FullScreenExclusiveError {
+    #[error("full-screen exclusive requires VK_EXT_full_screen_exclusive to be enabled")]
+    NotEnabled,
+    #[error("the swapchain was not created with APPLICATION_CONTROLLED full-screen exclusive")]
+    NotApplicationControlled,
+    #[error("failed to change full-screen exclusive mode: {0}")]
+    Vulkan(#[from] vk::Result),
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("pre-transform {0:?} is not a single transform")]
+pub struct InvalidTransformError(pub SurfaceTransformFlagsKHR);
+
+#[derive(Debug, thiserror::Error)]
+#[error("render scale {0} is not a finite number greater than 0")]
+pub struct InvalidRenderScaleError(pub f32);
+
+#[derive(Debug, thiserror::Error)]
+#[error("HDR metadata requires VK_EXT_hdr_metadata to be enabled")]
+pub struct HdrMetadataNotEnabledError;
+
+pub const HDR_METADATA_EXTENSION: &CStr = c"VK_EXT_hdr_metadata";
+
+///
+/// Both are needed for Queue::present_with_id and Swapchain::wait_for_present,
+/// their features are enabled together with the extensions
+///
+pub const PRESENT_ID_EXTENSION: &CStr = c"VK_KHR_present_id";
+pub const PRESENT_WAIT_EXTENSION: &CStr = c"VK_KHR_present_wait";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PresentWaitError {
+    #[error("present ids require VK_KHR_present_id and VK_KHR_present_wait to be enabled")]
+    NotEnabled,
+    #[error("the swapchain is out of date")]
+    OutOfDate,
+    #[error(transparent)]
+    DeviceLost(#[from] DeviceLostError),
+}
+
+///
+/// Instance extension that makes surfaces report extended color spaces such as
+/// HDR10_ST2084 or EXTENDED_SRGB_LINEAR
+///
+pub const SWAPCHAIN_COLORSPACE_EXTENSION: &CStr = c"VK_EXT_swapchain_colorspace";
+
+///
+/// Instance extensions needed to check that a surface supports protected swapchains
+///
+pub const SURFACE_PROTECTED_CAPABILITIES_EXTENSION: &CStr =
+    c"VK_KHR_surface_protected_capabilities";
+pub const GET_SURFACE_CAPABILITIES_2_EXTENSION: &CStr = c"VK_KHR_get_surface_capabilities2";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ProtectedSurfaceError {
+    #[error(
+        "protected swapchains require VK_KHR_surface_protected_capabilities and VK_KHR_get_surface_capabilities2 to be enabled on the instance"
+    )]
+    NotEnabled,
+    #[error("the surface doesn't support protected swapchains")]
+    Unsupported,
+}
+
+///
+/// Mastering display and content light levels of HDR content, luminance in nits
+///
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HdrMetadata {
+    pub display_primary_red: vk::XYColorEXT,
+    pub display_primary_green: vk::XYColorEXT,
+    pub display_primary_blue: vk::XYColorEXT,
+    pub white_point: vk::XYColorEXT,
+    pub max_luminance: f32,
+    pub min_luminance: f32,
+    pub max_content_light_level: f32,
+    pub max_frame_average_light_level: f32,
+}
+
+impl HdrMetadata {
+    fn to_vk(self) -> vk::HdrMetadataEXT<'static> {
+        vk::HdrMetadataEXT::default()
+            .display_primary_red(self.display_primary_red)
+            .display_primary_green(self.display_primary_green)
+            .display_primary_blue(self.display_primary_blue)
+            .white_point(self.white_point)
+            .max_luminance(self.max_luminance)
+            .min_luminance(self.min_luminance)
+            .max_content_light_level(self.max_content_light_level)
+            .max_frame_average_light_level(self.max_frame_average_light_level)
+    }
+}
+
+pub const FULL_SCREEN_EXCLUSIVE_EXTENSION: &CStr = c"VK_EXT_full_screen_exclusive";
+
+fn check_surface_lost<T>(result: Result<T, vk::Result>) -> Result<T, Box<dyn Error>> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(vk::Result::ERROR_SURFACE_LOST_KHR) => Err(SurfaceUnavailableError::Lost.into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+///
+/// Requested presentation behaviour. Every mode falls back to FIFO (Vsync),
+/// which is the only present mode guaranteed to be supported
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PresentMode {
+    #[default]
+    Vsync,
+    /// Vsync that tears instead of stuttering when a frame misses the refresh (FIFO_RELAXED)
+    AdaptiveVsync,
+    Mailbox,
+    Immediate,
+}
+
+impl PresentMode {
+    fn fallback_chain(self) -> &'static [PresentModeKHR] {
+        match self {
+            Self::Vsync => &[PresentModeKHR::FIFO],
+            Self::AdaptiveVsync => &[PresentModeKHR::FIFO_RELAXED, PresentModeKHR::FIFO],
+            Self::Mailbox => &[PresentModeKHR::MAILBOX, PresentModeKHR::FIFO],
+            Self::Immediate => &[PresentModeKHR::IMMEDIATE, PresentModeKHR::FIFO],
+        }
+    }
+
+    fn preferred(self) -> PresentModeKHR {
+        self.fallback_chain()[0]
+    }
+}
+
+///
+/// Who does the sRGB encoding of colors written to a color attachment
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpaceMode {
+    /// sRGB format: shaders output linear colors, the hardware encodes them on write
+    HardwareSrgb,
+    /// UNORM format: shaders have to output sRGB-encoded colors themselves
+    ShaderEncoded,
+}
+
+impl ColorSpaceMode {
+    pub fn from_format(format: Format) -> Self {
+        if is_srgb_format(format) {
+            Self::HardwareSrgb
+        } else {
+            Self::ShaderEncoded
+        }
+    }
+}
+
+pub fn is_srgb_format(format: Format) -> bool {
+    matches!(
+        format,
+        Format::R8_SRGB
+            | Format::R8G8_SRGB
+            | Format::R8G8B8_SRGB
+            | Format::B8G8R8_SRGB
+            | Format::R8G8B8A8_SRGB
+            | Format::B8G8R8A8_SRGB
+            | Format::A8B8G8R8_SRGB_PACK32
+    )
+}
+
 pub fn check_surface_info(surface_info: PhysicalDeviceSurfaceInfo) -> bool {
-    if choose_format(surface_info.formats).is_none()
-        || choose_present_mode(surface_info.present_modes).is_none()
+    if choose_format(surface_info.formats, &[]).is_none()
+        || choose_present_mode(surface_info.present_modes, PresentMode::Vsync).is_none()
     {
         return false;
     }
     true
 }
 
-fn choose_format(formats: Vec<SurfaceFormatKHR>) -> Option<SurfaceFormatKHR> {
-    formats.into_iter().find(|&format| {
-        format.format == Format::B8G8R8A8_SRGB
-            && format.color_space == ColorSpaceKHR::SRGB_NONLINEAR
-    })
+pub const DEFAULT_SURFACE_FORMAT: SurfaceFormatKHR = SurfaceFormatKHR {
+    format: Format::B8G8R8A8_SRGB,
+    color_space: ColorSpaceKHR::SRGB_NONLINEAR,
+};
+
+///
+/// The first of preferred the surface supports, then DEFAULT_SURFACE_FORMAT.
+/// None if the surface supports neither
+///
+fn choose_format(
+    formats: Vec<SurfaceFormatKHR>,
+    preferred: &[SurfaceFormatKHR],
+) -> Option<SurfaceFormatKHR> {
+    preferred
+        .iter()
+        .chain([&DEFAULT_SURFACE_FORMAT])
+        .find(|preferred| formats.contains(preferred))
+        .copied()
 }
 
-fn choose_present_mode(modes: Vec<PresentModeKHR>) -> Option<PresentModeKHR> {
-    modes.into_iter().find(|&mode| mode == PresentModeKHR::FIFO)
+fn choose_present_mode(
+    modes: Vec<PresentModeKHR>,
+    requested: PresentMode,
+) -> Option<PresentModeKHR> {
+    requested
+        .fallback_chain()
+        .iter()
+        .copied()
+        .find(|mode| modes.contains(mode))
+}
+
+///
+/// The surface extent, or framebuffer_size if the surface lets the swapchain decide,
+/// scaled by render_scale and clamped to the extents the surface supports
+///
+fn choose_swap_extent(
+    capabilities: SurfaceCapabilitiesKHR,
+    framebuffer_size: Extent2D,
+    render_scale: f32,
+) -> Extent2D {
+    let extent = if capabilities.current_extent.width != u32::MAX {
+        capabilities.current_extent
+    } else {
+        framebuffer_size
+    };
+    let scale = |size: u32| (size as f32 * render_scale).round() as u32;
+    Extent2D {
+        width: scale(extent.width).clamp(
+            capabilities.min_image_extent.width,
+            capabilities.max_image_extent.width,
+        ),
+        height: scale(extent.height).clamp(
+            capabilities.min_image_extent.height,
+            capabilities.max_image_extent.height,
+        ),
+    }
 }
 
-fn choose_swap_extent(capabilities: SurfaceCapabilitiesKHR) -> Extent2D {
-    if capabilities.current_extent.height != u32::MAX {
-        return capabilities.current_extent;
+fn check_render_scale(render_scale: f32) -> Result<(), InvalidRenderScaleError> {
+    if render_scale.is_finite() && render_scale > 0.0 {
+        Ok(())
+    } else {
+        Err(InvalidRenderScaleError(render_scale))
     }
-    todo!("swap extent was not set");
 }
 
-fn choose_image_count(capabilities: SurfaceCapabilitiesKHR) -> u32 {
-    let image_count = capabilities.min_image_count + 1;
+///
+/// One image more than the minimum, clamped to the maximum unless it is 0 (unbounded)
+///
+pub(crate) fn choose_image_count(capabilities: SurfaceCapabilitiesKHR) -> u32 {
+    let image_count = capabilities.min_image_count.saturating_add(1);
     if capabilities.max_image_count != 0 && capabilities.max_image_count < image_count {
         capabilities.max_image_count
     } else {
@@ -68,8 +358,72 @@ fn choose_image_count(capabilities: SurfaceCapabilitiesKHR) -> u32 {
     }
 }
 
-fn choose_transform(capabilities: SurfaceCapabilitiesKHR) -> SurfaceTransformFlagsKHR {
-    capabilities.current_transform
+fn make_viewport(extent: Extent2D, flip_y: bool) -> (vk::Viewport, vk::Rect2D) {
+    let Extent2D { width, height } = extent;
+    let viewport = vk::Viewport::default()
+        .width(width as f32)
+        .max_depth(1.0f32);
+    let viewport = if flip_y {
+        viewport.y(height as f32).height(-(height as f32))
+    } else {
+        viewport.height(height as f32)
+    };
+    let scissor = vk::Rect2D::default().extent(extent);
+    (viewport, scissor)
+}
+
+///
+/// requested if the surface supports it, otherwise the current transform of the surface
+///
+fn choose_transform(
+    capabilities: SurfaceCapabilitiesKHR,
+    requested: Option<SurfaceTransformFlagsKHR>,
+) -> SurfaceTransformFlagsKHR {
+    requested
+        .filter(|requested| capabilities.supported_transforms.contains(*requested))
+        .unwrap_or(capabilities.current_transform)
+}
+
+///
+/// Result of presenting, ordered from best to worst so statuses can be combined with max.
+/// OutOfDate means the swapchain has to be recreated before it can be used again,
+/// a Suboptimal one still works but should be recreated when convenient
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PresentStatus {
+    Optimal,
+    Suboptimal,
+    OutOfDate,
+}
+
+///
+/// Result of acquiring an image, see PresentStatus.
+/// No image is acquired when the swapchain is OutOfDate, the frame has to be skipped
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquireStatus {
+    Optimal(u32),
+    Suboptimal(u32),
+    OutOfDate,
+}
+
+impl AcquireStatus {
+    pub fn get_present_status(self) -> PresentStatus {
+        match self {
+            Self::Optimal(_) => PresentStatus::Optimal,
+            Self::Suboptimal(_) => PresentStatus::Suboptimal,
+            Self::OutOfDate => PresentStatus::OutOfDate,
+        }
+    }
+}
+
+///
+/// None waits without a limit
+///
+fn timeout_nanos(timeout: Option<Duration>) -> u64 {
+    timeout.map_or(u64::MAX, |timeout| {
+        timeout.as_nanos().try_into().unwrap_or(u64::MAX)
+    })
 }
 
 pub struct Swapchain {
@@ -77,29 +431,71 @@ pub struct Swapchain {
     swapchain_device: swapchain::Device,
     _surface: Arc<Surface>,
     swapchain_khr: SwapchainKHR,
+    generation: u64,
     extent: Extent2D,
     format: SurfaceFormatKHR,
-    _present_mode: PresentModeKHR,
-    _images: Vec<vk::Image>,
+    present_mode: PresentModeKHR,
+    transform: SurfaceTransformFlagsKHR,
+    requested_present_mode: PresentMode,
+    images: Vec<vk::Image>,
     views: Vec<vk::ImageView>,
+    full_screen_exclusive: Option<vk::FullScreenExclusiveEXT>,
     acquire_image_fence: RwLock<Fence>,
 }
 
 impl Swapchain {
-    pub fn make_viewport(&self) -> Result<(vk::Viewport, vk::Rect2D), InvalidSwapchainError> {
-        let Extent2D { width, height } = self.extent;
-        let viewport = vk::Viewport::default()
-            .width(width as f32)
-            .height(height as f32)
-            .max_depth(1.0f32);
-        let scissor = vk::Rect2D::default().extent(self.extent);
-        Ok((viewport, scissor))
+    ///
+    /// With flip_y the viewport has negative height and starts at the bottom,
+    /// so clip-space Y points up like in OpenGL (core since Vulkan 1.1)
+    ///
+    pub fn make_viewport(
+        &self,
+        flip_y: bool,
+    ) -> Result<(vk::Viewport, vk::Rect2D), InvalidSwapchainError> {
+        Ok(make_viewport(self.extent, flip_y))
     }
     pub fn get_format(&self) -> SurfaceFormatKHR {
         self.format
     }
-    pub fn create_framebuffers(&self, render_pass: Arc<RenderPass>) -> Vec<Arc<Framebuffer>> {
-        self.views
+    ///
+    /// True if the hardware sRGB-encodes writes to the swapchain images,
+    /// see ColorSpaceMode
+    ///
+    pub fn is_srgb(&self) -> bool {
+        is_srgb_format(self.format.format)
+    }
+    pub fn get_color_space_mode(&self) -> ColorSpaceMode {
+        ColorSpaceMode::from_format(self.format.format)
+    }
+    pub fn get_image_count(&self) -> usize {
+        self.images.len()
+    }
+    pub fn get_present_mode(&self) -> PresentModeKHR {
+        self.present_mode
+    }
+    ///
+    /// Transform the images are presented with. If it is the current transform of the surface,
+    /// e.g. ROTATE_90 on a rotated display, the app has to rotate its projection by it.
+    /// Otherwise the compositor rotates the images, see SwapchainManager::pre_transform
+    ///
+    pub fn get_transform(&self) -> SurfaceTransformFlagsKHR {
+        self.transform
+    }
+    ///
+    /// Returns false if the requested present mode was unavailable and a fallback is used
+    ///
+    pub fn is_requested_present_mode(&self) -> bool {
+        self.present_mode == self.requested_present_mode.preferred()
+    }
+    pub(in crate::vk) fn get_generation(&self) -> u64 {
+        self.generation
+    }
+    ///
+    /// One framebuffer per swapchain image, see SwapchainFramebuffers::framebuffer_for_image
+    ///
+    pub fn create_framebuffers(&self, render_pass: Arc<RenderPass>) -> SwapchainFramebuffers {
+        let framebuffers = self
+            .views
             .iter()
             .map(|view| {
                 let attachments = [view.clone()];
@@ -118,14 +514,18 @@ impl Swapchain {
                 )
             })
             .map(|fb| Arc::new(fb))
-            .collect()
+            .collect();
+        SwapchainFramebuffers::new(self.generation, framebuffers)
     }
 
     ///
-    /// Acquires next swapchain image index.
+    /// Acquires next swapchain image index, semaphore is only signaled if an image is acquired.
     /// Will block thread if previous acquire operation is in progress
     ///
-    pub async fn acquire_next_image(&self, semaphore: Option<&Semaphore>) -> (u32, bool) {
+    pub async fn acquire_next_image(
+        &self,
+        semaphore: Option<&Semaphore>,
+    ) -> Result<AcquireStatus, DeviceLostError> {
         let semaphore = match semaphore {
             Some(s) => unsafe { s.raw_handle() },
             None => vk::Semaphore::null(),
@@ -137,12 +537,150 @@ impl Swapchain {
         };
         fence.reset();
         let result = unsafe {
-            self.swapchain_device
-                .acquire_next_image(self.swapchain_khr, u64::MAX, semaphore, fence.raw_handle())
-                .unwrap_or_else(|error| fatal_vk_error("failed to acquire_next_image", error))
+            self.swapchain_device.acquire_next_image(
+                self.swapchain_khr,
+                u64::MAX,
+                semaphore,
+                fence.raw_handle(),
+            )
         };
-        fence.deref_mut().await;
-        result
+        // The fence is only signaled if an image is acquired
+        if result == Err(vk::Result::ERROR_OUT_OF_DATE_KHR) {
+            return Ok(AcquireStatus::OutOfDate);
+        }
+        let (index, suboptimal) = check_device_lost("failed to acquire_next_image", result)?;
+        fence.deref_mut().await?;
+        Ok(if suboptimal {
+            AcquireStatus::Suboptimal(index)
+        } else {
+            AcquireStatus::Optimal(index)
+        })
+    }
+
+    ///
+    /// Acquires next swapchain image index without waiting for the image to be ready,
+    /// semaphore and fence are signaled once it is.
+    /// Waits up to timeout for an image to become available, None waits without a limit
+    /// and Some(Duration::ZERO) polls. Returns None if no image was available in time.
+    /// Fails with InProgress while acquire_next_image is waiting, as acquires on a
+    /// swapchain must not overlap
+    ///
+    pub fn try_acquire_next_image(
+        &self,
+        timeout: Option<Duration>,
+        semaphore: Option<&Semaphore>,
+        fence: Option<&mut Fence>,
+    ) -> Result<Option<AcquireStatus>, AcquireError> {
+        if semaphore.is_none() && fence.is_none() {
+            return Err(AcquireError::NoSyncObject);
+        }
+        let Ok(_acquire_lock) = self.acquire_image_fence.try_write() else {
+            return Err(AcquireError::InProgress);
+        };
+        let semaphore = match semaphore {
+            Some(s) => unsafe { s.raw_handle() },
+            None => vk::Semaphore::null(),
+        };
+        let fence = match fence {
+            Some(fence) => unsafe {
+                fence.reset();
+                fence.raw_handle()
+            },
+            None => vk::Fence::null(),
+        };
+        let result = unsafe {
+            self.swapchain_device.acquire_next_image(
+                self.swapchain_khr,
+                timeout_nanos(timeout),
+                semaphore,
+                fence,
+            )
+        };
+        match result {
+            Err(vk::Result::NOT_READY | vk::Result::TIMEOUT) => Ok(None),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Ok(Some(AcquireStatus::OutOfDate)),
+            result => {
+                let (index, suboptimal) =
+                    check_device_lost("failed to acquire_next_image", result)?;
+                Ok(Some(if suboptimal {
+                    AcquireStatus::Suboptimal(index)
+                } else {
+                    AcquireStatus::Optimal(index)
+                }))
+            }
+        }
+    }
+
+    ///
+    /// Takes exclusive ownership of the display, only for swapchains created with
+    /// APPLICATION_CONTROLLED full-screen exclusive.
+    /// Fails with ERROR_INITIALIZATION_FAILED if the window isn't fullscreen
+    ///
+    pub fn acquire_full_screen_exclusive(&self) -> Result<(), FullScreenExclusiveError> {
+        let device = self.application_controlled_device()?;
+        unsafe { device.acquire_full_screen_exclusive_mode(self.swapchain_khr)? };
+        Ok(())
+    }
+
+    pub fn release_full_screen_exclusive(&self) -> Result<(), FullScreenExclusiveError> {
+        let device = self.application_controlled_device()?;
+        unsafe { device.release_full_screen_exclusive_mode(self.swapchain_khr)? };
+        Ok(())
+    }
+
+    ///
+    /// Describes the HDR content presented to the display, see SwapchainManager::set_hdr_metadata
+    ///
+    pub fn set_hdr_metadata(
+        &self,
+        metadata: HdrMetadata,
+    ) -> Result<(), HdrMetadataNotEnabledError> {
+        if !self.device.is_extension_enabled(HDR_METADATA_EXTENSION) {
+            return Err(HdrMetadataNotEnabledError);
+        }
+        let hdr_device = unsafe { self.device.make_hdr_metadata_device() };
+        unsafe { hdr_device.set_hdr_metadata(&[self.swapchain_khr], &[metadata.to_vk()]) };
+        Ok(())
+    }
+
+    ///
+    /// Blocks until the present with present_id or a later one is displayed, or timeout
+    /// passes (None waits without a limit). Returns false on timeout.
+    /// Present ids are passed to Queue::present_with_id
+    ///
+    pub fn wait_for_present(
+        &self,
+        present_id: u64,
+        timeout: Option<Duration>,
+    ) -> Result<bool, PresentWaitError> {
+        if !self.device.is_present_wait_enabled() {
+            return Err(PresentWaitError::NotEnabled);
+        }
+        let present_wait_device = unsafe { self.device.make_present_wait_device() };
+        let result = unsafe {
+            present_wait_device.wait_for_present(
+                self.swapchain_khr,
+                present_id,
+                timeout_nanos(timeout),
+            )
+        };
+        match result {
+            Err(vk::Result::TIMEOUT) => Ok(false),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => Err(PresentWaitError::OutOfDate),
+            result => {
+                check_device_lost("failed to wait_for_present", result)?;
+                Ok(true)
+            }
+        }
+    }
+
+    fn application_controlled_device(
+        &self,
+    ) -> Result<ash::ext::full_screen_exclusive::Device, FullScreenExclusiveError> {
+        if self.full_screen_exclusive != Some(vk::FullScreenExclusiveEXT::APPLICATION_CONTROLLED) {
+            return Err(FullScreenExclusiveError::NotApplicationControlled);
+        }
+        Ok(unsafe { self.device.make_full_screen_exclusive_device() })
     }
 
     pub(in crate::vk) unsafe fn device_handle(&self) -> swapchain::Device {
@@ -167,29 +705,252 @@ impl Drop for Swapchain {
 pub struct SwapchainManager {
     device: Arc<Device>,
     surface: Arc<Surface>,
+    present_mode: PresentMode,
+    surface_formats: Vec<SurfaceFormatKHR>,
+    hdr_metadata: Mutex<Option<HdrMetadata>>,
+    render_scale: f32,
+    pre_transform: Option<SurfaceTransformFlagsKHR>,
+    protected: bool,
+    full_screen_exclusive: Option<(vk::FullScreenExclusiveEXT, Option<vk::HMONITOR>)>,
+    framebuffer_size: Mutex<Extent2D>,
 }
 
 impl SwapchainManager {
     pub fn new(device: Arc<Device>, surface: Arc<Surface>) -> Self {
-        Self { device, surface }
+        Self {
+            device,
+            surface,
+            present_mode: PresentMode::default(),
+            surface_formats: Vec::new(),
+            hdr_metadata: Mutex::new(None),
+            render_scale: 1.0,
+            pre_transform: None,
+            protected: false,
+            full_screen_exclusive: None,
+            framebuffer_size: Mutex::new(Extent2D::default()),
+        }
+    }
+    pub fn present_mode(mut self, present_mode: PresentMode) -> Self {
+        self.present_mode = present_mode;
+        self
+    }
+    ///
+    /// Formats and color spaces to try in order, before DEFAULT_SURFACE_FORMAT.
+    /// Falling back to a later one logs a warning, creation fails with
+    /// SurfaceSupportError if the surface supports none of them.
+    /// HDR color spaces like HDR10_ST2084 are only reported by surfaces with
+    /// SWAPCHAIN_COLORSPACE_EXTENSION enabled on the instance
+    ///
+    pub fn surface_formats(mut self, surface_formats: Vec<SurfaceFormatKHR>) -> Self {
+        self.surface_formats = surface_formats;
+        self
     }
-    pub fn create_swapchain(
+    ///
+    /// HDR metadata set on every swapchain created afterwards,
+    /// the current one is updated with Swapchain::set_hdr_metadata.
+    /// Needs HDR_METADATA_EXTENSION enabled with DeviceBuilder::extension
+    ///
+    pub fn set_hdr_metadata(
         &self,
-        queue_family_selector: DrawQueueFamilySelector,
+        metadata: Option<HdrMetadata>,
+    ) -> Result<(), HdrMetadataNotEnabledError> {
+        if metadata.is_some() && !self.device.is_extension_enabled(HDR_METADATA_EXTENSION) {
+            return Err(HdrMetadataNotEnabledError);
+        }
+        *self.hdr_metadata.lock().unwrap() = metadata;
+        Ok(())
+    }
+    ///
+    /// Renders at a fraction of the window size and lets presentation scale it up.
+    /// The result is clamped to the surface's min and max image extent. Surfaces with
+    /// a fixed current extent (current_extent is not u32::MAX, e.g. on Windows and X11)
+    /// usually only allow that extent, so the scale has no effect there.
+    /// Swapchain creation fails with InvalidRenderScaleError unless the scale is finite
+    /// and greater than 0
+    ///
+    pub fn render_scale(mut self, render_scale: f32) -> Self {
+        self.render_scale = render_scale;
+        self
+    }
+    ///
+    /// Transform the images are presented with, the current transform of the surface
+    /// by default. That is the fast path where the app bakes the rotation into its
+    /// projection, requesting IDENTITY on a rotated mobile display makes the compositor
+    /// rotate the images instead. Has to be a single transform, falls back to the current
+    /// transform of the surface if unsupported, see Swapchain::get_transform
+    ///
+    pub fn pre_transform(mut self, transform: SurfaceTransformFlagsKHR) -> Self {
+        self.pre_transform = Some(transform);
+        self
+    }
+    ///
+    /// Creates swapchains with protected images, for protected content presented from
+    /// a protected queue. Requires DeviceBuilder::protected_memory, and
+    /// SURFACE_PROTECTED_CAPABILITIES_EXTENSION and GET_SURFACE_CAPABILITIES_2_EXTENSION
+    /// on the instance to check that the surface supports it
+    ///
+    pub fn protected(mut self, protected: bool) -> Self {
+        self.protected = protected;
+        self
+    }
+    ///
+    /// Chains SurfaceFullScreenExclusiveInfoEXT into the swapchain create info.
+    /// With APPLICATION_CONTROLLED, exclusive mode is entered with
+    /// Swapchain::acquire_full_screen_exclusive, and monitor (the HMONITOR the window is on)
+    /// is required for Win32 surfaces.
+    /// Needs FULL_SCREEN_EXCLUSIVE_EXTENSION enabled with DeviceBuilder::extension
+    ///
+    pub fn full_screen_exclusive(
+        mut self,
+        mode: vk::FullScreenExclusiveEXT,
+        monitor: Option<vk::HMONITOR>,
+    ) -> Self {
+        self.full_screen_exclusive = Some((mode, monitor));
+        self
+    }
+    ///
+    /// Window size in pixels, used when the surface lets the swapchain choose its extent.
+    /// Should be updated on resize, before recreating the swapchain
+    ///
+    pub fn set_framebuffer_size(&self, width: u32, height: u32) {
+        *self.framebuffer_size.lock().unwrap() = Extent2D { width, height };
+    }
+    ///
+    /// Returns SurfaceUnavailableError if the surface is temporarily unusable,
+    /// in which case the call should be retried later, SurfaceSupportError
+    /// if it can't be used at all, and MissingQueueFamilyError if the device has
+    /// no graphics or present family to share the images between
+    ///
+    pub fn create_swapchain(&self) -> Result<Swapchain, Box<dyn Error>> {
+        self.create(SwapchainKHR::null())
+    }
+    ///
+    /// Creates a swapchain, the default single-subpass RenderPass for it and one framebuffer
+    /// per swapchain image. References only point down the chain:
+    /// framebuffers keep the render pass alive, the render pass keeps the swapchain alive,
+    /// so dropping the framebuffers and render pass releases the swapchain without cycles.
+    /// Returns SurfaceUnavailableError like create_swapchain
+    ///
+    pub fn create_renderable(&self) -> Result<RenderableSwapchain, Box<dyn Error>> {
+        let swapchain = Arc::new(self.create_swapchain()?);
+        let render_pass = Arc::new(RenderPass::new(
+            Arc::clone(&self.device),
+            Arc::clone(&swapchain),
+        )?);
+        let framebuffers = swapchain.create_framebuffers(Arc::clone(&render_pass));
+        Ok(RenderableSwapchain {
+            swapchain,
+            render_pass,
+            framebuffers,
+        })
+    }
+    ///
+    /// Creates a swapchain replacing old_swapchain, e.g. after a resize or OutOfDate,
+    /// letting the driver reuse its resources.
+    /// Waits for the device to become idle first, so no submitted work references
+    /// old_swapchain, its images or framebuffers anymore and they can be dropped right away.
+    /// old_swapchain is only borrowed: replace it with the new swapchain on success, it is
+    /// destroyed once the last reference to it, e.g. in a FrameRenderer or GraphicsPipeline,
+    /// is dropped.
+    /// Errors returned before the new swapchain is created, like SurfaceUnavailableError
+    /// while the window is minimized, leave old_swapchain usable and the call can be
+    /// retried later. If vkCreateSwapchainKHR itself fails, old_swapchain is retired and
+    /// can't acquire images anymore
+    ///
+    pub fn recreate_swapchain(
+        &self,
+        old_swapchain: &Arc<Swapchain>,
     ) -> Result<Swapchain, Box<dyn Error>> {
-        let surface_info = self.device.get_surface_info()?;
+        self.device.wait_idle();
+        self.create(old_swapchain.swapchain_khr)
+    }
+    fn create(&self, old_swapchain: SwapchainKHR) -> Result<Swapchain, Box<dyn Error>> {
+        check_render_scale(self.render_scale)?;
+        if self.protected && !self.device.is_protected_memory_enabled() {
+            return Err(ProtectedMemoryNotEnabledError.into());
+        }
+        if self.full_screen_exclusive.is_some()
+            && !self
+                .device
+                .is_extension_enabled(FULL_SCREEN_EXCLUSIVE_EXTENSION)
+        {
+            return Err(FullScreenExclusiveError::NotEnabled.into());
+        }
+        let surface_info = check_surface_lost(self.device.get_surface_info_for(&self.surface))?;
+        if self.protected {
+            match check_surface_lost(self.device.get_surface_protected_for(&self.surface))? {
+                Some(true) => {}
+                Some(false) => return Err(ProtectedSurfaceError::Unsupported.into()),
+                None => return Err(ProtectedSurfaceError::NotEnabled.into()),
+            }
+        }
 
-        let graphic = queue_family_selector.graphics.unwrap();
-        let present = queue_family_selector.present.unwrap();
-        let indices = [graphic as u32, present as u32];
+        let graphic = self
+            .device
+            .get_graphics_family()
+            .ok_or(MissingQueueFamilyError::Graphics)?;
+        let present = self
+            .device
+            .get_present_family()
+            .ok_or(MissingQueueFamilyError::Present)?;
+        let indices = [graphic, present];
 
         let capabilities = surface_info.capabilities;
 
-        let format = choose_format(surface_info.formats).unwrap();
-        let extent = choose_swap_extent(capabilities);
-        let present_mode = choose_present_mode(surface_info.present_modes).unwrap();
+        let format = choose_format(surface_info.formats, &self.surface_formats)
+            .ok_or(SurfaceSupportError::NoSuitableSurfaceFormat)?;
+        if let Some(preferred) = self.surface_formats.first()
+            && format != *preferred
+        {
+            log::warn!(
+                "Surface format {:?} is unavailable, falling back to {:?}",
+                preferred,
+                format
+            );
+        }
+        let extent = choose_swap_extent(
+            capabilities,
+            *self.framebuffer_size.lock().unwrap(),
+            self.render_scale,
+        );
+        if extent.width == 0 || extent.height == 0 {
+            return Err(SurfaceUnavailableError::ZeroExtent.into());
+        }
+        let present_mode = choose_present_mode(surface_info.present_modes, self.present_mode)
+            .ok_or(SurfaceSupportError::NoSuitablePresentMode(
+                self.present_mode,
+            ))?;
+        if present_mode != self.present_mode.preferred() {
+            log::warn!(
+                "Present mode {:?} is unavailable, falling back to {:?}",
+                self.present_mode,
+                present_mode
+            );
+        }
+
+        if let Some(requested) = self.pre_transform
+            && requested.as_raw().count_ones() != 1
+        {
+            return Err(InvalidTransformError(requested).into());
+        }
+        let transform = choose_transform(capabilities, self.pre_transform);
+        if let Some(requested) = self.pre_transform
+            && transform != requested
+        {
+            log::warn!(
+                "Surface transform {:?} is unsupported, falling back to {:?}",
+                requested,
+                transform
+            );
+        }
 
+        let flags = if self.protected {
+            vk::SwapchainCreateFlagsKHR::PROTECTED
+        } else {
+            vk::SwapchainCreateFlagsKHR::empty()
+        };
         let mut swapchain_info = SwapchainCreateInfoKHR::default()
+            .flags(flags)
             .surface(unsafe { self.surface.raw_handle() })
             .min_image_count(choose_image_count(capabilities))
             .image_format(format.format)
@@ -197,10 +958,24 @@ impl SwapchainManager {
             .image_extent(extent)
             .image_array_layers(1)
             .image_usage(ImageUsageFlags::COLOR_ATTACHMENT)
-            .pre_transform(choose_transform(capabilities))
+            .pre_transform(transform)
             .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
             .present_mode(present_mode)
-            .clipped(true);
+            .clipped(true)
+            .old_swapchain(old_swapchain);
+
+        let mut full_screen_exclusive_info = vk::SurfaceFullScreenExclusiveInfoEXT::default();
+        let mut full_screen_exclusive_win32_info =
+            vk::SurfaceFullScreenExclusiveWin32InfoEXT::default();
+        if let Some((mode, monitor)) = self.full_screen_exclusive {
+            full_screen_exclusive_info = full_screen_exclusive_info.full_screen_exclusive(mode);
+            swapchain_info = swapchain_info.push_next(&mut full_screen_exclusive_info);
+            if let Some(monitor) = monitor {
+                full_screen_exclusive_win32_info =
+                    full_screen_exclusive_win32_info.hmonitor(monitor);
+                swapchain_info = swapchain_info.push_next(&mut full_screen_exclusive_win32_info);
+            }
+        }
 
         if graphic == present {
             swapchain_info = swapchain_info.image_sharing_mode(SharingMode::EXCLUSIVE)
@@ -209,40 +984,249 @@ impl SwapchainManager {
                 .image_sharing_mode(SharingMode::CONCURRENT)
                 .queue_family_indices(&indices);
         }
-        let swapchain_khr = self.device.create_swapchain(&swapchain_info)?;
+        let swapchain_khr = check_surface_lost(self.device.create_swapchain(&swapchain_info))?;
         let images = unsafe { self.device.get_swapchain_images(swapchain_khr) }?;
 
-        let view_info = ImageViewCreateInfo::default()
-            .view_type(vk::ImageViewType::TYPE_2D)
-            .format(format.format)
-            .subresource_range(
-                vk::ImageSubresourceRange::default()
-                    .level_count(1)
-                    .layer_count(1)
-                    .aspect_mask(ImageAspectFlags::COLOR),
-            );
-
+        let view_builder = ImageViewBuilder::new(format.format);
         let views = images
             .iter()
             .map(|image| {
-                let info = view_info.image(*image);
+                let info = view_builder.create_info(*image);
                 unsafe { self.device.create_image_view(&info) }
             })
             .collect();
 
         let swapchain_device = unsafe { self.device.make_swapchain_device() };
 
-        Ok(Swapchain {
+        let swapchain = Swapchain {
             _surface: Arc::clone(&self.surface),
             device: Arc::clone(&self.device),
             swapchain_device,
             swapchain_khr,
-            _images: images,
+            generation: SWAPCHAIN_GENERATION.fetch_add(1, Ordering::Relaxed),
+            images,
             views,
             format,
-            _present_mode: present_mode,
+            present_mode,
+            transform,
+            requested_present_mode: self.present_mode,
             extent,
+            full_screen_exclusive: self.full_screen_exclusive.map(|(mode, _)| mode),
             acquire_image_fence: Fence::new(Arc::clone(&self.device)).into(),
-        })
+        };
+        if let Some(metadata) = *self.hdr_metadata.lock().unwrap() {
+            swapchain.set_hdr_metadata(metadata)?;
+        }
+        Ok(swapchain)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn unsupported_surface() {
+        assert_eq!(choose_format(Vec::new(), &[]), None);
+        assert_eq!(
+            choose_present_mode(vec![PresentModeKHR::MAILBOX], PresentMode::Vsync),
+            None
+        );
+        assert_eq!(
+            choose_present_mode(vec![PresentModeKHR::FIFO], PresentMode::Mailbox),
+            Some(PresentModeKHR::FIFO)
+        );
+    }
+
+    #[test]
+    fn combined_status() {
+        let acquired = AcquireStatus::Suboptimal(0).get_present_status();
+        assert_eq!(
+            acquired.max(PresentStatus::Optimal),
+            PresentStatus::Suboptimal
+        );
+        assert_eq!(
+            acquired.max(PresentStatus::OutOfDate),
+            PresentStatus::OutOfDate
+        );
+    }
+
+    #[test]
+    fn acquire_timeout() {
+        assert_eq!(timeout_nanos(None), u64::MAX);
+        assert_eq!(timeout_nanos(Some(Duration::ZERO)), 0);
+        assert_eq!(timeout_nanos(Some(Duration::from_millis(16))), 16_000_000);
+        assert_eq!(timeout_nanos(Some(Duration::MAX)), u64::MAX);
+    }
+
+    #[test]
+    fn flipped_viewport() {
+        let (viewport, scissor) = make_viewport(
+            Extent2D {
+                width: 800,
+                height: 600,
+            },
+            true,
+        );
+        assert_eq!(viewport.y, 600.0f32);
+        assert_eq!(viewport.height, -600.0f32);
+        assert_eq!(scissor.extent.height, 600);
+    }
+
+    #[test]
+    fn adaptive_vsync() {
+        let modes = vec![PresentModeKHR::FIFO, PresentModeKHR::FIFO_RELAXED];
+        assert_eq!(
+            choose_present_mode(modes, PresentMode::AdaptiveVsync),
+            Some(PresentModeKHR::FIFO_RELAXED)
+        );
+    }
+
+    #[test]
+    fn adaptive_vsync_fallback() {
+        let modes = vec![PresentModeKHR::MAILBOX, PresentModeKHR::FIFO];
+        assert_eq!(
+            choose_present_mode(modes, PresentMode::AdaptiveVsync),
+            Some(PresentModeKHR::FIFO)
+        );
+    }
+
+    #[test]
+    fn preferred_surface_format() {
+        let hdr10 = SurfaceFormatKHR {
+            format: Format::A2B10G10R10_UNORM_PACK32,
+            color_space: ColorSpaceKHR::HDR10_ST2084_EXT,
+        };
+        let formats = vec![
+            SurfaceFormatKHR {
+                format: Format::B8G8R8A8_UNORM,
+                color_space: ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            DEFAULT_SURFACE_FORMAT,
+            hdr10,
+        ];
+        assert_eq!(choose_format(formats.clone(), &[hdr10]), Some(hdr10));
+        assert_eq!(
+            choose_format(formats[..2].to_vec(), &[hdr10]),
+            Some(DEFAULT_SURFACE_FORMAT)
+        );
+        assert_eq!(choose_format(vec![hdr10], &[]), None);
+        assert_eq!(choose_format(Vec::new(), &[hdr10]), None);
+    }
+
+    #[test]
+    fn color_space_mode() {
+        assert_eq!(
+            ColorSpaceMode::from_format(Format::B8G8R8A8_SRGB),
+            ColorSpaceMode::HardwareSrgb
+        );
+        assert_eq!(
+            ColorSpaceMode::from_format(Format::B8G8R8A8_UNORM),
+            ColorSpaceMode::ShaderEncoded
+        );
+    }
+
+    fn image_count_capabilities(
+        min_image_count: u32,
+        max_image_count: u32,
+    ) -> SurfaceCapabilitiesKHR {
+        SurfaceCapabilitiesKHR {
+            min_image_count,
+            max_image_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn unbounded_image_count() {
+        assert_eq!(choose_image_count(image_count_capabilities(2, 0)), 3);
+    }
+
+    #[test]
+    fn clamped_image_count() {
+        assert_eq!(choose_image_count(image_count_capabilities(2, 8)), 3);
+        assert_eq!(choose_image_count(image_count_capabilities(2, 2)), 2);
+        assert_eq!(
+            choose_image_count(image_count_capabilities(u32::MAX, 0)),
+            u32::MAX
+        );
+        assert_eq!(
+            choose_image_count(image_count_capabilities(u32::MAX, u32::MAX)),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn requested_transform() {
+        let capabilities = SurfaceCapabilitiesKHR {
+            current_transform: SurfaceTransformFlagsKHR::ROTATE_90,
+            supported_transforms: SurfaceTransformFlagsKHR::IDENTITY
+                | SurfaceTransformFlagsKHR::ROTATE_90,
+            ..Default::default()
+        };
+        assert_eq!(
+            choose_transform(capabilities, None),
+            SurfaceTransformFlagsKHR::ROTATE_90
+        );
+        assert_eq!(
+            choose_transform(capabilities, Some(SurfaceTransformFlagsKHR::IDENTITY)),
+            SurfaceTransformFlagsKHR::IDENTITY
+        );
+        assert_eq!(
+            choose_transform(capabilities, Some(SurfaceTransformFlagsKHR::ROTATE_180)),
+            SurfaceTransformFlagsKHR::ROTATE_90
+        );
+    }
+
+    fn free_extent_capabilities() -> SurfaceCapabilitiesKHR {
+        SurfaceCapabilitiesKHR {
+            current_extent: Extent2D {
+                width: u32::MAX,
+                height: u32::MAX,
+            },
+            min_image_extent: Extent2D {
+                width: 1,
+                height: 1,
+            },
+            max_image_extent: Extent2D {
+                width: 4096,
+                height: 4096,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn invalid_render_scale() {
+        assert!(check_render_scale(0.5).is_ok());
+        for scale in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(check_render_scale(scale).is_err());
+        }
+    }
+
+    #[test]
+    fn scaled_swap_extent() {
+        let extent = choose_swap_extent(
+            free_extent_capabilities(),
+            Extent2D {
+                width: 3840,
+                height: 2160,
+            },
+            2.0 / 3.0,
+        );
+        assert_eq!((extent.width, extent.height), (2560, 1440));
+    }
+
+    #[test]
+    fn clamped_swap_extent() {
+        let extent = choose_swap_extent(
+            free_extent_capabilities(),
+            Extent2D {
+                width: 3840,
+                height: 2160,
+            },
+            2.0,
+        );
+        assert_eq!((extent.width, extent.height), (4096, 4096));
     }
 }
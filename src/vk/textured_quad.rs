@@ -0,0 +1,258 @@
+use std::{error::Error, sync::Arc};
+
+use ash::vk;
+
+use super::{
+    buffer::Buffer,
+    command_buffer::{CommandBuffer, CommandRecordingError},
+    command_pool::CommandPool,
+    device::Device,
+    image::Image,
+    memory::Allocator,
+    pipeline::{GraphicsPipeline, GraphicsPipelineBuilder, vertex_layout::VertexLayoutBuilder},
+    sampled_pass::{SampledPass, builtin_stage},
+    sampler::SamplerBuilder,
+    shader::ShaderStage,
+    swapchain::Swapchain,
+};
+
+///
+/// SPIR-V of the vertex shader passing the vertex attributes through:
+///
+/// ```glsl
+/// #version 450
+/// layout(location = 0) in vec2 position;
+/// layout(location = 1) in vec2 uv;
+/// layout(location = 0) out vec2 out_uv;
+/// void main() {
+///     out_uv = uv;
+///     gl_Position = vec4(position, 0.0, 1.0);
+/// }
+/// ```
+///
+const TEXTURED_QUAD_VERT: [u32; 127] = [
+    0x07230203, 0x00010000, 0x00000000, 0x00000016, 0x00000000, 0x00020011, 0x00000001, 0x0003000e,
+    0x00000000, 0x00000001, 0x0009000f, 0x00000000, 0x00000001, 0x6e69616d, 0x00000000, 0x00000002,
+    0x00000003, 0x00000004, 0x00000005, 0x00040047, 0x00000002, 0x0000001e, 0x00000000, 0x00040047,
+    0x00000003, 0x0000001e, 0x00000001, 0x00040047, 0x00000004, 0x0000001e, 0x00000000, 0x00040047,
+    0x00000005, 0x0000000b, 0x00000000, 0x00020013, 0x00000006, 0x00030021, 0x00000007, 0x00000006,
+    0x00030016, 0x00000008, 0x00000020, 0x00040017, 0x00000009, 0x00000008, 0x00000002, 0x00040017,
+    0x0000000a, 0x00000008, 0x00000004, 0x00040020, 0x0000000b, 0x00000001, 0x00000009, 0x00040020,
+    0x0000000c, 0x00000003, 0x00000009, 0x00040020, 0x0000000d, 0x00000003, 0x0000000a, 0x0004003b,
+    0x0000000b, 0x00000002, 0x00000001, 0x0004003b, 0x0000000b, 0x00000003, 0x00000001, 0x0004003b,
+    0x0000000c, 0x00000004, 0x00000003, 0x0004003b, 0x0000000d, 0x00000005, 0x00000003, 0x0004002b,
+    0x00000008, 0x0000000e, 0x00000000, 0x0004002b, 0x00000008, 0x0000000f, 0x3f800000, 0x00050036,
+    0x00000006, 0x00000001, 0x00000000, 0x00000007, 0x000200f8, 0x00000010, 0x0004003d, 0x00000009,
+    0x00000011, 0x00000003, 0x0003003e, 0x00000004, 0x00000011, 0x0004003d, 0x00000009, 0x00000012,
+    0x00000002, 0x00050051, 0x00000008, 0x00000013, 0x00000012, 0x00000000, 0x00050051, 0x00000008,
+    0x00000014, 0x00000012, 0x00000001, 0x00070050, 0x0000000a, 0x00000015, 0x00000013, 0x00000014,
+    0x0000000e, 0x0000000f, 0x0003003e, 0x00000005, 0x00000015, 0x000100fd, 0x00010038,
+];
+
+///
+/// SPIR-V of the fragment shader sampling the texture:
+///
+/// ```glsl
+/// #version 450
+/// layout(location = 0) in vec2 uv;
+/// layout(location = 0) out vec4 color;
+/// layout(set = 0, binding = 0) uniform sampler2D tex;
+/// void main() {
+///     color = texture(tex, uv);
+/// }
+/// ```
+///
+const TEXTURED_QUAD_FRAG: [u32; 113] = [
+    0x07230203, 0x00010000, 0x00000000, 0x00000013, 0x00000000, 0x00020011, 0x00000001, 0x0003000e,
+    0x00000000, 0x00000001, 0x0007000f, 0x00000004, 0x00000001, 0x6e69616d, 0x00000000, 0x00000002,
+    0x00000003, 0x00030010, 0x00000001, 0x00000007, 0x00040047, 0x00000002, 0x0000001e, 0x00000000,
+    0x00040047, 0x00000003, 0x0000001e, 0x00000000, 0x00040047, 0x00000004, 0x00000022, 0x00000000,
+    0x00040047, 0x00000004, 0x00000021, 0x00000000, 0x00020013, 0x00000005, 0x00030021, 0x00000006,
+    0x00000005, 0x00030016, 0x00000007, 0x00000020, 0x00040017, 0x00000008, 0x00000007, 0x00000002,
+    0x00040017, 0x00000009, 0x00000007, 0x00000004, 0x00090019, 0x0000000a, 0x00000007, 0x00000001,
+    0x00000000, 0x00000000, 0x00000000, 0x00000001, 0x00000000, 0x0003001b, 0x0000000b, 0x0000000a,
+    0x00040020, 0x0000000c, 0x00000001, 0x00000008, 0x00040020, 0x0000000d, 0x00000003, 0x00000009,
+    0x00040020, 0x0000000e, 0x00000000, 0x0000000b, 0x0004003b, 0x0000000c, 0x00000002, 0x00000001,
+    0x0004003b, 0x0000000d, 0x00000003, 0x00000003, 0x0004003b, 0x0000000e, 0x00000004, 0x00000000,
+    0x00050036, 0x00000005, 0x00000001, 0x00000000, 0x00000006, 0x000200f8, 0x0000000f, 0x0004003d,
+    0x0000000b, 0x00000010, 0x00000004, 0x0004003d, 0x00000008, 0x00000011, 0x00000002, 0x00050057,
+    0x00000009, 0x00000012, 0x00000010, 0x00000011, 0x0003003e, 0x00000003, 0x00000012, 0x000100fd,
+    0x00010038,
+];
+
+///
+/// Position in normalized device coordinates and texture coordinate of a quad corner
+///
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct QuadVertex {
+    pub position: [f32; 2],
+    pub uv: [f32; 2],
+}
+
+///
+/// Two triangles covering the viewport, with the texture upright
+///
+pub const QUAD_VERTICES: [QuadVertex; 6] = [
+    QuadVertex {
+        position: [-1.0, -1.0],
+        uv: [0.0, 0.0],
+    },
+    QuadVertex {
+        position: [1.0, -1.0],
+        uv: [1.0, 0.0],
+    },
+    QuadVertex {
+        position: [1.0, 1.0],
+        uv: [1.0, 1.0],
+    },
+    QuadVertex {
+        position: [-1.0, -1.0],
+        uv: [0.0, 0.0],
+    },
+    QuadVertex {
+        position: [1.0, 1.0],
+        uv: [1.0, 1.0],
+    },
+    QuadVertex {
+        position: [-1.0, 1.0],
+        uv: [0.0, 1.0],
+    },
+];
+
+fn vertex_bytes(vertices: &[QuadVertex]) -> Vec<u8> {
+    vertices
+        .iter()
+        .flat_map(|vertex| vertex.position.into_iter().chain(vertex.uv))
+        .flat_map(f32::to_ne_bytes)
+        .collect()
+}
+
+///
+/// Draws a texture on a quad covering the swapchain images, the most basic way
+/// to get something textured on screen. The vertices are QUAD_VERTICES in a
+/// host-visible vertex buffer, the texture is sampled with a linear sampler
+///
+pub struct TexturedQuadPipeline {
+    pass: SampledPass,
+}
+
+impl TexturedQuadPipeline {
+    ///
+    /// texture has to be in SHADER_READ_ONLY_OPTIMAL layout whenever the command buffers execute
+    ///
+    pub fn new(
+        device: Arc<Device>,
+        swapchain: Arc<Swapchain>,
+        command_pool: Arc<CommandPool>,
+        texture: &Image,
+    ) -> Result<Self, Box<dyn Error>> {
+        let vertex = builtin_stage(&device, &TEXTURED_QUAD_VERT, ShaderStage::Vertex)?;
+        let fragment = builtin_stage(&device, &TEXTURED_QUAD_FRAG, ShaderStage::Fragment)?;
+
+        let vertices = vertex_bytes(&QUAD_VERTICES);
+        let vertex_buffer = Buffer::new(
+            Arc::clone(&device),
+            &Allocator::new(Arc::clone(&device)),
+            vertices.len() as u64,
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+        vertex_buffer.map()?.copy_from_slice(&vertices);
+
+        let vertex_layout = VertexLayoutBuilder::new(0, size_of::<QuadVertex>() as u32)
+            .attribute(0, vk::Format::R32G32_SFLOAT, 0)
+            .attribute(1, vk::Format::R32G32_SFLOAT, size_of::<[f32; 2]>() as u32)
+            .build()?;
+
+        let sampler = Arc::new(SamplerBuilder::new(Arc::clone(&device)).build());
+        let builder = GraphicsPipelineBuilder::new(Arc::clone(&device), swapchain, command_pool)
+            .add_stage(String::from("textured_quad_vertex"), vertex)
+            .add_stage(String::from("textured_quad_fragment"), fragment)
+            .vertex_layout(vertex_layout);
+        Ok(Self {
+            pass: SampledPass::new(
+                device,
+                builder,
+                texture,
+                sampler,
+                Some(vertex_buffer),
+                QUAD_VERTICES.len() as u32,
+            )?,
+        })
+    }
+
+    ///
+    /// Records begin pass -> bind -> draw(6) -> end for every swapchain image,
+    /// refreshing the pipeline's command buffers replays the same recording.
+    /// None of the command buffers may be pending execution
+    ///
+    pub fn record_command_buffers(&mut self) -> Result<(), CommandRecordingError> {
+        self.pass.record_command_buffers()
+    }
+
+    pub fn get_pipeline(&self) -> &GraphicsPipeline {
+        self.pass.get_pipeline()
+    }
+
+    pub fn get_command_buffer(&self, index: u32) -> Arc<CommandBuffer> {
+        self.pass.get_command_buffer(index)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interleaved_vertices() {
+        let bytes = vertex_bytes(&QUAD_VERTICES[1..2]);
+        assert_eq!(bytes.len(), size_of::<QuadVertex>());
+        let floats: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|chunk| f32::from_ne_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(floats, [1.0, -1.0, 1.0, 0.0]);
+    }
+
+    #[cfg(feature = "reflect")]
+    #[test]
+    fn reflected_sampler() {
+        use crate::vk::shader::reflect::{ReflectedBinding, reflect};
+
+        assert!(
+            reflect(&TEXTURED_QUAD_VERT)
+                .unwrap()
+                .get_bindings()
+                .is_empty()
+        );
+        assert_eq!(
+            reflect(&TEXTURED_QUAD_FRAG).unwrap().get_bindings(),
+            [ReflectedBinding {
+                set: 0,
+                binding: 0,
+                descriptor_type: vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+                count: 1,
+            }]
+        );
+    }
+
+    #[cfg(feature = "reflect")]
+    #[test]
+    fn reflected_entry_points() {
+        use crate::vk::shader::reflect::{EntryPoint, reflect};
+
+        for (code, stage) in [
+            (TEXTURED_QUAD_VERT.as_slice(), ShaderStage::Vertex),
+            (TEXTURED_QUAD_FRAG.as_slice(), ShaderStage::Fragment),
+        ] {
+            assert_eq!(
+                reflect(code).unwrap().get_entry_points(),
+                [EntryPoint {
+                    name: String::from("main"),
+                    stage: Some(stage),
+                }]
+            );
+        }
+    }
+}
@@ -49,6 +49,12 @@ impl ValidationLayerManager {
             })
             .collect())
     }
+    pub fn available_names(&self) -> Vec<String> {
+        self.available
+            .iter()
+            .map(|l| l.name.to_string_lossy().into_owned())
+            .collect()
+    }
     pub fn check_layers(
         &self,
         layers: &[String],
@@ -80,6 +86,22 @@ impl ValidationLayerManager {
         Ok(())
     }
 
+    ///
+    /// Enables the available layers, logs a warning for each missing one
+    ///
+    pub fn add_available_layers(&mut self, layers: &[String]) {
+        for l in layers.iter() {
+            if let Err(error) = self.check_layers(std::slice::from_ref(l)) {
+                log::warn!("{}, continuing without it", error);
+            }
+        }
+        for a_vl in self.available.iter_mut() {
+            if layers.contains(&a_vl.name.to_str().unwrap().to_owned()) {
+                a_vl.enabled = true;
+            }
+        }
+    }
+
     pub fn make_load_layer_list(&self) -> Vec<*const c_char> {
         self.available
             .iter()
@@ -101,6 +123,9 @@ impl ValidationLayerManager {
     pub fn enumerate(_: &Entry) -> VkResult<Vec<ValidationLayer>> {
         Ok(Vec::new())
     }
+    pub fn available_names(&self) -> Vec<String> {
+        Vec::new()
+    }
     pub fn check_layers(
         &self,
         _: &[String],
@@ -113,6 +138,7 @@ impl ValidationLayerManager {
     ) -> Result<(), ValidationLayerUnavailableError> {
         Ok(())
     }
+    pub fn add_available_layers(&mut self, _: &[String]) {}
 
     pub fn make_load_layer_list(&mut self) -> Vec<*const c_char> {
         vec![]
@@ -1,13 +1,32 @@
 use crate::vk::instance::Instance;
-use std::sync::Arc;
+use std::{cell::Cell, error::Error, sync::Arc};
 
 use ash::vk::SurfaceKHR;
-use sdl3::{self, Sdl, VideoSubsystem, video::Window};
+use sdl3::{
+    self, Sdl, VideoSubsystem,
+    event::{Event, WindowEvent},
+    video::Window,
+};
+
+///
+/// Returned by the frame closure of WindowManager::run
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlFlow {
+    /// Call the frame closure again as soon as pending events are handled
+    Poll,
+    /// Sleep until the next event or a request_redraw call
+    Wait,
+    Exit,
+}
 
 pub struct WindowManager {
-    _sdl_context: Sdl,
-    _video_subsystem: VideoSubsystem,
+    sdl_context: Sdl,
+    video_subsystem: VideoSubsystem,
     window: Window,
+    resized: Option<(u32, u32)>,
+    window_events: Vec<(u32, WindowEvent)>,
+    redraw_requested: Cell<bool>,
 }
 
 impl WindowManager {
@@ -17,14 +36,18 @@ impl WindowManager {
         let window = video_subsystem
             .window("Test window", 800, 600)
             .position_centered()
+            .resizable()
             .vulkan()
             .build()
             .unwrap();
 
         Self {
-            _sdl_context: sdl_context,
-            _video_subsystem: video_subsystem,
+            sdl_context,
+            video_subsystem,
             window,
+            resized: None,
+            window_events: Vec::new(),
+            redraw_requested: Cell::new(false),
         }
     }
 
@@ -32,7 +55,130 @@ impl WindowManager {
         instance.create_surface(&self.window)
     }
 
+    ///
+    /// Additional window, its events don't affect the main window and are reported
+    /// by get_window_resize and is_close_requested.
+    /// Render to it with Vulkan::create_surface_for and Vulkan::create_swapchain_for
+    ///
+    pub fn create_window(
+        &self,
+        title: &str,
+        width: u32,
+        height: u32,
+    ) -> Result<Window, Box<dyn Error>> {
+        let window = self
+            .video_subsystem
+            .window(title, width, height)
+            .position_centered()
+            .resizable()
+            .vulkan()
+            .build()?;
+        Ok(window)
+    }
+
     pub fn get_vk_extensions(&self) -> Result<Vec<String>, sdl3::Error> {
         self.window.vulkan_instance_extensions()
     }
+
+    pub fn get_size_in_pixels(&self) -> (u32, u32) {
+        self.window.size_in_pixels()
+    }
+
+    ///
+    /// New size in pixels if the window was resized since the previous frame,
+    /// the swapchain has to be recreated then
+    ///
+    pub fn get_resize(&self) -> Option<(u32, u32)> {
+        self.resized
+    }
+
+    ///
+    /// Same as get_resize for a window created with create_window
+    ///
+    pub fn get_window_resize(&self, window: &Window) -> Option<(u32, u32)> {
+        self.window_events
+            .iter()
+            .rev()
+            .find_map(|(id, event)| match event {
+                WindowEvent::PixelSizeChanged(width, height) if *id == window.id() => {
+                    Some((*width as u32, *height as u32))
+                }
+                _ => None,
+            })
+    }
+
+    ///
+    /// Whether closing a window created with create_window was requested since
+    /// the previous frame, it's up to the caller to drop it
+    ///
+    pub fn is_close_requested(&self, window: &Window) -> bool {
+        self.window_events
+            .iter()
+            .any(|(id, event)| *id == window.id() && matches!(event, WindowEvent::CloseRequested))
+    }
+
+    ///
+    /// Makes run call the frame closure again even if it returned ControlFlow::Wait
+    ///
+    pub fn request_redraw(&self) {
+        self.redraw_requested.set(true);
+    }
+
+    ///
+    /// Pumps SDL events and calls frame after each batch of them,
+    /// until the window is closed or frame returns ControlFlow::Exit
+    ///
+    pub fn run(mut self, mut frame: impl FnMut(&WindowManager) -> ControlFlow) {
+        let mut event_pump = self.sdl_context.event_pump().unwrap();
+        let mut control_flow = ControlFlow::Poll;
+        loop {
+            self.resized = None;
+            self.window_events.clear();
+            let mut close = false;
+            if control_flow == ControlFlow::Wait && !self.redraw_requested.get() {
+                close |= self.handle_event(event_pump.wait_event());
+            }
+            for event in event_pump.poll_iter() {
+                close |= self.handle_event(event);
+            }
+            if close {
+                return;
+            }
+
+            self.redraw_requested.set(false);
+            control_flow = frame(&self);
+            if control_flow == ControlFlow::Exit {
+                return;
+            }
+        }
+    }
+
+    ///
+    /// Returns true if the window should be closed
+    ///
+    fn handle_event(&mut self, event: Event) -> bool {
+        match event {
+            Event::Quit { .. } => true,
+            Event::Window {
+                window_id,
+                win_event,
+                ..
+            } if window_id != self.window.id() => {
+                self.window_events.push((window_id, win_event));
+                false
+            }
+            Event::Window {
+                win_event: WindowEvent::CloseRequested,
+                ..
+            } => true,
+            Event::Window {
+                win_event: WindowEvent::PixelSizeChanged(width, height),
+                ..
+            } => {
+                self.resized = Some((width as u32, height as u32));
+                false
+            }
+            _ => false,
+        }
+    }
 }